@@ -0,0 +1,61 @@
+//!
+//! Benchmark of argument expansion: a builtin is given hundreds of
+//! arguments, plain, with variables and with globs over a directory of
+//! files, and the time each line takes is printed
+//!
+//! Run with `cargo bench --bench expand_args`
+//!
+
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use shell::Shell;
+
+/// Arguments given to each command
+const ARGS: usize = 500;
+
+/// Files in the directory the globs match against
+const FILES: usize = 500;
+
+/// Times each line is run for its average
+const ROUNDS: u32 = 200;
+
+///
+/// Average time to run a line, after running it once to warm up
+///
+fn time(shell: &mut Shell, line: &str) -> Duration {
+    let _ = shell.run(line);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let _ = black_box(shell.run(black_box(line)));
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let dir = env::temp_dir().join(format!("rust-shell-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for i in 0..FILES {
+        fs::write(dir.join(format!("file{i}.txt")), "").unwrap();
+    }
+
+    let mut shell = Shell::new();
+    shell.state_mut().set_var("word", "value").unwrap();
+    let _ = shell.run(&format!("cd '{}'", dir.display()));
+
+    let plain = (0..ARGS).map(|i| format!("arg{i}")).collect::<Vec<_>>().join(" ");
+    let vars = (0..ARGS).map(|i| format!("${{word}}{i}")).collect::<Vec<_>>().join(" ");
+    let globs = ["file1*.txt"; ARGS / 100].join(" ");
+    let cases = [
+        ("plain words", format!("true {plain}")),
+        ("variables", format!("true {vars}")),
+        ("globs", format!("true {globs} *")),
+    ];
+    for (name, line) in &cases {
+        println!("{name:12} {:>10.1?}", time(&mut shell, line));
+    }
+
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,412 @@
+use std::fmt;
+
+use crate::error;
+use crate::{assign, lookup, readonly, State};
+
+///
+/// Reasons an arithmetic expression could not be evaluated
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArithError {
+    /// Malformed expression, with the text from where it went wrong
+    Syntax(String),
+    DivisionByZero,
+    /// `**` with a negative exponent, which has no integer result
+    NegativeExponent,
+    /// Assignment to something other than a variable, or `++`/`--` of one
+    NotAssignable(String),
+    /// A variable whose value refers back to itself
+    TooDeep(String),
+    /// Assignment to a variable `declare -r` made readonly
+    Readonly(String),
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithError::Syntax(rest) if rest.is_empty() => write!(f, "syntax error: operand expected"),
+            ArithError::Syntax(rest) => write!(f, "syntax error in expression (error token is \"{rest}\")"),
+            ArithError::DivisionByZero => write!(f, "division by 0"),
+            ArithError::NegativeExponent => write!(f, "exponent less than 0"),
+            ArithError::NotAssignable(what) => write!(f, "attempted assignment to non-variable (`{what}')"),
+            ArithError::TooDeep(name) => write!(f, "{name}: expression recursion level exceeded"),
+            ArithError::Readonly(name) => write!(f, "{name}: readonly variable"),
+        }
+    }
+}
+
+type ArithResult<T> = Result<T, ArithError>;
+
+/// How many variables deep a value may refer to other variables
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    /// Operator or parenthesis
+    Op(&'static str),
+}
+
+/// Operators, longest first so `<<=` is not read as `<<` then `=`
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=",
+    "/=", "%=", "&=", "|=", "^=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^", "?",
+    ":", "(", ")", ",",
+];
+
+///
+/// Splits an expression into numbers, variable names and operators
+///
+/// Output:
+///   Tokens, each with the text it started at for error messages
+///
+fn tokenize(expr: &str) -> ArithResult<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < expr.len() {
+        let rest = &expr[i..];
+        let c = rest.chars().next().unwrap_or_default();
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+
+        let len = if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            tokens.push((Token::Number(number(&rest[..len])?), i));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push((Token::Name(rest[..len].to_string()), i));
+            len
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push((Token::Op(op), i));
+            op.len()
+        } else {
+            return Err(ArithError::Syntax(rest.to_string()));
+        };
+        i += len;
+    }
+    Ok(tokens)
+}
+
+///
+/// Reads an integer literal: decimal, `0x` hex, or octal with a leading `0`
+///
+fn number(text: &str) -> ArithResult<i64> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if text.len() > 1 && text.starts_with('0') {
+        i64::from_str_radix(&text[1..], 8)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| ArithError::Syntax(text.to_string()))
+}
+
+///
+/// Evaluates while parsing, one precedence level per method
+///
+struct Evaluator<'a> {
+    state: &'a mut State,
+    expr: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Variables currently being evaluated for their values
+    depth: usize,
+    /// Set inside the side of `&&`, `||` or `?:` not taken, where nothing is
+    /// assigned and division by zero is not an error
+    skip: usize,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Error for the token at the current position
+    ///
+    fn unexpected(&self) -> ArithError {
+        let rest = self.tokens.get(self.pos).map_or("", |&(_, at)| &self.expr[at..]);
+        ArithError::Syntax(rest.trim().to_string())
+    }
+
+    fn expect(&mut self, op: &str) -> ArithResult<()> {
+        if self.peek_op() != Some(op) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    ///
+    /// Value of a variable, itself evaluated as an expression; unset or
+    /// empty is 0
+    ///
+    fn variable(&mut self, name: &str) -> ArithResult<i64> {
+        let value = lookup(self.state, name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse() {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(ArithError::TooDeep(name.to_string()));
+        }
+        evaluate(self.state, value, self.depth + 1)
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> ArithResult<()> {
+        if self.skip > 0 {
+            return Ok(());
+        }
+        if readonly(self.state, name) {
+            return Err(ArithError::Readonly(name.to_string()));
+        }
+        // A number is a valid value even for an integer variable
+        let _ = assign(self.state, name, value.to_string());
+        Ok(())
+    }
+
+    ///
+    /// A whole expression: assignments separated by commas, the value
+    /// being that of the last
+    ///
+    fn comma(&mut self) -> ArithResult<i64> {
+        let mut value = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> ArithResult<i64> {
+        let (Some(Token::Name(name)), Some((Token::Op(op), _))) = (self.peek().cloned(), self.tokens.get(self.pos + 1)) else {
+            return self.conditional();
+        };
+        let Some(binary) = op.strip_suffix('=').filter(|_| !matches!(*op, "==" | "!=" | "<=" | ">=")) else {
+            return self.conditional();
+        };
+
+        self.pos += 2;
+        let right = self.assignment()?;
+        let value = if binary.is_empty() {
+            right
+        } else {
+            let left = self.variable(&name)?;
+            self.apply(binary, left, right)?
+        };
+        self.set(&name, value)?;
+        Ok(value)
+    }
+
+    fn conditional(&mut self) -> ArithResult<i64> {
+        let condition = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+
+        let taken = condition != 0;
+        let yes = self.skipping(!taken, Self::assignment)?;
+        self.expect(":")?;
+        let no = self.skipping(taken, Self::conditional)?;
+        Ok(if taken { yes } else { no })
+    }
+
+    fn skipping(&mut self, skip: bool, parse: impl FnOnce(&mut Self) -> ArithResult<i64>) -> ArithResult<i64> {
+        self.skip += usize::from(skip);
+        let value = parse(self);
+        self.skip -= usize::from(skip);
+        value
+    }
+
+    ///
+    /// Binary operators from loosest binding (level 0, `||`) to tightest
+    ///
+    fn binary(&mut self, level: usize) -> ArithResult<i64> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.power();
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| ops.contains(op)) {
+            self.pos += 1;
+            // The right of `&&` and `||` is only evaluated if it matters
+            let skip = (op == "&&" && left == 0) || (op == "||" && left != 0);
+            let right = self.skipping(skip, |this| this.binary(level + 1))?;
+            left = self.apply(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn power(&mut self) -> ArithResult<i64> {
+        let base = self.unary()?;
+        if self.peek_op() != Some("**") {
+            return Ok(base);
+        }
+        self.pos += 1;
+        // Right associative, so `2**3**2` is `2**9`
+        let exponent = self.power()?;
+        self.apply("**", base, exponent)
+    }
+
+    fn unary(&mut self) -> ArithResult<i64> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let Some(Token::Name(name)) = self.peek().cloned() else {
+                    return Err(ArithError::NotAssignable(op.to_string()));
+                };
+                self.pos += 1;
+                let value = self.variable(&name)? + if op == "++" { 1 } else { -1 };
+                self.set(&name, value)?;
+                Ok(value)
+            }
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                let value = self.unary()?;
+                Ok(match op {
+                    "-" => value.wrapping_neg(),
+                    "+" => value,
+                    "!" => i64::from(value == 0),
+                    _ => !value,
+                })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> ArithResult<i64> {
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                let value = self.variable(&name)?;
+                // Postfix `x++` gives the value from before the change
+                if let Some(op @ ("++" | "--")) = self.peek_op() {
+                    self.pos += 1;
+                    self.set(&name, value + if op == "++" { 1 } else { -1 })?;
+                }
+                Ok(value)
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn apply(&self, op: &str, left: i64, right: i64) -> ArithResult<i64> {
+        if matches!(op, "/" | "%") && right == 0 {
+            return if self.skip > 0 { Ok(0) } else { Err(ArithError::DivisionByZero) };
+        }
+        if op == "**" && right < 0 {
+            return if self.skip > 0 { Ok(0) } else { Err(ArithError::NegativeExponent) };
+        }
+        Ok(match op {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<" => i64::from(left < right),
+            "<=" => i64::from(left <= right),
+            ">" => i64::from(left > right),
+            ">=" => i64::from(left >= right),
+            "==" => i64::from(left == right),
+            "!=" => i64::from(left != right),
+            "&&" => i64::from(left != 0 && right != 0),
+            _ => i64::from(left != 0 || right != 0),
+        })
+    }
+}
+
+fn evaluate(state: &mut State, expr: &str, depth: usize) -> ArithResult<i64> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut evaluator = Evaluator { state, expr, tokens, pos: 0, depth, skip: 0 };
+    let value = evaluator.comma()?;
+    if evaluator.pos < evaluator.tokens.len() {
+        return Err(evaluator.unexpected());
+    }
+    Ok(value)
+}
+
+///
+/// Evaluates an arithmetic expression, as in `$((...))` and `let`
+///
+/// Inputs:
+///   Shell state, whose variables the expression reads and assigns
+///   Expression of integers, variable names, parentheses and the C
+///   operators: `+ - * / % **`, comparisons, `&& || !`, bitwise
+///   operators, `?:`, `=`/`+=` and the like, and `++`/`--`
+///
+/// Output:
+///   64-bit value of the expression, wrapping on overflow, 0 if empty
+///
+pub fn eval(state: &mut State, expr: &str) -> ArithResult<i64> {
+    evaluate(state, expr, 0)
+}
+
+///
+/// Builtin let: evaluates each argument as an arithmetic expression
+///
+/// Output:
+///   Exit status, 0 if the last value was non-zero, 1 if it was zero or
+///   an expression failed
+///
+pub fn builtin_let(state: &mut State, args: &[String]) -> i32 {
+    if args.is_empty() {
+        return error::builtin("let", "expression expected");
+    }
+
+    let mut last = 0;
+    for arg in args {
+        match eval(state, arg) {
+            Ok(value) => last = value,
+            Err(e) => return error::builtin("let", format!("{arg}: {e}")),
+        }
+    }
+    i32::from(last == 0)
+}
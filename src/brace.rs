@@ -0,0 +1,202 @@
+use crate::lexer::{self, Quoting, Segment, Word};
+
+///
+/// One character of a word being brace expanded
+///
+#[derive(Clone, Copy)]
+struct Unit {
+    /// None stands for an empty quoted segment such as `""`, kept so the
+    /// word still counts as an argument
+    c: Option<char>,
+    quoting: Quoting,
+    /// Whether the character can take part in a brace expression: unquoted
+    /// and outside `${...}` and `$(...)`
+    active: bool,
+}
+
+///
+/// Splits a word into units, marking which characters braces may act on
+///
+fn units(word: &Word) -> Vec<Unit> {
+    let mut out = Vec::new();
+    for seg in &word.segments {
+        if seg.text.is_empty() {
+            out.push(Unit { c: None, quoting: seg.quoting, active: false });
+            continue;
+        }
+        if seg.quoting != Quoting::Bare {
+            out.extend(seg.text.chars().map(|c| Unit { c: Some(c), quoting: seg.quoting, active: false }));
+            continue;
+        }
+
+        // Characters still to skip as part of a parameter or substitution
+        let mut inside = 0;
+        for (i, c) in seg.text.char_indices() {
+            if inside == 0 && c == '$' {
+                let rest = &seg.text[i + 1..];
+                let end = match rest.chars().next() {
+                    Some('(') => lexer::substitution_end(&rest[1..]).map(|end| end + 2),
+                    Some('{') => lexer::parameter_end(&rest[1..]).map(|end| end + 2),
+                    _ => None,
+                };
+                inside = end.map_or(0, |end| rest[..end].chars().count());
+                out.push(Unit { c: Some(c), quoting: Quoting::Bare, active: false });
+                continue;
+            }
+            out.push(Unit { c: Some(c), quoting: Quoting::Bare, active: inside == 0 });
+            inside = inside.saturating_sub(1);
+        }
+    }
+    out
+}
+
+///
+/// Puts units back together into a word, one segment per run of quoting
+///
+fn word(units: &[Unit]) -> Word {
+    let mut word = Word::default();
+    for unit in units {
+        match word.segments.last_mut() {
+            Some(seg) if seg.quoting == unit.quoting => seg.text.extend(unit.c),
+            _ => word.segments.push(Segment { text: unit.c.map(String::from).unwrap_or_default(), quoting: unit.quoting }),
+        }
+    }
+    word
+}
+
+fn is(unit: &Unit, c: char) -> bool {
+    unit.active && unit.c == Some(c)
+}
+
+///
+/// Finds the `}` closing the brace at open, and the top level commas
+/// between them
+///
+fn closing(units: &[Unit], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, unit) in units.iter().enumerate().skip(open + 1) {
+        if is(unit, '{') {
+            depth += 1;
+        } else if is(unit, '}') {
+            if depth == 0 {
+                return Some((i, commas));
+            }
+            depth -= 1;
+        } else if depth == 0 && is(unit, ',') {
+            commas.push(i);
+        }
+    }
+    None
+}
+
+///
+/// Expands a sequence such as `1..10`, `a..e` or `0..20..5`
+///
+/// Output:
+///   Each item of the sequence, or None if text is not one. Numbers are
+///   padded with zeros if either end was written with a leading zero
+///
+fn sequence(text: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = text.split("..").collect();
+    let (start, end, step) = match parts.as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (*start, *end, step.parse::<i64>().ok()?.unsigned_abs().max(1)),
+        _ => return None,
+    };
+
+    if let (Ok(a), Ok(b)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+        let width = if padded(start) || padded(end) { start.len().max(end.len()) } else { 0 };
+        let items = stepped(a, b, step).map(|n| format!("{n:0width$}")).collect();
+        return Some(items);
+    }
+
+    let letter = |s: &str| match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(*c),
+        _ => None,
+    };
+    if let (Some(a), Some(b)) = (letter(start), letter(end)) {
+        // Counting between cases passes through the punctuation in between
+        let items = stepped(a as i64, b as i64, step)
+            .map(|n| (n as u8 as char).to_string())
+            .collect();
+        return Some(items);
+    }
+    None
+}
+
+///
+/// Counts from a to b inclusive, downwards if b is smaller
+///
+fn stepped(a: i64, b: i64, step: u64) -> impl Iterator<Item = i64> {
+    let count = a.abs_diff(b) / step + 1;
+    let step = if b < a { -(step as i64) } else { step as i64 };
+    (0..count as i64).map(move |i| a + i * step)
+}
+
+///
+/// Expands the first brace expression in units, then whatever braces are
+/// left in each resulting word
+///
+fn expand_units(units: &[Unit], out: &mut Vec<Word>) {
+    for open in (0..units.len()).filter(|&i| is(&units[i], '{')) {
+        let Some((close, commas)) = closing(units, open) else {
+            continue;
+        };
+
+        let (prefix, suffix) = (&units[..open], &units[close + 1..]);
+        let alternatives: Vec<Vec<Unit>> = if commas.is_empty() {
+            // Only a sequence when every character of it is unquoted
+            let inner = &units[open + 1..close];
+            if !inner.iter().all(|unit| unit.active) {
+                continue;
+            }
+            let text: String = inner.iter().filter_map(|unit| unit.c).collect();
+            let Some(items) = sequence(&text) else {
+                continue;
+            };
+            items.iter()
+                .map(|item| item.chars().map(|c| Unit { c: Some(c), quoting: Quoting::Bare, active: false }).collect())
+                .collect()
+        } else {
+            std::iter::once(open).chain(commas.iter().copied())
+                .zip(commas.iter().copied().chain(std::iter::once(close)))
+                .map(|(from, to)| units[from + 1..to].to_vec())
+                .collect()
+        };
+
+        for alternative in alternatives {
+            let mut joined = prefix.to_vec();
+            joined.extend(alternative);
+            joined.extend_from_slice(suffix);
+            expand_units(&joined, out);
+        }
+        return;
+    }
+    out.push(word(units));
+}
+
+///
+/// Brace expansion, the first expansion done to a word
+///
+/// Input:
+///   Word from the lexer
+///
+/// Output:
+///   The words it stands for: `a{b,c}d` is `abd` and `acd`, `{1..3}` is
+///   `1`, `2` and `3`, and braces nest. Quoted braces, braces inside `${}`
+///   or `$()`, and braces with neither a comma nor a sequence inside (like
+///   the `{}` of `find -exec`) are left as they are
+///
+pub fn expand(word: &Word) -> Vec<Word> {
+    let has_brace = word.segments.iter()
+        .any(|seg| seg.quoting == Quoting::Bare && seg.text.contains('{'));
+    if !has_brace {
+        return vec![word.clone()];
+    }
+
+    let mut out = Vec::new();
+    expand_units(&units(word), &mut out);
+    out
+}
@@ -0,0 +1,2173 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::ops::ControlFlow;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::*;
+use std::process;
+use std::str::Chars;
+use std::time::{Duration, Instant};
+
+use crate::editor::{self, RawMode};
+use crate::error::{self, ShellError};
+use crate::jobs::JobState;
+use crate::lexer::{self, is_name};
+use crate::parser::RESERVED;
+use crate::{arith, condition, history, hooks, jobs, jump, options, pattern, platform, prompt, signals, theme, trap};
+use crate::{assign, command_words, lookup, readonly, restricted, run_lines, Attributes, State, RESTRICTED_VARS};
+
+/// Names handled inside the shell rather than spawned
+pub const BUILTINS: &[&str] = &[
+    ".", "[", "alias", "bg", "bookmark", "break", "cd", "continue", "declare", "dirs", "echo", "enable", "exec",
+    "exit", "export", "false", "fg", "hash", "history", "hook", "j", "jobs", "kill", "let", "logout", "popd", "pushd",
+    "pwd", "read", "rehash", "return", "set", "shift", "shopt", "source", "test", "theme", "trap", "true", "type", "typeset",
+    "ulimit", "umask", "unalias", "unset", "wait", "which",
+];
+
+///
+/// One line on what a builtin does, for the completion menu
+///
+pub fn summary(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "." | "source" => "run a file in the current shell",
+        "[" | "test" => "evaluate a conditional expression",
+        "alias" => "define or list aliases",
+        "bg" => "resume a job in the background",
+        "bookmark" => "name the current directory",
+        "break" => "leave the enclosing loop",
+        "cd" => "change the current directory",
+        "continue" => "start the next iteration of the enclosing loop",
+        "declare" | "typeset" => "set variable values and attributes",
+        "dirs" => "show the directory stack",
+        "echo" => "write arguments to stdout",
+        "enable" => "switch builtins on or off",
+        "exec" => "replace the shell with a command",
+        "exit" => "exit the shell",
+        "export" => "mark variables for the environment",
+        "false" => "do nothing, unsuccessfully",
+        "fg" => "resume a job in the foreground",
+        "hash" => "remember or list command locations",
+        "history" => "show, search or add to the history",
+        "hook" => "run functions on shell events",
+        "j" => "jump to a frequent or bookmarked directory",
+        "jobs" => "list jobs",
+        "kill" => "send a signal to a job or process",
+        "let" => "evaluate arithmetic expressions",
+        "logout" => "exit a login shell",
+        "popd" => "leave the top directory of the stack",
+        "pushd" => "change directory, keeping the old one on the stack",
+        "pwd" => "print the current directory",
+        "read" => "read a line into variables",
+        "rehash" => "forget remembered command locations",
+        "return" => "return from a function or sourced file",
+        "set" => "set options and positional parameters",
+        "shift" => "shift positional parameters",
+        "shopt" => "set optional shell behaviour",
+        "theme" => "show or change colours",
+        "trap" => "run commands on signals",
+        "true" => "do nothing, successfully",
+        "type" => "describe how a name would run",
+        "ulimit" => "show or set resource limits",
+        "umask" => "show or set the file creation mask",
+        "unalias" => "remove aliases",
+        "unset" => "remove variables or functions",
+        "wait" => "wait for jobs to finish",
+        "which" => "locate a command",
+        _ => return None,
+    })
+}
+
+///
+/// Whether a name runs as a builtin, being one and not switched off
+///
+pub fn is_enabled(state: &State, name: &str) -> bool {
+    BUILTINS.contains(&name) && !state.disabled.contains(name)
+}
+
+///
+/// Builtin echo: writes its arguments separated by spaces
+///
+/// Inputs:
+///   Arguments after `echo`. Leading `-n` leaves off the newline, `-e`
+///   interprets backslash escapes, `-E` turns that off again; flags can be
+///   combined as in `-ne`. Anything else is the first word to print
+///   Whether escapes are interpreted without `-e`, under `shopt -s xpg_echo`
+///
+/// Output:
+///   Exit status, 1 if writing failed
+///
+pub fn echo(args: &[String], xpg_echo: bool) -> i32 {
+    let mut newline = true;
+    let mut escapes = xpg_echo;
+    let mut words = args;
+    while let Some(flag) = words.first().and_then(|word| word.strip_prefix('-'))
+        && !flag.is_empty()
+        && flag.chars().all(|c| matches!(c, 'n' | 'e' | 'E')) {
+        for c in flag.chars() {
+            match c {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        words = &words[1..];
+    }
+
+    let mut out = words.join(" ");
+    if escapes {
+        let (text, stop) = unescape(&out);
+        out = text;
+        // `\c` cuts off everything after it, newline included
+        newline &= !stop;
+    }
+    if newline {
+        out.push('\n');
+    }
+
+    let mut stdout = io::stdout();
+    match stdout.write_all(out.as_bytes()).and_then(|_| stdout.flush()) {
+        Ok(()) => 0,
+        Err(e) => error::builtin("echo", format!("write error: {}", error::cause(&e))),
+    }
+}
+
+///
+/// Interprets the backslash escapes of `echo -e`
+///
+/// Output:
+///   The text with `\n`, `\t`, `\\`, `\e`, `\0nnn` octal, `\xHH` hex and
+///   the rest replaced, and whether a `\c` ended it early. Unknown escapes
+///   are kept as written
+///
+fn unescape(text: &str) -> (String, bool) {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('a') => out.push('\x07'),
+            Some('b') => out.push('\x08'),
+            Some('c') => return (out, true),
+            Some('e' | 'E') => out.push('\x1b'),
+            Some('f') => out.push('\x0c'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\x0b'),
+            Some('\\') => out.push('\\'),
+            Some('0') => out.extend(char::from_u32(number(&mut chars, 8, 3).unwrap_or(0))),
+            Some('x') => match number(&mut chars, 16, 2) {
+                Some(code) => out.extend(char::from_u32(code)),
+                None => out.push_str("\\x"),
+            },
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    (out, false)
+}
+
+///
+/// Reads up to max digits of the given radix as a character code
+///
+fn number(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> Option<u32> {
+    let mut code = None;
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                code = Some(code.unwrap_or(0) * radix + digit);
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    code
+}
+
+///
+/// Builtin pwd: prints the current directory
+///
+/// Input:
+///   Arguments after `pwd`: `-P` prints the directory with symlinks
+///   resolved; by default (`-L`) `$PWD` is printed while it still names
+///   the current directory, keeping the links it was reached through
+///
+/// Output:
+///   Exit status, 1 if the current directory is gone
+///
+pub fn pwd(args: &[String]) -> i32 {
+    let mut physical = false;
+    for arg in args {
+        match arg.as_str() {
+            "-P" => physical = true,
+            "-L" => physical = false,
+            arg => return error::usage("pwd", format!("{arg}: invalid option")),
+        }
+    }
+
+    let cwd = match env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(e) => return error::builtin("pwd", error::cause(&e)),
+    };
+    let logical = logical_cwd().filter(|_| !physical);
+
+    let shown = match logical {
+        Some(pwd) => pwd,
+        None if physical => fs::canonicalize(&cwd).unwrap_or(cwd),
+        None => cwd,
+    };
+    println!("{}", shown.display());
+    0
+}
+
+///
+/// Builtin exec: replaces the shell with a command
+///
+/// Inputs:
+///   Shell state, for restricted mode and the `$PATH` lookup
+///   Command and its arguments. With none there is nothing to do; the
+///   redirections given to `exec` have already been kept by the shell
+///
+/// Output:
+///   Exit status if the command could not be run, 127 if it was not found
+///   and 126 otherwise. On success it does not return
+///
+pub fn exec(state: &mut State, args: &[String]) -> i32 {
+    let Some(name) = args.first() else {
+        return 0;
+    };
+    if restricted(state, "replace the shell with exec") {
+        return 1;
+    }
+
+    let program = state.path_table.find(name).unwrap_or_else(|| PathBuf::from(name));
+    io::stdout().flush().ok();
+    io::stderr().flush().ok();
+    signals::reset_for_child();
+    let err = process::Command::new(program).arg0(name).args(&args[1..]).exec();
+
+    // Still here, so the shell carries on as it was
+    if jobs::interactive() {
+        signals::install_handlers();
+    }
+    if err.kind() == io::ErrorKind::NotFound {
+        error::builtin("exec", format!("{name}: not found"));
+        127
+    } else {
+        error::builtin("exec", format!("{name}: {}", error::cause(&err)));
+        126
+    }
+}
+
+///
+/// What a command name refers to, in the order the shell looks
+///
+enum Kind {
+    Alias(String),
+    Keyword,
+    Function,
+    Builtin,
+    /// Program on `$PATH`, and whether it has been run before
+    File(PathBuf, bool),
+}
+
+///
+/// Works out what a command name would run
+///
+fn kind(state: &mut State, name: &str) -> Option<Kind> {
+    if let Some(definition) = state.aliases.get(name) {
+        Some(Kind::Alias(definition.clone()))
+    } else if RESERVED.contains(&name) {
+        Some(Kind::Keyword)
+    } else if state.functions.contains_key(name) {
+        Some(Kind::Function)
+    } else if is_enabled(state, name) {
+        Some(Kind::Builtin)
+    } else {
+        let hashed = state.path_table.hits(name).is_some();
+        state.path_table.find(name).map(|path| Kind::File(path, hashed))
+    }
+}
+
+///
+/// Builtins type and which: tell what each name would run as a command
+///
+/// Inputs:
+///   Shell state holding the aliases, functions and disabled builtins
+///   Command name and arguments. `type NAME...` describes each name in a
+///   sentence and `type -t` in one word (alias, keyword, function,
+///   builtin or file); `which` prints the path of a file, or what the name
+///   is otherwise
+///
+/// Output:
+///   Exit status, 1 if any name was not found
+///
+pub fn type_of(state: &mut State, argv: &[String]) -> i32 {
+    let which = argv[0] == "which";
+    let mut names = &argv[1..];
+    let terse = !which && names.first().is_some_and(|arg| arg == "-t");
+    if terse {
+        names = &names[1..];
+    }
+
+    let mut status = 0;
+    for name in names {
+        let Some(kind) = kind(state, name) else {
+            // `type -t` says nothing for a name it cannot find
+            if !terse {
+                error::builtin(&argv[0], format!("{name}: not found"));
+            }
+            status = 1;
+            continue;
+        };
+
+        match (kind, which, terse) {
+            (Kind::Alias(_), _, true) => println!("alias"),
+            (Kind::Keyword, _, true) => println!("keyword"),
+            (Kind::Function, _, true) => println!("function"),
+            (Kind::Builtin, _, true) => println!("builtin"),
+            (Kind::File(..), _, true) => println!("file"),
+
+            (Kind::Alias(definition), true, _) => println!("{name}: aliased to {definition}"),
+            (Kind::Keyword, true, _) => println!("{name}: shell reserved word"),
+            (Kind::Function, true, _) => println!("{name}: shell function"),
+            (Kind::Builtin, true, _) => println!("{name}: shell builtin"),
+            (Kind::File(path, _), true, _) => println!("{}", path.display()),
+
+            (Kind::Alias(definition), false, _) => println!("{name} is aliased to `{definition}'"),
+            (Kind::Keyword, false, _) => println!("{name} is a shell keyword"),
+            (Kind::Function, false, _) => println!("{name} is a function"),
+            (Kind::Builtin, false, _) => println!("{name} is a shell builtin"),
+            (Kind::File(path, true), false, _) => println!("{name} is hashed ({})", path.display()),
+            (Kind::File(path, false), false, _) => println!("{name} is {}", path.display()),
+        }
+    }
+    status
+}
+
+///
+/// Options of the read builtin
+///
+#[derive(Default)]
+struct ReadOptions {
+    prompt: Option<String>,
+    /// `-s`: do not echo what is typed
+    silent: bool,
+    /// `-n N`: stop after this many characters
+    count: Option<usize>,
+    /// `-N N`: the count is exact, a newline being read like any other
+    /// character and nothing split at `$IFS`
+    exact: bool,
+    /// `-r`: backslashes are ordinary characters
+    raw: bool,
+    /// `-t SECONDS`: give up if the line is not read in time
+    timeout: Option<Duration>,
+}
+
+///
+/// Splits the arguments of read into its options and variable names
+///
+fn read_options(args: &[String]) -> Result<(ReadOptions, Vec<String>), String> {
+    let mut options = ReadOptions::default();
+    let mut args = args.iter();
+    let mut names = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty() && names.is_empty()) else {
+            names.push(arg.clone());
+            continue;
+        };
+
+        for (i, flag) in flags.char_indices() {
+            match flag {
+                's' => options.silent = true,
+                'r' => options.raw = true,
+                // The value is the rest of the argument or the next one
+                'p' | 'n' | 'N' | 't' => {
+                    let rest = &flags[i + 1..];
+                    let value = if rest.is_empty() { args.next().cloned() } else { Some(rest.to_string()) };
+                    let Some(value) = value else {
+                        return Err(format!("-{flag}: option requires an argument"));
+                    };
+                    match flag {
+                        'p' => options.prompt = Some(value),
+                        'n' | 'N' => {
+                            options.count = Some(value.parse().map_err(|_| format!("{value}: invalid number"))?);
+                            options.exact = flag == 'N';
+                        }
+                        _ => {
+                            let seconds = value.parse::<f64>().ok().filter(|s| s.is_finite() && *s >= 0.0);
+                            let Some(seconds) = seconds else {
+                                return Err(format!("{value}: invalid timeout specification"));
+                            };
+                            options.timeout = Some(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                    break;
+                }
+                flag => return Err(format!("-{flag}: invalid option")),
+            }
+        }
+    }
+    Ok((options, names))
+}
+
+///
+/// Builtin read: reads a line of stdin into variables
+///
+/// Inputs:
+///   Shell state whose variables are set
+///   Arguments after `read`: `-p prompt` shown first if stdin is a
+///   terminal, `-s` to not echo (for passwords), `-n N` to return after N
+///   characters, `-N N` to read exactly N characters, newlines included,
+///   into the first name unsplit, `-t SECONDS` to give up if the line has
+///   not come in time, `-r` to keep backslashes, then the variable names. The
+///   line is split into fields at `$IFS` characters (blanks by default);
+///   each name takes one field and the last the rest of the line. With no
+///   names the whole line goes in `$REPLY`
+///
+/// Output:
+///   Exit status, 1 at end of input (after setting what was read), 130 if
+///   interrupted, 142 on timeout (leaving the variables alone), 2
+///   for bad arguments. `-t 0` reads nothing and is 0 only if input is
+///   waiting
+///
+pub fn read(state: &mut State, args: &[String]) -> i32 {
+    let (options, mut names) = match read_options(args) {
+        Ok(parsed) => parsed,
+        Err(e) => return error::usage("read", e),
+    };
+    if names.is_empty() {
+        names.push("REPLY".to_string());
+    }
+    if let Some(name) = names.iter().find(|name| !is_name(name)) {
+        return error::usage("read", format!("`{name}': not a valid identifier"));
+    }
+    if let Some(name) = names.iter().find(|name| RESTRICTED_VARS.contains(&name.as_str()))
+        && restricted(state, &format!("set {name}")) {
+        return 1;
+    }
+
+    if options.timeout == Some(Duration::ZERO) {
+        return match editor::ready(0) {
+            Ok(ready) => i32::from(!ready),
+            Err(e) => error::builtin("read", error::cause(&e)),
+        };
+    }
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    // SAFETY: isatty has no memory safety requirements
+    let terminal = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    if terminal && let Some(prompt) = &options.prompt {
+        eprint!("{prompt}");
+        io::stderr().flush().ok();
+    }
+
+    // A count needs the terminal to hand over each key as it is typed
+    let mut modes = 0;
+    if options.silent {
+        modes |= libc::ECHO;
+    }
+    if options.count.is_some() {
+        modes |= libc::ICANON;
+    }
+    let _mode = if terminal && modes != 0 { RawMode::without(modes).ok() } else { None };
+
+    // Read byte by byte, so nothing after the line is taken from a pipe or
+    // file that later commands read
+    let mut bytes = Vec::new();
+    let mut chars = 0;
+    let mut eof = false;
+    let mut timed_out = false;
+    while options.count.is_none_or(|count| chars < count) {
+        // Waits only as long as is left before the deadline, like SIGALRM
+        // ending bash's read
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match editor::ready(left.as_millis().min(i32::MAX as u128) as i32) {
+                Ok(true) => {}
+                Ok(false) => {
+                    timed_out = true;
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    if signals::take_interrupt() {
+                        return 128 + libc::SIGINT;
+                    }
+                    continue;
+                }
+                Err(e) => return error::builtin("read", error::cause(&e)),
+            }
+        }
+        let byte = match editor::read_byte() {
+            Ok(Some(byte)) => byte,
+            Ok(None) => {
+                eof = true;
+                break;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return 128 + libc::SIGINT,
+            Err(e) => return error::builtin("read", error::cause(&e)),
+        };
+
+        if byte == b'\n' && !options.exact {
+            // An escaped newline continues the line
+            if !options.raw && bytes.last() == Some(&b'\\') && escaped_end(&bytes) {
+                bytes.pop();
+                continue;
+            }
+            break;
+        }
+        bytes.push(byte);
+        // Counts characters, not the bytes of one
+        if byte & 0xc0 != 0x80 {
+            chars += 1;
+        }
+    }
+
+    if timed_out {
+        return 128 + libc::SIGALRM;
+    }
+
+    let line = String::from_utf8_lossy(&bytes);
+    let ifs = lookup(state, "IFS").unwrap_or_else(|| " \t\n".to_string());
+    let fields = match options.exact {
+        true => split_fields(&line, "", 1, options.raw),
+        false => split_fields(&line, &ifs, names.len(), options.raw),
+    };
+    let mut status = i32::from(eof);
+    for (i, name) in names.iter().enumerate() {
+        if let Err(e) = assign(state, name, fields.get(i).cloned().unwrap_or_default()) {
+            status = error::report(state, e);
+        }
+    }
+    status
+}
+
+///
+/// Whether the backslash ending bytes is itself unescaped
+///
+fn escaped_end(bytes: &[u8]) -> bool {
+    bytes.iter().rev().take_while(|&&b| b == b'\\').count() % 2 == 1
+}
+
+///
+/// Splits a line read into at most count fields, the last keeping the
+/// rest of the line. Unless raw, a backslash keeps the next character
+/// from separating fields and is removed
+///
+fn split_fields(line: &str, ifs: &str, count: usize, raw: bool) -> Vec<String> {
+    let mut chars = Vec::new();
+    let mut iter = line.chars();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' if !raw => chars.extend(iter.next().map(|c| (c, true))),
+            c => chars.push((c, false)),
+        }
+    }
+    let separator = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+
+    let mut fields = Vec::new();
+    let mut rest = &chars[..];
+    while fields.len() + 1 < count {
+        rest = &rest[rest.iter().take_while(|c| separator(c)).count()..];
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.iter().position(separator).unwrap_or(rest.len());
+        fields.push(rest[..end].iter().map(|(c, _)| c).collect());
+        rest = &rest[end..];
+    }
+
+    // The last field is the rest, without separators at either end
+    let start = rest.iter().take_while(|c| separator(c)).count();
+    let end = rest.len() - rest.iter().rev().take_while(|c| separator(c)).count();
+    if start < end {
+        fields.push(rest[start..end].iter().map(|(c, _)| c).collect());
+    }
+    fields
+}
+
+///
+/// Current file-creation mask
+///
+fn current_umask() -> u32 {
+    // SAFETY: umask has no memory safety requirements; the mask is put
+    // straight back
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+///
+/// Applies a symbolic mode like `u=rwx,g-w,o=` to the permissions a mask
+/// allows
+///
+/// Output:
+///   The new mask, or None if the mode is not valid
+///
+fn symbolic_umask(mode: &str, mask: u32) -> Option<u32> {
+    let mut allowed = !mask & 0o777;
+    for clause in mode.split(',') {
+        let op_at = clause.find(['=', '+', '-'])?;
+        let (who, rest) = clause.split_at(op_at);
+        let mut who_bits = 0;
+        for c in who.chars() {
+            who_bits |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if who.is_empty() {
+            who_bits = 0o777;
+        }
+
+        // Each operator applies the permissions up to the next one
+        let mut rest = rest;
+        while let Some(op) = rest.chars().next() {
+            let end = rest[1..].find(['=', '+', '-']).map_or(rest.len(), |end| end + 1);
+            let mut perms = 0;
+            for c in rest[1..end].chars() {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    _ => return None,
+                };
+            }
+            perms &= who_bits;
+            match op {
+                '=' => allowed = (allowed & !who_bits) | perms,
+                '+' => allowed |= perms,
+                _ => allowed &= !perms,
+            }
+            rest = &rest[end..];
+        }
+    }
+    Some(!allowed & 0o777)
+}
+
+///
+/// Builtin shift: drops positional parameters from the front, so `$2`
+/// becomes `$1`
+///
+/// Inputs:
+///   Shell state holding the positional parameters
+///   Arguments after `shift`: how many to drop, 1 if not given
+///
+/// Output:
+///   Exit status, 1 if the count is not a number or more than `$#`
+///
+pub fn shift(state: &mut State, args: &[String]) -> i32 {
+    let count = match args {
+        [] => 1,
+        [count] => match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => return error::builtin("shift", format!("{count}: numeric argument required")),
+        },
+        _ => return error::builtin("shift", "too many arguments"),
+    };
+    if count > state.positional.len() {
+        return error::builtin("shift", format!("{count}: shift count out of range"));
+    }
+    state.positional.drain(..count);
+    0
+}
+
+///
+/// How many loops builtin break or continue leaves
+///
+/// Inputs:
+///   Shell state, for how many loops are running
+///   Name it was run as
+///   Arguments after the name: how many loops, 1 if not given. More than
+///   are running means all of them
+///
+/// Output:
+///   The count, or the exit status to give instead: 0 outside a loop,
+///   where it does nothing, and 1 for a count that is not a positive number
+///
+pub fn loop_count(state: &State, name: &str, args: &[String]) -> Result<usize, i32> {
+    let count = match args {
+        [] => 1,
+        [count] => match count.parse::<usize>() {
+            Ok(0) => return Err(error::builtin(name, format!("{count}: loop count out of range"))),
+            Ok(count) => count,
+            Err(_) => return Err(error::builtin(name, format!("{count}: numeric argument required"))),
+        },
+        _ => return Err(error::builtin(name, "too many arguments")),
+    };
+    if state.loop_depth == 0 {
+        error::builtin(name, "only meaningful in a `for', `while', or `until' loop");
+        return Err(0);
+    }
+    Ok(count.min(state.loop_depth))
+}
+
+///
+/// Builtin umask: shows or sets the mask of permissions new files do not
+/// get
+///
+/// Input:
+///   Arguments after `umask`: a mask in octal (`022`) or a symbolic mode
+///   of the permissions to allow (`u=rwx,g=rx,o=`, `g-w`) sets it. With
+///   none the mask is printed, symbolically with `-S`, and `-p` prints it
+///   as the command that would set it
+///
+/// Output:
+///   Exit status, 1 if the mask was not valid
+///
+pub fn umask(args: &[String]) -> i32 {
+    let mut symbolic = false;
+    let mut reusable = false;
+    let mut args = args;
+    while let Some(flag) = args.first().and_then(|arg| arg.strip_prefix('-'))
+        && !flag.is_empty()
+        && flag.chars().all(|c| matches!(c, 'S' | 'p')) {
+        symbolic |= flag.contains('S');
+        reusable |= flag.contains('p');
+        args = &args[1..];
+    }
+
+    let mask = current_umask();
+    let Some(mode) = args.first() else {
+        let shown = if symbolic {
+            let allowed = !mask & 0o777;
+            let perms = |shift: u32| {
+                [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')].iter()
+                    .filter(|&&(bit, _)| allowed >> shift & bit != 0)
+                    .map(|&(_, c)| c)
+                    .collect::<String>()
+            };
+            format!("u={},g={},o={}", perms(6), perms(3), perms(0))
+        } else {
+            format!("{mask:04o}")
+        };
+        if reusable {
+            println!("umask {}{shown}", if symbolic { "-S " } else { "" });
+        } else {
+            println!("{shown}");
+        }
+        return 0;
+    };
+
+    let new = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+        match u32::from_str_radix(mode, 8) {
+            Ok(new) if new <= 0o777 => new,
+            _ => return error::builtin("umask", format!("{mode}: octal number out of range")),
+        }
+    } else {
+        match symbolic_umask(mode, mask) {
+            Some(new) => new,
+            None => return error::builtin("umask", format!("{mode}: invalid symbolic mode")),
+        }
+    };
+    // SAFETY: umask has no memory safety requirements
+    unsafe { libc::umask(new as libc::mode_t) };
+    0
+}
+
+/// Type setrlimit names resources with
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+///
+/// Limits `ulimit` knows, as its option letter, the resource, what
+/// `ulimit -a` calls it, the unit it shows and the bytes in that unit
+///
+const LIMITS: &[(char, Resource, &str, &str, libc::rlim_t)] = &[
+    ('c', libc::RLIMIT_CORE, "core file size", "blocks", 1024),
+    ('d', libc::RLIMIT_DATA, "data seg size", "kbytes", 1024),
+    ('f', libc::RLIMIT_FSIZE, "file size", "blocks", 1024),
+    ('l', libc::RLIMIT_MEMLOCK, "max locked memory", "kbytes", 1024),
+    ('m', libc::RLIMIT_RSS, "max memory size", "kbytes", 1024),
+    ('n', libc::RLIMIT_NOFILE, "open files", "", 1),
+    ('s', libc::RLIMIT_STACK, "stack size", "kbytes", 1024),
+    ('t', libc::RLIMIT_CPU, "cpu time", "seconds", 1),
+    ('u', libc::RLIMIT_NPROC, "max user processes", "", 1),
+    ('v', libc::RLIMIT_AS, "virtual memory", "kbytes", 1024),
+];
+
+///
+/// Builtin ulimit: shows or sets limits on the resources the shell and
+/// the commands it runs may use
+///
+/// Input:
+///   Arguments after `ulimit`: option letters for the limits to show or
+///   set (`-n` open files, `-c` core size and the others `-a` lists),
+///   `-f` if none are given, and then a value to set, a number in the
+///   limit's unit or `unlimited`. `-S` and `-H` pick the soft or hard
+///   limit; values are set for both unless one is picked, and the soft
+///   limit is shown
+///
+/// Output:
+///   Exit status, 1 if a limit could not be read or set
+///
+pub fn ulimit(args: &[String]) -> i32 {
+    let mut soft = false;
+    let mut hard = false;
+    let mut all = false;
+    let mut wanted = Vec::new();
+    let mut value = None;
+
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && value.is_none() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'S' => soft = true,
+                        'H' => hard = true,
+                        'a' => all = true,
+                        flag => match LIMITS.iter().find(|limit| limit.0 == flag) {
+                            Some(limit) => wanted.push(limit),
+                            None => return error::usage("ulimit", format!("-{flag}: invalid option")),
+                        },
+                    }
+                }
+            }
+            _ if value.is_none() => value = Some(arg.as_str()),
+            _ => return error::usage("ulimit", format!("{arg}: too many arguments")),
+        }
+    }
+    if all {
+        wanted = LIMITS.iter().collect();
+    }
+    if wanted.is_empty() {
+        wanted.extend(LIMITS.iter().find(|limit| limit.0 == 'f'));
+    }
+
+    let mut status = 0;
+    for &&(flag, resource, name, unit, scale) in &wanted {
+        let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        // SAFETY: getrlimit only writes the struct we own
+        if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+            let cause = error::cause(&io::Error::last_os_error());
+            status = error::builtin("ulimit", format!("{name}: cannot get limit: {cause}"));
+            continue;
+        }
+
+        let Some(value) = value else {
+            let current = if hard && !soft { limit.rlim_max } else { limit.rlim_cur };
+            let shown = if current == libc::RLIM_INFINITY {
+                "unlimited".to_string()
+            } else {
+                (current / scale).to_string()
+            };
+            if wanted.len() > 1 {
+                let unit = if unit.is_empty() { String::new() } else { format!("{unit}, ") };
+                println!("{:<28}({unit}-{flag}) {shown}", name);
+            } else {
+                println!("{shown}");
+            }
+            continue;
+        };
+
+        let new = match value {
+            "unlimited" => libc::RLIM_INFINITY,
+            "hard" => limit.rlim_max,
+            "soft" => limit.rlim_cur,
+            number => match number.parse::<libc::rlim_t>().ok().and_then(|n| n.checked_mul(scale)) {
+                Some(n) => n,
+                None => return error::builtin("ulimit", format!("{number}: invalid number")),
+            },
+        };
+        if soft || !hard {
+            limit.rlim_cur = new;
+        }
+        if hard || !soft {
+            limit.rlim_max = new;
+        }
+        // SAFETY: setrlimit only reads the struct we own
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            let cause = error::cause(&io::Error::last_os_error());
+            status = error::builtin("ulimit", format!("{name}: cannot modify limit: {cause}"));
+        }
+    }
+    status
+}
+
+///
+/// Runs a builtin in the shell process
+///
+/// Inputs:
+///   Shell state shared across commands
+///   Expanded command name and arguments, name being an enabled builtin
+///
+/// Returns:
+///   Continue with the builtin's exit status, or Break with the status
+///   the shell should exit with
+///
+pub fn run(state: &mut State, argv: &[String]) -> ControlFlow<i32, i32> {
+    let mut parts = argv[1..].iter().map(String::as_str);
+
+    let status = match argv[0].as_str() {
+        "cd" | "j" | "pushd" | "popd" if restricted(state, "change directory") => 1,
+        "cd" => match resolve_cd(state, &argv[1..]) {
+            Ok((dir, show, physical)) => {
+                let status = change_dir("cd", &dir, physical);
+                if status == 0 && show {
+                    println!("{}", logical_cwd().unwrap_or(dir).display());
+                }
+                status
+            },
+            Err(e) => error::builtin("cd", e),
+        },
+        "dirs" => dirs(state, parts),
+        "pushd" => pushd(state, &argv[1..]),
+        "popd" => popd(state),
+        "j" => {
+            match parts.next() {
+                Some(fragment) => match jump::best_match(fragment) {
+                    Some(dir) => change_dir("j", &dir, false),
+                    None => error::builtin("j", format!("no match for '{fragment}'")),
+                },
+                None => error::usage("j", "usage: j <bookmark or directory fragment>"),
+            }
+        },
+        "bookmark" => jump::builtin_bookmark(&argv[1..]),
+        "source" | "." => return source(state, argv),
+        "enable" => enable(state, parts),
+        "export" => export(state, parts),
+        "unset" => unset(state, parts),
+        "declare" | "typeset" => declare(state, &argv[0], &argv[1..]),
+        "exec" => {
+            // A script cannot go on once its exec has failed
+            let status = exec(state, &argv[1..]);
+            if status != 0 && !jobs::interactive() {
+                return ControlFlow::Break(status);
+            }
+            status
+        },
+        "exit" => {
+            // Without an argument the shell exits with the last status
+            return match parts.next() {
+                None => ControlFlow::Break(state.last_status),
+                Some(arg) => match arg.parse::<i32>() {
+                    Ok(code) => ControlFlow::Break(code & 0xff),
+                    Err(_) => {
+                        error::builtin("exit", format!("{arg}: numeric argument required"));
+                        ControlFlow::Break(2)
+                    }
+                },
+            };
+        },
+        "logout" if !state.login => error::builtin("logout", "not login shell: use `exit'"),
+        "logout" => return ControlFlow::Break(state.last_status),
+        "history" => history(state, parts),
+        "wait" => wait(state, parts),
+        "return" if state.function_depth == 0 => {
+            error::builtin("return", "can only return from a function or sourced file")
+        },
+        "return" => {
+            // Unwinds like exit until the function call catches it
+            let code = match parts.next().map(str::parse::<i32>) {
+                None => state.last_status,
+                Some(Ok(code)) => code & 0xff,
+                Some(Err(_)) => error::usage("return", "numeric argument required"),
+            };
+            state.returning = true;
+            return ControlFlow::Break(code);
+        },
+        "break" | "continue" => match loop_count(state, &argv[0], &argv[1..]) {
+            // Unwinds like return until the loop catches it
+            Ok(count) => {
+                state.breaking = count;
+                state.continuing = argv[0] == "continue";
+                return ControlFlow::Break(0);
+            },
+            Err(status) => status,
+        },
+        "echo" => echo(&argv[1..], state.options.xpg_echo),
+        "pwd" => pwd(&argv[1..]),
+        "true" => 0,
+        "false" => 1,
+        "test" | "[" => condition::builtin_test(&argv[0], &argv[1..]),
+        "type" | "which" => type_of(state, argv),
+        "hash" => state.path_table.builtin_hash(&argv[1..]),
+        "let" => arith::builtin_let(state, &argv[1..]),
+        "read" => read(state, &argv[1..]),
+        "set" => options::builtin_set(state, &argv[1..]),
+        "shopt" => options::builtin_shopt(state, &argv[1..]),
+        "shift" => shift(state, &argv[1..]),
+        "theme" => theme::builtin_theme(&mut state.editor.theme, &argv[1..]),
+        "trap" => trap::builtin_trap(state, &argv[1..]),
+        "ulimit" => ulimit(&argv[1..]),
+        "umask" => umask(&argv[1..]),
+        "rehash" => {
+            state.path_table.rehash();
+            0
+        },
+        "kill" => kill(state, parts),
+        "jobs" => jobs(state, parts),
+        "fg" => fg(state, parts.next()),
+        "bg" => bg(state, parts),
+        "alias" => alias(state, parts),
+        "unalias" => unalias(state, parts),
+        "hook" => hooks::builtin_hook(state, &argv[1..]),
+        name => unreachable!("{name} is not a builtin"),
+    };
+
+    if status == 0 && matches!(argv[0].as_str(), "cd" | "j" | "pushd" | "popd") {
+        hooks::run(state, hooks::Event::Chpwd, &[])?;
+    }
+    ControlFlow::Continue(status)
+}
+
+///
+/// Finds the directory cd should change to
+///
+/// Inputs:
+///   Shell state, for `$CDPATH` whether or not it is exported
+///   Already expanded arguments to cd: `-P` to follow symlinks to the
+///   physical directory or `-L`, the default, to keep them in `$PWD`,
+///   then the directory. None is a valid command (takes to home), and `-`
+///   goes back to `$OLDPWD`
+///
+/// Output:
+///   The new directory, whether cd should print it because it was not
+///   spelled out (`cd -` or a `$CDPATH` match), and whether `-P` was
+///   given. A glob may have expanded to several paths; the first that is
+///   a directory wins. Err holds the message to report, also for a second
+///   operand
+///
+fn resolve_cd(state: &State, args: &[String]) -> std::result::Result<(PathBuf, bool, bool), String> {
+    let mut words = by_word(state, args);
+    // Options are words of their own, a lone `-` being the operand
+    let mut physical = false;
+    while let Some([option]) = words.first().copied()
+        && option.len() > 1
+        && option.starts_with('-') {
+        words.remove(0);
+        if option == "--" {
+            break;
+        }
+        for flag in option[1..].chars() {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                flag => return Err(format!("-{flag}: invalid option")),
+            }
+        }
+    }
+
+    let Some(&paths) = words.first() else {
+        return Ok((platform::home().unwrap_or_else(|| PathBuf::from("/")), false, physical));
+    };
+    if words.len() > 1 {
+        return Err("too many arguments".to_string());
+    }
+    let first = &paths[0];
+
+    if first == "-" {
+        return match env::var("OLDPWD") {
+            Ok(old) => Ok((PathBuf::from(old), true, physical)),
+            Err(_) => Err("OLDPWD not set".to_string()),
+        };
+    }
+
+    let target = paths.iter()
+        .find(|path| Path::new(path).is_dir())
+        .unwrap_or(first);
+
+    // Relative names are looked up in each `$CDPATH` entry first; an empty
+    // entry stands for the current directory
+    let explicit = Path::new(target).is_absolute() || target == "." || target == ".."
+        || target.starts_with("./") || target.starts_with("../");
+    if !explicit && let Some(cdpath) = lookup(state, "CDPATH") {
+        for base in env::split_paths(&cdpath) {
+            let candidate = base.join(target);
+            if candidate.is_dir() {
+                let spelled_out = base.as_os_str().is_empty();
+                return Ok((candidate, !spelled_out, physical));
+            }
+        }
+    }
+
+    Ok((PathBuf::from(target), false, physical))
+}
+
+///
+/// Groups the arguments of the running builtin by the word each came from
+///
+/// Inputs:
+///   Shell state, holding how many arguments each word expanded to
+///   Arguments after the builtin's name
+///
+/// Output:
+///   What each word gave, leaving out words that gave nothing. Counts
+///   that do not add up to the arguments are from another command, and
+///   each argument is taken as a word of its own
+///
+fn by_word<'a>(state: &State, args: &'a [String]) -> Vec<&'a [String]> {
+    let counts = state.arg_words.get(1..).unwrap_or_default();
+    if counts.iter().sum::<usize>() != args.len() {
+        return args.chunks(1).collect();
+    }
+    let mut rest = args;
+    counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let (word, after) = rest.split_at(count);
+            rest = after;
+            word
+        })
+        .collect()
+}
+
+///
+/// Changes the working directory and remembers it for `j`
+///
+/// Inputs:
+///   Builtin changing it, named in the error if it cannot
+///   Directory to go to
+///   Whether `$PWD` becomes the directory with symlinks resolved, rather
+///   than the path it was reached by, `..` taking off the last part of
+///   that path as in bash
+///
+/// Output:
+///   Exit status, 1 if the directory could not be entered
+///
+fn change_dir(name: &str, dir: &Path, physical: bool) -> i32 {
+    let old = logical_cwd().or_else(|| env::current_dir().ok());
+    let logical = old.as_ref()
+        .filter(|_| !physical)
+        .map(|old| normalize(&old.join(dir)))
+        .filter(|path| env::set_current_dir(path).is_ok());
+    if logical.is_none() && let Err(e) = env::set_current_dir(dir) {
+        return error::builtin(name, format!("{}: {}", dir.display(), error::cause(&e)));
+    }
+
+    if let Some(old) = old {
+        platform::set_env("OLDPWD", old);
+    }
+    let cwd = match logical {
+        Some(path) => Some(path),
+        None => env::current_dir().ok().map(|cwd| fs::canonicalize(&cwd).unwrap_or(cwd)),
+    };
+    if let Some(cwd) = cwd {
+        platform::set_env("PWD", &cwd);
+        jump::record(&cwd);
+    }
+    0
+}
+
+///
+/// `$PWD` while it still names the current directory, the path that
+/// reached it with any symlinks on the way
+///
+fn logical_cwd() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let same = |a: &Path, b: &Path| match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    };
+    env::var_os("PWD")
+        .map(PathBuf::from)
+        .filter(|pwd| pwd.is_absolute() && same(pwd, &cwd))
+}
+
+///
+/// Takes `.` and `..` out of an absolute path without looking at the
+/// filesystem, so `..` after a symlink goes back to where the link was
+///
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            part => out.push(part),
+        }
+    }
+    out
+}
+
+///
+/// Builtin kill: sends signals to processes by pid, or to jobs
+///
+/// Inputs:
+///   Shell state holding the jobs table
+///   Arguments after `kill`. Either `-l [sig...]` to list or translate
+///   signals, or an optional `-SIG` (`-9`, `-KILL`, `-SIGKILL`) or
+///   `-s SIG` followed by pids and job specs like `%1`. Signal defaults
+///   to TERM. A job gets the signal in every one of its processes, and a
+///   stopped one is continued so it can act on it
+///
+/// Output:
+///   Exit status, 1 if any argument could not be handled
+///
+fn kill<'a>(state: &mut State, mut args: impl Iterator<Item=&'a str>) -> i32 {
+    let mut first = args.next();
+    let mut status = 0;
+
+    if first == Some("-l") {
+        let mut listed = false;
+        for arg in args {
+            listed = true;
+
+            // Numbers translate to names (exit statuses past 128 included)
+            // and names translate back to numbers
+            match arg.parse::<i32>() {
+                Ok(num) => {
+                    let num = if num > 128 { num - 128 } else { num };
+                    match signals::signal_name(num) {
+                        Some(name) => println!("{name}"),
+                        None => {
+                            status = error::builtin("kill", format!("{arg}: invalid signal specification"));
+                        }
+                    }
+                }
+                Err(_) => match signals::signal_number(arg) {
+                    Some(num) => println!("{num}"),
+                    None => {
+                        status = error::builtin("kill", format!("{arg}: invalid signal specification"));
+                    }
+                }
+            }
+        }
+
+        if !listed {
+            for (name, num) in signals::SIGNALS {
+                println!("{num:>2}) SIG{name}");
+            }
+        }
+        return status;
+    }
+
+    let mut signal = libc::SIGTERM;
+    if first == Some("-s") {
+        first = args.next();
+        let Some(spec) = first else {
+            return error::usage("kill", "-s: option requires an argument");
+        };
+        let Some(num) = signals::signal_number(spec) else {
+            return error::builtin("kill", format!("{spec}: invalid signal specification"));
+        };
+        signal = num;
+        first = args.next();
+    } else if first == Some("--") {
+        first = args.next();
+    } else if let Some(spec) = first.and_then(|f| f.strip_prefix('-')) {
+        let Some(num) = signals::signal_number(spec) else {
+            return error::builtin("kill", format!("{spec}: invalid signal specification"));
+        };
+        signal = num;
+        first  = args.next();
+    }
+
+    let Some(first) = first else {
+        return error::usage("kill", "usage: kill [-s signal | -signal] pid | jobspec ... or kill -l [signal]");
+    };
+
+    for arg in std::iter::once(first).chain(args) {
+        if arg.starts_with('%') {
+            let job = state.jobs.resolve(Some(arg)).and_then(|id| state.jobs.get(id).ok_or_else(|| format!("{arg}: no such job")));
+            let (pgid, stopped) = match job {
+                Ok(job) => (job.pgid, job.state == JobState::Stopped),
+                Err(e) => {
+                    status = error::builtin("kill", e);
+                    continue;
+                }
+            };
+
+            // SAFETY: kill has no memory safety requirements
+            if unsafe { libc::kill(-pgid, signal) } != 0 {
+                status = error::builtin("kill", format!("{arg}: {}", error::cause(&io::Error::last_os_error())));
+            } else if stopped && ![libc::SIGSTOP, libc::SIGTSTP, libc::SIGCONT].contains(&signal) {
+                // SAFETY: as above
+                unsafe { libc::kill(-pgid, libc::SIGCONT) };
+            }
+            continue;
+        }
+
+        let Ok(pid) = arg.parse::<libc::pid_t>() else {
+            status = error::builtin("kill", format!("{arg}: arguments must be process or job IDs"));
+            continue;
+        };
+
+        // SAFETY: kill has no memory safety requirements
+        if unsafe { libc::kill(pid, signal) } != 0 {
+            status = error::builtin("kill", format!("({pid}) - {}", error::cause(&io::Error::last_os_error())));
+        }
+    }
+
+    status
+}
+
+///
+/// Builtin enable: turns builtins on and off
+///
+/// Inputs:
+///   Shell state holding the disabled set
+///   Arguments after `enable`: `-n name...` disables, `name...` re-enables.
+///   With no names it lists the enabled builtins (or disabled with `-n`)
+///
+/// Output:
+///   Exit status, 1 if any name could not be switched
+///
+fn enable<'a>(state: &mut State, args: impl Iterator<Item=&'a str>) -> i32 {
+    let mut disable = false;
+    let mut names = Vec::new();
+    for arg in args {
+        match arg {
+            "-n" => disable = true,
+            name => names.push(name),
+        }
+    }
+
+    if names.is_empty() {
+        for name in BUILTINS {
+            if state.disabled.contains(*name) == disable {
+                let flag = if disable { "-n " } else { "" };
+                println!("enable {flag}{name}");
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for name in names {
+        if !BUILTINS.contains(&name) {
+            status = error::builtin("enable", format!("{name}: not a shell builtin"));
+        } else if !disable {
+            state.disabled.remove(name);
+        } else if name == "enable" {
+            // Nothing could turn it back on again
+            status = error::builtin("enable", "cannot disable enable");
+        } else {
+            state.disabled.insert(name.to_string());
+        }
+    }
+
+    status
+}
+
+///
+/// Builtin export: puts variables in the environment of later commands
+///
+/// Inputs:
+///   Shell state holding the shell variables
+///   Arguments after `export`: `NAME=value` sets and exports, `NAME+=value`
+///   appends to what it held, `NAME` exports an existing shell variable,
+///   and `--` ends the options so a name after it may start with `-`. With
+///   none it lists the environment
+///
+/// Output:
+///   Exit status, 1 if any argument was not a valid name
+///
+fn export<'a>(state: &mut State, args: impl Iterator<Item=&'a str>) -> i32 {
+    let mut args = args.peekable();
+    match args.peek() {
+        Some(&"--") => {
+            args.next();
+        },
+        Some(option) if option.starts_with('-') && option.len() > 1 => {
+            return error::usage("export", format!("{option}: invalid option"));
+        },
+        _ => {},
+    }
+    if args.peek().is_none() {
+        let mut vars: Vec<_> = env::vars().collect();
+        vars.sort();
+        for (name, value) in vars {
+            println!("export {name}={}", quoted(&value));
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => match name.strip_suffix('+') {
+                Some(name) => (name, Some(lookup(state, name).unwrap_or_default() + value)),
+                None => (name, Some(value.to_string())),
+            },
+            None => (arg, None),
+        };
+        if !lexer::is_name(name) {
+            status = error::builtin("export", format!("`{arg}': not a valid identifier"));
+            continue;
+        }
+
+        let changes = value.is_some() || state.vars.contains_key(name);
+        if changes && RESTRICTED_VARS.contains(&name) && restricted(state, &format!("set {name}")) {
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value
+            && let Err(e) = assign(state, name, value) {
+            status = error::report(state, e);
+            continue;
+        }
+        // Already in the environment if it was exported before
+        if let Some(value) = state.vars.remove(name) {
+            platform::set_env(name, value);
+        }
+    }
+
+    status
+}
+
+///
+/// Builtin unset: removes variables or functions
+///
+/// Inputs:
+///   Shell state holding the shell variables and functions
+///   Arguments after `unset`: `-v` for variables only or `-f` for
+///   functions only, `--` to end the options, then the names. Without
+///   either option a name is a variable if one is set, else a function
+///
+/// Output:
+///   Exit status, 1 if any name was not valid, 2 for a bad option
+///
+fn unset<'a>(state: &mut State, args: impl Iterator<Item=&'a str>) -> i32 {
+    let mut args = args.peekable();
+    let (mut variables, mut functions) = (true, true);
+    while let Some(&option) = args.peek()
+        && option.starts_with('-') && option.len() > 1 {
+        args.next();
+        match option {
+            "--" => break,
+            "-v" => functions = false,
+            "-f" => variables = false,
+            _ => return error::usage("unset", format!("{option}: invalid option")),
+        }
+    }
+
+    let mut status = 0;
+    for name in args {
+        if variables && !lexer::is_name(name) {
+            status = error::builtin("unset", format!("`{name}': not a valid identifier"));
+            continue;
+        }
+        if variables && (state.vars.contains_key(name) || env::var_os(name).is_some()) {
+            if RESTRICTED_VARS.contains(&name) && restricted(state, &format!("unset {name}")) {
+                status = 1;
+                continue;
+            }
+            if readonly(state, name) {
+                status = error::builtin("unset", format!("{name}: cannot unset: readonly variable"));
+                continue;
+            }
+            state.vars.remove(name);
+            state.attributes.remove(name);
+            platform::remove_env(name);
+        } else if functions {
+            state.functions.remove(name);
+        }
+    }
+
+    status
+}
+
+///
+/// A value in double quotes, so a listing of it can be read back in
+///
+fn quoted(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+///
+/// A variable as `declare -p` shows it, such as `declare -ir n="5"`, or
+/// None if it is neither set nor given attributes
+///
+fn declaration(state: &State, name: &str) -> Option<String> {
+    let attributes = state.attributes.get(name).copied().unwrap_or_default();
+    let value = lookup(state, name);
+    if value.is_none() && attributes == Attributes::default() {
+        return None;
+    }
+
+    let mut flags = String::new();
+    if attributes.integer {
+        flags.push('i');
+    }
+    if attributes.readonly {
+        flags.push('r');
+    }
+    if env::var_os(name).is_some() {
+        flags.push('x');
+    }
+    if flags.is_empty() {
+        flags.push('-');
+    }
+    Some(match value {
+        Some(value) => format!("declare -{flags} {name}={}", quoted(&value)),
+        None => format!("declare -{flags} {name}"),
+    })
+}
+
+///
+/// Builtin declare, or typeset: gives variables attributes and values
+///
+/// Inputs:
+///   Shell state holding the variables and their attributes
+///   Name it was run as
+///   Arguments after it: `-i` makes values assigned be evaluated as
+///   arithmetic, `-r` makes the variables readonly, `-x` exports them and
+///   `-p` prints them, `+i` and `+x` undo the first and third, then
+///   `NAME` or `NAME=value`. Without names it lists the variables, only
+///   those with the attributes given if any are
+///
+/// Output:
+///   Exit status, 1 if a name was not valid or readonly or its value
+///   could not be assigned, 2 for a bad option
+///
+fn declare(state: &mut State, builtin: &str, args: &[String]) -> i32 {
+    let (mut on, mut off) = (Attributes::default(), Attributes::default());
+    let (mut export, mut unexport, mut print) = (false, false, false);
+    let mut args = args.iter().map(String::as_str).peekable();
+    while let Some(&option) = args.peek()
+        && (option.starts_with('-') || option.starts_with('+')) && option.len() > 1 {
+        args.next();
+        if option == "--" {
+            break;
+        }
+        let set = option.starts_with('-');
+        for flag in option[1..].chars() {
+            match (flag, set) {
+                ('i', true) => on.integer = true,
+                ('i', false) => off.integer = true,
+                ('r', true) => on.readonly = true,
+                ('r', false) => off.readonly = true,
+                ('x', true) => export = true,
+                ('x', false) => unexport = true,
+                ('p', _) => print = true,
+                _ => return error::usage(builtin, format!("{option}: invalid option")),
+            }
+        }
+    }
+
+    let names: Vec<&str> = args.collect();
+    if names.is_empty() {
+        let mut all: Vec<String> = state.vars.keys().cloned()
+            .chain(env::vars_os().filter_map(|(name, _)| name.into_string().ok()))
+            .chain(state.attributes.keys().cloned())
+            .collect();
+        all.sort();
+        all.dedup();
+        for name in all {
+            let attributes = state.attributes.get(&name).copied().unwrap_or_default();
+            let wanted = (!on.integer || attributes.integer)
+                && (!on.readonly || attributes.readonly)
+                && (!export || env::var_os(&name).is_some());
+            if wanted && let Some(line) = declaration(state, &name) {
+                println!("{line}");
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg, None),
+        };
+        if !is_name(name) {
+            status = error::builtin(builtin, format!("`{arg}': not a valid identifier"));
+            continue;
+        }
+        if print {
+            match declaration(state, name) {
+                Some(line) => println!("{line}"),
+                None => status = error::builtin(builtin, format!("{name}: not found")),
+            }
+            continue;
+        }
+
+        // A readonly variable keeps its value and attributes for good
+        if readonly(state, name) && (value.is_some() || on.integer || off.integer || off.readonly) {
+            status = error::report(state, ShellError::Readonly(name.to_string()));
+            continue;
+        }
+        if value.is_some() && RESTRICTED_VARS.contains(&name) && restricted(state, &format!("set {name}")) {
+            status = 1;
+            continue;
+        }
+
+        let mut attributes = state.attributes.get(name).copied().unwrap_or_default();
+        attributes.integer = (attributes.integer || on.integer) && !off.integer;
+        state.attributes.insert(name.to_string(), attributes);
+        if let Some(value) = value
+            && let Err(e) = assign(state, name, value) {
+            status = error::report(state, e);
+            continue;
+        }
+        attributes.readonly |= on.readonly;
+        match attributes == Attributes::default() {
+            true => state.attributes.remove(name),
+            false => state.attributes.insert(name.to_string(), attributes),
+        };
+
+        if export && let Some(value) = state.vars.remove(name) {
+            platform::set_env(name, value);
+        }
+        if unexport && let Ok(value) = env::var(name) {
+            platform::remove_env(name);
+            state.vars.insert(name.to_string(), value);
+        }
+    }
+    status
+}
+
+///
+/// Builtin jobs: lists the job table
+///
+/// Inputs:
+///   Shell state holding the jobs
+///   Arguments after `jobs`: `-l` adds pids, `-p` prints only process group
+///   ids. Finished jobs are shown once and then dropped
+///
+fn jobs<'a>(state: &mut State, args: impl Iterator<Item=&'a str>) -> i32 {
+    let (mut long, mut pids_only) = (false, false);
+    for arg in args {
+        match arg {
+            "-l" => long = true,
+            "-p" => pids_only = true,
+            _ => {
+                error::builtin("jobs", format!("{arg}: invalid option"));
+                return error::usage("jobs", "usage: jobs [-lp]");
+            }
+        }
+    }
+
+    state.jobs.reap();
+    let mut finished = Vec::new();
+    for job in state.jobs.iter() {
+        if pids_only {
+            println!("{}", job.pgid);
+        } else {
+            let pids = match long {
+                true => job.pids().map(|pid| format!("{pid} ")).collect(),
+                false => String::new(),
+            };
+            let amp  = if job.state == JobState::Running { " &" } else { "" };
+            println!(
+                "[{}]{}  {pids}{:<24}{}{amp}",
+                job.id, state.jobs.marker(job.id), job.state_label(), job.command
+            );
+        }
+
+        if matches!(job.state, JobState::Done(_)) {
+            finished.push(job.id);
+        }
+    }
+
+    for id in finished {
+        state.jobs.remove(id);
+    }
+    for id in state.jobs.iter().map(|job| job.id).collect::<Vec<_>>() {
+        state.jobs.seen(id);
+    }
+    0
+}
+
+///
+/// Reports jobs that finished or stopped since the last prompt, the way
+/// `jobs` shows them, and drops the finished ones from the table
+///
+pub fn notify_jobs(state: &mut State) {
+    state.jobs.reap();
+    for id in state.jobs.unreported() {
+        let marker = state.jobs.marker(id);
+        let Some(job) = state.jobs.get(id) else {
+            continue;
+        };
+        println!("[{id}]{marker}  {:<24}{}", job.state_label(), job.command);
+
+        if matches!(job.state, JobState::Done(_)) {
+            state.jobs.remove(id);
+        }
+    }
+}
+
+///
+/// Status of a foreground job once the shell has the terminal back
+///
+/// Inputs:
+///   Shell state holding the jobs
+///   Id of the job that was waited on
+///   State the wait left it in
+///
+/// Output:
+///   Exit status of the job, or 128 + SIGTSTP if it stopped, which is
+///   reported the way `jobs` would show it
+///
+pub fn foreground_status(state: &mut State, id: usize, job_state: JobState) -> i32 {
+    match job_state {
+        JobState::Done(code) => {
+            // Leave the line the echoed ^C is on
+            if code == 128 + libc::SIGINT && jobs::interactive() {
+                println!();
+            }
+            code
+        },
+        _ => {
+            let marker = state.jobs.marker(id);
+            if let Some(job) = state.jobs.get(id) {
+                println!("\n[{id}]{marker}  {:<24}{}", job.state_label(), job.command);
+            }
+            state.jobs.seen(id);
+            128 + libc::SIGTSTP
+        }
+    }
+}
+
+///
+/// Builtin alias: defines or shows aliases
+///
+/// Inputs:
+///   Shell state holding the aliases
+///   Arguments: `name=value` defines, `name` shows one. With none it lists
+///   all of them
+///
+/// Output:
+///   Exit status, 1 if a name shown has no alias
+///
+fn alias<'a>(state: &mut State, args: impl Iterator<Item = &'a str>) -> i32 {
+    // Quoted so the listing can be read back in
+    let show = |name: &str, value: &str| println!("alias {name}='{}'", value.replace('\'', "'\\''"));
+
+    let mut args = args.peekable();
+    if args.peek().is_none() {
+        let mut names: Vec<_> = state.aliases.keys().collect();
+        names.sort();
+        for name in names {
+            show(name, &state.aliases[name]);
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) => {
+                state.aliases.insert(name.to_string(), value.to_string());
+            },
+            None => match state.aliases.get(arg) {
+                Some(value) => show(arg, value),
+                None => {
+                    status = error::builtin("alias", format!("{arg}: not found"));
+                }
+            },
+        }
+    }
+    status
+}
+
+///
+/// Builtin unalias: removes aliases, or all of them with -a
+///
+fn unalias<'a>(state: &mut State, args: impl Iterator<Item = &'a str>) -> i32 {
+    let mut status = 0;
+    for arg in args {
+        if arg == "-a" {
+            state.aliases.clear();
+        } else if state.aliases.remove(arg).is_none() {
+            status = error::builtin("unalias", format!("{arg}: not found"));
+        }
+    }
+    status
+}
+
+///
+/// Prints the directory stack as dirs shows it: the current directory,
+/// then the saved ones from most recently pushed
+///
+fn print_dirs(state: &State) {
+    let cwd = env::current_dir().unwrap_or_default();
+    let dirs: Vec<String> = std::iter::once(&cwd)
+        .chain(state.dir_stack.iter().rev())
+        .map(|dir| prompt::tilde(dir))
+        .collect();
+    println!("{}", dirs.join(" "));
+}
+
+///
+/// Builtin dirs: shows the directory stack, or clears it with -c
+///
+fn dirs<'a>(state: &mut State, mut args: impl Iterator<Item = &'a str>) -> i32 {
+    match args.next() {
+        None => print_dirs(state),
+        Some("-c") => state.dir_stack.clear(),
+        Some(other) => return error::usage("dirs", format!("{other}: invalid option")),
+    }
+    0
+}
+
+///
+/// Builtin pushd: saves the current directory and changes to another
+///
+/// Inputs:
+///   Shell state holding the directory stack
+///   Arguments: the directory to change to, resolved like cd. Without one
+///   the current directory swaps with the most recently saved
+///
+/// Output:
+///   Exit status
+///
+fn pushd(state: &mut State, args: &[String]) -> i32 {
+    let Ok(cwd) = env::current_dir() else {
+        return error::builtin("pushd", "cannot determine current directory");
+    };
+
+    let target = if args.is_empty() {
+        match state.dir_stack.pop() {
+            Some(dir) => dir,
+            None => return error::builtin("pushd", "no other directory"),
+        }
+    } else {
+        match resolve_cd(state, args) {
+            Ok((dir, _, _)) => dir,
+            Err(e) => return error::builtin("pushd", e),
+        }
+    };
+
+    if change_dir("pushd", &target, false) != 0 {
+        // A swap that failed leaves the stack as it was
+        if args.is_empty() {
+            state.dir_stack.push(target);
+        }
+        return 1;
+    }
+
+    state.dir_stack.push(cwd);
+    print_dirs(state);
+    0
+}
+
+///
+/// Builtin popd: changes back to the most recently saved directory
+///
+fn popd(state: &mut State) -> i32 {
+    let Some(dir) = state.dir_stack.pop() else {
+        return error::builtin("popd", "directory stack empty");
+    };
+
+    if change_dir("popd", &dir, false) != 0 {
+        state.dir_stack.push(dir);
+        return 1;
+    }
+    print_dirs(state);
+    0
+}
+
+///
+/// Writes what a builtin prints through `$PAGER`, `less` by default, when
+/// there is more of it than fits on the terminal
+///
+/// It goes straight to stdout instead when that is not a terminal, under
+/// `set -o nopager`, or when the pager cannot be started
+///
+fn page(state: &mut State, text: &str) {
+    // SAFETY: isatty has no memory safety requirements
+    let terminal = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    let (_, rows) = editor::terminal_size();
+    let pager = match lookup(state, "PAGER") {
+        Some(pager) => command_words(state, &pager),
+        None => vec!["less".to_string()],
+    };
+    let long = text.lines().count() >= rows;
+
+    if terminal && long && !state.options.nopager && let Some((program, args)) = pager.split_first() {
+        io::stdout().flush().ok();
+        let mut command = process::Command::new(program);
+        command.args(args).stdin(process::Stdio::piped());
+        // SAFETY: reset_for_child only changes signal dispositions, which
+        // is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                signals::reset_for_child();
+                Ok(())
+            });
+        }
+        if let Ok(mut child) = command.spawn() {
+            // The pager may be quit before reading it all
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            let _ = child.wait();
+            return;
+        }
+    }
+    print!("{text}");
+}
+
+///
+/// Builtin history: lists entered lines, clears them with -c, adds one
+/// with -s, or lists those matching a pattern with `search`
+///
+/// Inputs:
+///   Shell state holding the history
+///   Arguments: -c; -s and words to join into an entry; `search` and a
+///   pattern, matched anywhere in an entry; or a count to list only the
+///   newest n entries
+///
+/// Output:
+///   Exit status, 2 for bad usage
+///
+fn history<'a>(state: &mut State, mut args: impl Iterator<Item = &'a str>) -> i32 {
+    let entries = state.history.entries();
+    let shown: Vec<usize> = match args.next() {
+        None => (0..entries.len()).collect(),
+        Some("-c") => {
+            state.history.clear();
+            return 0;
+        },
+        Some("-s") => {
+            let line = args.collect::<Vec<_>>().join(" ");
+            let size = history::max_size(lookup(state, "HISTSIZE").as_deref());
+            state.history.add(&line, &history::Filter::default(), size);
+            return 0;
+        },
+        Some("search") => {
+            let words: Vec<&str> = args.collect();
+            if words.is_empty() {
+                return error::usage("history", "usage: history search pattern");
+            }
+            let pattern = pattern::Pattern::new(&format!("*{}*", words.join(" ")));
+            (0..entries.len()).filter(|&i| pattern.matches(&entries[i])).collect()
+        },
+        Some(count) => match count.parse::<usize>() {
+            Ok(n) => (entries.len() - n.min(entries.len())..entries.len()).collect(),
+            Err(_) => return error::usage("history", format!("{count}: numeric argument required")),
+        },
+    };
+
+    // Like bash, `$HISTTIMEFORMAT` shows when each entry was added
+    let format = lookup(state, "HISTTIMEFORMAT");
+    let times = state.history.times();
+    let mut text = String::new();
+    for i in shown {
+        let time = match (&format, times[i]) {
+            (Some(format), time) if time > 0 => history::format_time(time, format),
+            _ => String::new(),
+        };
+        text.push_str(&format!("{:>5}  {time}{}\n", i + 1, entries[i]));
+    }
+    page(state, &text);
+    0
+}
+
+///
+/// Builtin fg: continues a job in the foreground and waits for it
+///
+/// Inputs:
+///   Shell state holding the jobs
+///   Job spec, the current job if None
+///
+/// Output:
+///   Exit status of the job, or 128 + SIGTSTP if it stopped again
+///
+fn fg(state: &mut State, spec: Option<&str>) -> i32 {
+    state.jobs.reap();
+    let id = match state.jobs.resolve(spec) {
+        Ok(id) => id,
+        Err(e) => return error::builtin("fg", e),
+    };
+
+    let Some(job) = state.jobs.get(id) else {
+        return 1;
+    };
+    println!("{}", job.command);
+
+    let resumed = state.jobs.wait_in_foreground(id, true);
+
+    match resumed {
+        Ok(job_state) => foreground_status(state, id, job_state),
+        Err(e) => error::builtin("fg", error::cause(&e)),
+    }
+}
+
+///
+/// Builtin bg: continues stopped jobs in the background
+///
+/// Inputs:
+///   Shell state holding the jobs
+///   Job specs, the current job if there are none
+///
+fn bg<'a>(state: &mut State, args: impl Iterator<Item=&'a str>) -> i32 {
+    state.jobs.reap();
+    let specs: Vec<&str> = args.collect();
+    let specs: Vec<Option<&str>> = if specs.is_empty() {
+        vec![None]
+    } else {
+        specs.into_iter().map(Some).collect()
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let id = match state.jobs.resolve(spec) {
+            Ok(id) => id,
+            Err(e) => {
+                status = error::builtin("bg", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = state.jobs.resume(id) {
+            status = error::builtin("bg", error::cause(&e));
+            continue;
+        }
+
+        let marker = state.jobs.marker(id);
+        if let Some(job) = state.jobs.get(id) {
+            println!("[{id}]{marker} {} &", job.command);
+        }
+    }
+
+    status
+}
+
+///
+/// Builtin wait: waits for background jobs to finish
+///
+/// Inputs:
+///   Shell state holding the jobs
+///   Job specs such as `%1`, or pids; every job if there are none
+///
+/// Output:
+///   Exit status of the last job named, 127 if it is not a job of this
+///   shell, or 0 when waiting for every job
+///
+fn wait<'a>(state: &mut State, args: impl Iterator<Item=&'a str>) -> i32 {
+    state.jobs.reap();
+    let specs: Vec<&str> = args.collect();
+    if specs.is_empty() {
+        let ids: Vec<usize> = state.jobs.iter().map(|job| job.id).collect();
+        for id in ids {
+            if let Err(e) = state.jobs.wait(id) {
+                error::builtin("wait", error::cause(&e));
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for spec in specs {
+        let id = if spec.starts_with('%') {
+            state.jobs.resolve(Some(spec))
+        } else {
+            match spec.parse::<libc::pid_t>() {
+                Ok(pid) => state.jobs.with_pid(pid)
+                    .ok_or_else(|| format!("pid {pid} is not a child of this shell")),
+                Err(_) => Err(format!("`{spec}': not a pid or valid job spec")),
+            }
+        };
+        let id = match id {
+            Ok(id) => id,
+            Err(e) => {
+                error::builtin("wait", e);
+                status = 127;
+                continue;
+            }
+        };
+
+        status = match state.jobs.wait(id) {
+            Ok(JobState::Done(code)) => code,
+            Ok(_) => 128 + libc::SIGTSTP,
+            Err(e) => {
+                error::builtin("wait", error::cause(&e));
+                127
+            }
+        };
+    }
+    status
+}
+
+///
+/// Finds the file `source` reads: a name with a `/` as it is, otherwise
+/// the first readable file of that name in `$PATH`, then in the current
+/// directory
+///
+fn source_path(state: &State, name: &str) -> PathBuf {
+    if platform::is_path(name) {
+        return PathBuf::from(name);
+    }
+    env::split_paths(&lookup(state, "PATH").unwrap_or_default())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+///
+/// Runs the `source` and `.` builtins, reading a file's commands into the
+/// current shell, so its variables, aliases, functions and directory
+/// changes stay once it finishes
+///
+/// Inputs:
+///   Shell state the file runs in
+///   Arguments, the builtin's name first, then the file and any
+///   arguments, which are `$1` onwards while it runs
+///
+/// Output:
+///   Continue with the status of the file's last command once it finishes
+///   or returns, Break if it exited
+///
+fn source(state: &mut State, argv: &[String]) -> ControlFlow<i32, i32> {
+    let Some(name) = argv.get(1) else {
+        return ControlFlow::Continue(error::usage(&argv[0], format!("usage: {} filename [arguments]", argv[0])));
+    };
+    if name.contains('/') && restricted(state, "specify '/' in names to source") {
+        return ControlFlow::Continue(1);
+    }
+
+    let path = source_path(state, name);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            ShellError::Spawn { command: format!("{}: {name}", argv[0]), error }.print();
+            return ControlFlow::Continue(1);
+        }
+    };
+
+    // Without arguments the file sees the caller's own
+    let saved = (argv.len() > 2).then(|| std::mem::replace(&mut state.positional, argv[2..].to_vec()));
+    state.last_status = 0;
+    state.function_depth += 1;
+    let flow = run_lines(state, &contents);
+    state.function_depth -= 1;
+    if let Some(saved) = saved {
+        state.positional = saved;
+    }
+
+    // `return` stops only the file
+    match flow {
+        ControlFlow::Break(code) if state.returning => {
+            state.returning = false;
+            ControlFlow::Continue(code)
+        },
+        ControlFlow::Break(code) => ControlFlow::Break(code),
+        ControlFlow::Continue(()) => ControlFlow::Continue(state.last_status),
+    }
+}
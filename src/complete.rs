@@ -0,0 +1,397 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::*;
+use std::rc::Rc;
+
+use crate::platform;
+
+///
+/// Source of candidates for the word under the cursor
+///
+pub trait Completer {
+    ///
+    /// Lists every possible completion of a word
+    ///
+    /// Input:
+    ///   Word typed so far, with backslash escapes removed
+    ///
+    /// Output:
+    ///   Full replacements for the word, each starting with it. Directories
+    ///   end in `/`
+    ///
+    fn complete(&self, word: &str) -> Vec<String>;
+}
+
+///
+/// Completes file and directory paths, relative or absolute, with `~`
+/// standing for the home directory
+///
+pub struct Paths {
+    /// Offer only directories, e.g. for `cd`
+    pub dirs_only: bool,
+    /// Offer only directories and executable files, for a command name
+    pub executables: bool,
+}
+
+impl Completer for Paths {
+    fn complete(&self, word: &str) -> Vec<String> {
+        // `src/le` lists `src/` for names starting with `le`
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => word.split_at(i + 1),
+            None => ("", word),
+        };
+        let search = match dir {
+            "" => PathBuf::from("."),
+            dir => PathBuf::from(shellexpand::tilde(dir).as_ref()),
+        };
+
+        let Ok(entries) = fs::read_dir(search) else {
+            return Vec::new();
+        };
+
+        entries.flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                // Dotfiles only when asked for with a leading dot
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                    return None;
+                }
+
+                // Follows symlinks, so a link to a directory completes as one
+                let meta = fs::metadata(entry.path()).ok()?;
+                if meta.is_dir() {
+                    Some(format!("{dir}{name}/"))
+                } else if self.dirs_only || (self.executables && !platform::is_runnable(&meta)) {
+                    None
+                } else {
+                    Some(format!("{dir}{name}"))
+                }
+            })
+            .collect()
+    }
+}
+
+///
+/// Completes a command name from a list of every command there is
+///
+pub struct Commands<'a> {
+    /// Names the shell runs itself, such as builtins, and the executables
+    /// on `$PATH`
+    pub names: &'a [String],
+}
+
+impl Completer for Commands<'_> {
+    fn complete(&self, word: &str) -> Vec<String> {
+        self.names.iter()
+            .filter(|name| name.starts_with(word))
+            .cloned()
+            .collect()
+    }
+}
+
+///
+/// Completes the name of a bookmark, for `j` and `bookmark`
+///
+pub struct Bookmarks;
+
+impl Completer for Bookmarks {
+    fn complete(&self, word: &str) -> Vec<String> {
+        crate::jump::bookmarks().into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with(word))
+            .collect()
+    }
+}
+
+///
+/// Completes `%N` job specs from the jobs table, for fg, bg and kill, or
+/// paths when there are no jobs
+///
+pub struct JobSpecs {
+    /// Spec of each job with its command, shared with the Completion that
+    /// is given them before each line is read
+    jobs: Rc<RefCell<Vec<(String, String)>>>,
+}
+
+impl Completer for JobSpecs {
+    fn complete(&self, word: &str) -> Vec<String> {
+        let jobs = self.jobs.borrow();
+        if jobs.is_empty() {
+            return Paths { dirs_only: false, executables: false }.complete(word);
+        }
+        jobs.iter()
+            .map(|(spec, _)| spec.clone())
+            .filter(|spec| spec.starts_with(word))
+            .collect()
+    }
+}
+
+/// Options of the builtins, completed for a word starting with `-`
+const OPTIONS: &[(&str, &[(&str, &str)])] = &[
+    ("cd", &[("-L", "follow symbolic links"), ("-P", "use the physical directory")]),
+    ("declare", &[
+        ("-i", "make values integers"), ("-r", "make readonly"), ("-x", "export"), ("-p", "print the variables"),
+    ]),
+    ("dirs", &[("-c", "clear the directory stack")]),
+    ("echo", &[("-n", "no trailing newline"), ("-e", "interpret backslash escapes"), ("-E", "print backslashes as they are")]),
+    ("enable", &[("-n", "switch builtins off")]),
+    ("history", &[("-c", "clear the history"), ("-s", "add the arguments as an entry")]),
+    ("hook", &[("-d", "remove functions from an event")]),
+    ("jobs", &[("-l", "list process ids too"), ("-p", "list only process ids")]),
+    ("kill", &[("-l", "list signal names"), ("-s", "signal to send")]),
+    ("read", &[
+        ("-r", "keep backslashes"), ("-s", "do not echo input"), ("-p", "prompt to show"),
+        ("-n", "read only this many characters"), ("-N", "read exactly this many characters"),
+        ("-t", "give up after this many seconds"),
+    ]),
+    ("shopt", &[
+        ("-s", "switch options on"), ("-u", "switch options off"), ("-q", "only set the status"),
+        ("-p", "print as shopt commands"),
+    ]),
+    ("trap", &[("-p", "print the traps set")]),
+    ("type", &[("-t", "print only the kind of command")]),
+    ("umask", &[("-S", "print the mask symbolically"), ("-p", "print as a reusable command")]),
+    ("unalias", &[("-a", "remove every alias")]),
+    ("unset", &[("-v", "remove only variables"), ("-f", "remove only functions")]),
+];
+
+fn options(command: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    OPTIONS.iter().find(|(name, _)| *name == command).map(|(_, options)| *options)
+}
+
+///
+/// Completion engine the line editor asks on Tab
+///
+/// The first word of a command completes as a command name and any later
+/// word as a path, unless the command has registered its own completer.
+/// A word starting with `-` after a builtin completes as one of its options
+///
+pub struct Completion {
+    /// Names completed as commands
+    pub names: Vec<String>,
+    /// Short descriptions of command names, shown in the completion menu
+    pub descriptions: HashMap<String, String>,
+    /// `%N` spec of each job with its command, for JobSpecs
+    jobs: Rc<RefCell<Vec<(String, String)>>>,
+    by_command: HashMap<String, Box<dyn Completer>>,
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        let mut completion = Completion {
+            names: Vec::new(),
+            descriptions: HashMap::new(),
+            jobs: Rc::default(),
+            by_command: HashMap::new(),
+        };
+        for command in ["cd", "pushd"] {
+            completion.register(command, Box::new(Paths { dirs_only: true, executables: false }));
+        }
+        for command in ["j", "bookmark"] {
+            completion.register(command, Box::new(Bookmarks));
+        }
+        for command in ["fg", "bg", "kill"] {
+            completion.register(command, Box::new(JobSpecs { jobs: Rc::clone(&completion.jobs) }));
+        }
+        completion
+    }
+}
+
+impl Completion {
+    ///
+    /// Makes a completer responsible for the arguments of a command
+    ///
+    pub fn register(&mut self, command: &str, completer: Box<dyn Completer>) {
+        self.by_command.insert(command.to_string(), completer);
+    }
+
+    ///
+    /// Gives the jobs job specs complete to, as `%N` and the command
+    ///
+    pub fn set_jobs(&mut self, jobs: Vec<(String, String)>) {
+        *self.jobs.borrow_mut() = jobs;
+    }
+
+    ///
+    /// Finds completions for the word ending at the cursor
+    ///
+    /// Input:
+    ///   Line up to the cursor
+    ///
+    /// Output:
+    ///   Character index where the word starts, and its completions, sorted
+    ///   and escaped so they can replace the word as typed
+    ///
+    pub fn complete(&self, line: &[char]) -> (usize, Vec<String>) {
+        let start = word_start(line, line.len());
+        let word = unescape(&line[start..]);
+        let before: String = line[..start].iter().collect();
+        let command = command_of(&before);
+
+        if let Some(options) = command.and_then(options)
+            && word.starts_with('-') {
+            let found = options.iter()
+                .map(|(option, _)| option.to_string())
+                .filter(|option| option.starts_with(&word))
+                .collect();
+            return (start, found);
+        }
+
+        let mut found = match command {
+            None if word.contains('/') => Paths { dirs_only: false, executables: true }.complete(&word),
+            None => Commands { names: &self.names }.complete(&word),
+            Some(command) => match self.by_command.get(command) {
+                Some(completer) => completer.complete(&word),
+                None => Paths { dirs_only: false, executables: false }.complete(&word),
+            },
+        };
+
+        found.sort_unstable();
+        found.dedup();
+        (start, found.iter().map(|c| escape(c)).collect())
+    }
+
+    ///
+    /// Short description of a completion, for the menu
+    ///
+    /// Inputs:
+    ///   Line before the word being completed
+    ///   Completion, escaped as complete gave it
+    ///
+    /// Output:
+    ///   What the command name, job or option is, if known
+    ///
+    pub fn describe(&self, before: &[char], candidate: &str) -> Option<String> {
+        let before: String = before.iter().collect();
+        let candidate = unescape(&candidate.chars().collect::<Vec<_>>());
+        match command_of(&before) {
+            None => self.descriptions.get(&candidate).cloned(),
+            Some("fg" | "bg" | "kill") if candidate.starts_with('%') => self.jobs.borrow().iter()
+                .find(|(spec, _)| *spec == candidate)
+                .map(|(_, command)| command.clone()),
+            Some(command) => options(command)?.iter()
+                .find(|(option, _)| *option == candidate)
+                .map(|(_, description)| description.to_string()),
+        }
+    }
+}
+
+///
+/// Command the word after a line's text belongs to: the first word after
+/// the last operator, or None if the word is itself the command
+///
+fn command_of(before: &str) -> Option<&str> {
+    before.rsplit(['|', ';', '&', '(']).next().unwrap_or("").split_whitespace().next()
+}
+
+///
+/// Index where the word ending at pos begins; a backslash-escaped blank
+/// does not end a word
+///
+fn word_start(line: &[char], pos: usize) -> usize {
+    let mut start = pos;
+    while start > 0 {
+        let c = line[start - 1];
+        let escaped = start > 1 && line[start - 2] == '\\';
+        if (c.is_whitespace() && !escaped) || matches!(c, '|' | ';' | '&' | '<' | '>' | '(') {
+            break;
+        }
+        start -= 1;
+    }
+    start
+}
+
+fn unescape(word: &[char]) -> String {
+    let mut out = String::with_capacity(word.len());
+    let mut chars = word.iter();
+    while let Some(&c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+///
+/// Backslash-escapes characters the lexer would otherwise treat specially
+///
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        // A leading `~` is left to expand to the home directory
+        if c.is_whitespace() || ("\\'\"$`|&;<>()*?[#~".contains(c) && !(c == '~' && out.is_empty())) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+///
+/// Edit distance between two words, counting a swap of two neighbouring
+/// characters as one edit so `gti` is one away from `git`
+///
+fn distance(a: &[char], b: &[char]) -> usize {
+    // rows[i][j] is the distance between the first i of a and first j of b
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    rows[0] = (0..=b.len()).collect();
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+///
+/// Picks the candidate a mistyped word most likely stands for
+///
+/// Inputs:
+///   Word as typed
+///   Names it could have been meant as
+///
+/// Output:
+///   The nearest candidate by edit distance, alphabetically first among
+///   equals, or None if none is within one edit per three characters.
+///   One that changes every character, as `.` would for `f`, is no match
+///
+pub fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let word: Vec<char> = word.chars().collect();
+    let limit = (word.len() / 3).max(1);
+
+    candidates
+        .filter(|candidate| candidate.chars().count().abs_diff(word.len()) <= limit)
+        .map(|candidate| (distance(&word, &candidate.chars().collect::<Vec<_>>()), candidate))
+        .filter(|&(distance, _)| distance <= limit && distance < word.len())
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+///
+/// Longest prefix every candidate starts with
+///
+pub fn common_prefix(candidates: &[String]) -> &str {
+    let Some(first) = candidates.first() else {
+        return "";
+    };
+
+    let mut len = first.len();
+    for other in &candidates[1..] {
+        len = first.char_indices()
+            .zip(other.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(other.len()), |((i, _), _)| i.min(len));
+    }
+    &first[..len]
+}
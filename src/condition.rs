@@ -0,0 +1,213 @@
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use crate::error;
+
+/// Operators taking one argument
+const UNARY: &[&str] = &[
+    "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-p", "-r", "-s", "-S", "-t", "-u", "-w", "-x", "-z",
+];
+
+/// Operators between two arguments
+const BINARY: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+///
+/// Reads and evaluates a `test` expression, one argument at a time
+///
+struct Parser<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self, offset: usize) -> Option<&str> {
+        self.args.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<&str, String> {
+        let arg = self.args.get(self.pos).ok_or("argument expected")?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    ///
+    /// expr: and-expressions joined by `-o`
+    ///
+    fn or(&mut self) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
+
+    ///
+    /// and: negations joined by `-a`
+    ///
+    fn and(&mut self) -> Result<bool, String> {
+        let mut value = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            value &= self.not()?;
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        // `! = x` compares `!` rather than negating
+        if self.peek(0) == Some("!")
+            && self.peek(1).is_some_and(|next| !BINARY.contains(&next)) {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        if let Some(op) = self.peek(1)
+            && BINARY.contains(&op)
+            && self.peek(2).is_some() {
+            let left = self.next()?.to_string();
+            let op = self.next()?.to_string();
+            let right = self.next()?;
+            return binary(&left, &op, right);
+        }
+
+        let arg = self.next()?.to_string();
+        if arg == "(" && self.peek(0).is_some() {
+            let value = self.or()?;
+            return match self.next() {
+                Ok(")") => Ok(value),
+                _ => Err("`)' expected".to_string()),
+            };
+        }
+        if UNARY.contains(&arg.as_str()) && let Some(operand) = self.peek(0) {
+            let operand = operand.to_string();
+            self.pos += 1;
+            return unary(&arg, &operand);
+        }
+        // A lone word is true unless empty
+        Ok(!arg.is_empty())
+    }
+}
+
+///
+/// Whether the shell may read, write or execute a file, as access(2) says
+///
+fn access(path: &str, mode: i32) -> bool {
+    let Ok(path) = CString::new(Path::new(path).as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: path is a valid NUL-terminated string that outlives the call
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+fn unary(op: &str, operand: &str) -> Result<bool, String> {
+    let meta = || fs::metadata(operand).ok();
+    let has = |test: fn(&Metadata) -> bool| meta().is_some_and(|meta| test(&meta));
+    Ok(match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" => meta().is_some(),
+        "-f" => has(|meta| meta.is_file()),
+        "-d" => has(|meta| meta.is_dir()),
+        "-s" => has(|meta| meta.len() > 0),
+        "-b" => has(|meta| meta.file_type().is_block_device()),
+        "-c" => has(|meta| meta.file_type().is_char_device()),
+        "-p" => has(|meta| meta.file_type().is_fifo()),
+        "-S" => has(|meta| meta.file_type().is_socket()),
+        "-u" => has(|meta| meta.mode() & libc::S_ISUID != 0),
+        "-g" => has(|meta| meta.mode() & libc::S_ISGID != 0),
+        "-k" => has(|meta| meta.mode() & libc::S_ISVTX != 0),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|meta| meta.file_type().is_symlink()),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        // SAFETY: isatty has no memory safety requirements
+        _ => unsafe { libc::isatty(integer(operand)? as i32) == 1 },
+    })
+}
+
+fn integer(text: &str) -> Result<i64, String> {
+    text.trim().parse().map_err(|_| format!("{text}: integer expression expected"))
+}
+
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, _) => left.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (_, right) => right.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+            _ => false,
+        },
+        _ => {
+            let (left, right) = (integer(left)?, integer(right)?);
+            match op {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            }
+        }
+    })
+}
+
+///
+/// Builtins test and `[`: evaluate a conditional expression
+///
+/// `-e`, `-f`, `-d`, `-s`, `-r`, `-w`, `-x`, `-L` and the like test a
+/// file, `-z`/`-n` whether a string is empty or not, `=`/`!=` compare
+/// strings and `-eq`, `-ne`, `-lt`, `-le`, `-gt`, `-ge` integers, and
+/// `-nt`/`-ot` compare modification times. `!` negates, `-a` and `-o`
+/// join, and `(`/`)` group. A single word is true if it is not empty
+///
+/// Inputs:
+///   Name it was run as, `[` needing a closing `]` as its last argument
+///   Arguments after the name
+///
+/// Output:
+///   0 if the expression is true, 1 if it is false, 2 if it is malformed
+///
+pub fn builtin_test(name: &str, args: &[String]) -> i32 {
+    let args = if name == "[" {
+        match args.split_last() {
+            Some((last, rest)) if last == "]" => rest,
+            _ => return error::usage("[", "missing `]'"),
+        }
+    } else {
+        args
+    };
+    if args.is_empty() {
+        return 1;
+    }
+
+    let mut parser = Parser { args, pos: 0 };
+    let value = parser.or().and_then(|value| match parser.peek(0) {
+        None => Ok(value),
+        Some(extra) => Err(format!("{extra}: unexpected argument")),
+    });
+    match value {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => error::usage(name, e),
+    }
+}
@@ -0,0 +1,1199 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::complete::{self, Completion};
+use crate::highlight::highlight;
+use crate::prompt::Prompt;
+use crate::signals;
+use crate::theme::{paint, Theme};
+
+///
+/// Key read from the terminal, with escape sequences already decoded
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    /// Control combination, e.g. `Ctrl('a')` for Ctrl-A
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Esc pressed on its own rather than starting a sequence
+    Escape,
+    /// Alt (Meta) combination, sent as Esc and then the key
+    Alt(char),
+    AltBackspace,
+    /// Shift-Tab
+    BackTab,
+    /// Anything the editor has no binding for
+    Unknown,
+}
+
+///
+/// Terminal settings in effect before raw mode, put back when dropped
+///
+pub struct RawMode {
+    saved: libc::termios,
+}
+
+impl RawMode {
+    ///
+    /// Turns off line buffering, echo and signal keys on the terminal so
+    /// every key reaches the editor as it is pressed
+    ///
+    fn enable() -> io::Result<RawMode> {
+        RawMode::without(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN)
+    }
+
+    ///
+    /// Turns off some of the terminal's local modes, such as just ECHO to
+    /// read a password
+    ///
+    pub fn without(flags: libc::tcflag_t) -> io::Result<RawMode> {
+        // SAFETY: tcgetattr fills the zeroed struct before it is read
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = saved;
+        raw.c_lflag &= !flags;
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        // SAFETY: raw is a valid termios copied from the current settings
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: saved holds the settings tcgetattr returned
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.saved) };
+    }
+}
+
+///
+/// Reads one byte from the terminal, or whatever stdin is, without
+/// buffering anything past it
+///
+/// Output:
+///   The byte, None at end of input, or Interrupted if a Ctrl-C signal
+///   arrived while waiting (other signals just restart the read)
+///
+pub fn read_byte() -> io::Result<Option<u8>> {
+    loop {
+        let mut byte = 0u8;
+        // SAFETY: reads at most one byte into a local we own
+        let n = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        match n {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted && !signals::take_interrupt() {
+                    continue;
+                }
+                return Err(err);
+            }
+        }
+    }
+}
+
+///
+/// Splits a prompt into the lines above the one being edited, if it has
+/// several, and the last line, which is redrawn along with the line
+///
+fn split_prompt(prompt: &str) -> (Option<&str>, &str) {
+    match prompt.rsplit_once('\n') {
+        Some((above, last)) => (Some(above), last),
+        None => (None, prompt),
+    }
+}
+
+///
+/// Reads and decodes the next key press
+///
+/// Output:
+///   The key, or None at end of input
+///
+fn read_key() -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte()? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => read_escape()?,
+        0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+        // Ctrl-_, which terminals also send for Ctrl-/
+        0x1f => Key::Ctrl('_'),
+        0x00..=0x1f => Key::Unknown,
+        _ => read_utf8(byte)?.map_or(Key::Unknown, Key::Char),
+    };
+    Ok(Some(key))
+}
+
+///
+/// Decodes the rest of an escape sequence such as `ESC [ A` (up arrow)
+///
+fn read_escape() -> io::Result<Key> {
+    if !pending()? {
+        return Ok(Key::Escape);
+    }
+    let Some(kind) = read_byte()? else {
+        return Ok(Key::Unknown);
+    };
+    if kind != b'[' && kind != b'O' {
+        return Ok(match kind {
+            b' '..=b'~' => Key::Alt(kind as char),
+            0x7f | 0x08 => Key::AltBackspace,
+            _ => Key::Unknown,
+        });
+    }
+
+    // Parameters like the `3` of `ESC [ 3 ~`, up to the final letter or `~`
+    let mut param = Vec::new();
+    let last = loop {
+        match read_byte()? {
+            Some(b @ b'0'..=b'9') | Some(b @ b';') => param.push(b),
+            Some(b) => break b,
+            None => return Ok(Key::Unknown),
+        }
+    };
+
+    Ok(match (last, param.as_slice()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', b"1") | (b'~', b"7") => Key::Home,
+        (b'F', _) | (b'~', b"4") | (b'~', b"8") => Key::End,
+        (b'~', b"3") => Key::Delete,
+        (b'Z', _) => Key::BackTab,
+        _ => Key::Unknown,
+    })
+}
+
+///
+/// Whether more input arrives within a moment, which tells a lone Esc
+/// from the start of a sequence the terminal sends all at once
+///
+fn pending() -> io::Result<bool> {
+    ready(ESCAPE_TIMEOUT_MS)
+}
+
+///
+/// Whether input arrives within timeout milliseconds
+///
+pub fn ready(timeout: i32) -> io::Result<bool> {
+    let mut poll = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    // SAFETY: poll only touches the one pollfd we own
+    match unsafe { libc::poll(&mut poll, 1, timeout) } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n > 0),
+    }
+}
+
+/// How long to wait after Esc for the rest of a sequence
+const ESCAPE_TIMEOUT_MS: i32 = 50;
+
+/// How often to look for slow prompt segments while waiting for a key
+const PROMPT_POLL_MS: i32 = 20;
+
+///
+/// Reads the continuation bytes of a UTF-8 character
+///
+/// Input:
+///   Its first byte, already read
+///
+/// Output:
+///   The character, or None if the bytes were not valid UTF-8
+///
+fn read_utf8(first: u8) -> io::Result<Option<char>> {
+    let len = match first.leading_ones() {
+        0 => 1,
+        n @ 2..=4 => n as usize,
+        _ => return Ok(None),
+    };
+
+    let mut bytes = vec![first];
+    while bytes.len() < len {
+        match read_byte()? {
+            Some(b) => bytes.push(b),
+            None => return Ok(None),
+        }
+    }
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()))
+}
+
+///
+/// Line being edited and where the cursor sits in it
+///
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    /// Cursor position, as an index into chars
+    pos: usize,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.pos = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.pos, c);
+        self.pos += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.pos > 0 {
+            self.pos -= 1;
+            self.chars.remove(self.pos);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.pos < self.chars.len() {
+            self.chars.remove(self.pos);
+        }
+    }
+
+    ///
+    /// Removes a range of the line, leaving the cursor where it started
+    ///
+    /// Output:
+    ///   The text removed
+    ///
+    fn cut(&mut self, range: Range<usize>) -> String {
+        self.pos = range.start;
+        self.chars.drain(range).collect()
+    }
+
+    ///
+    /// Inserts text at the cursor and moves past it
+    ///
+    /// Output:
+    ///   Where the text went in the line
+    ///
+    fn paste(&mut self, text: &str) -> Range<usize> {
+        let start = self.pos;
+        self.chars.splice(start..start, text.chars());
+        self.pos = start + text.chars().count();
+        start..self.pos
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot { chars: self.chars.clone(), pos: self.pos }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.chars = snapshot.chars;
+        self.pos = snapshot.pos;
+    }
+
+    ///
+    /// Start of the next word after the cursor, as vi's `w` moves
+    ///
+    fn next_word(&self) -> usize {
+        let mut end = self.pos;
+        while end < self.chars.len() && !self.chars[end].is_whitespace() {
+            end += 1;
+        }
+        while end < self.chars.len() && self.chars[end].is_whitespace() {
+            end += 1;
+        }
+        end
+    }
+
+    ///
+    /// Start of the word before the cursor, as vi's `b` moves
+    ///
+    fn previous_word(&self) -> usize {
+        let mut start = self.pos;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        start
+    }
+
+    ///
+    /// Start of the word before the cursor as Ctrl-W and Alt-Backspace cut
+    /// it, stopping at a `/` or other character outside the word
+    ///
+    /// Inputs:
+    ///   Characters besides letters and digits that count as part of a word
+    ///
+    fn kill_word_start(&self, wordchars: &str) -> usize {
+        let mut start = self.pos;
+        while start > 0 && !is_word_char(self.chars[start - 1], wordchars) {
+            start -= 1;
+        }
+        while start > 0 && is_word_char(self.chars[start - 1], wordchars) {
+            start -= 1;
+        }
+        start
+    }
+
+    ///
+    /// Last character of the word the cursor is in or the next, as vi's
+    /// `e` moves
+    ///
+    fn word_end(&self) -> usize {
+        let mut end = self.pos + 1;
+        while end < self.chars.len() && self.chars[end].is_whitespace() {
+            end += 1;
+        }
+        while end + 1 < self.chars.len() && !self.chars[end + 1].is_whitespace() {
+            end += 1;
+        }
+        end.min(self.chars.len().saturating_sub(1))
+    }
+
+    ///
+    /// Keeps the cursor on a character, as vi's normal mode does
+    ///
+    fn clamp(&mut self) {
+        self.pos = self.pos.min(self.chars.len().saturating_sub(1));
+    }
+}
+
+/// Characters besides letters and digits that are part of a word when
+/// `$WORDCHARS` is unset, as in zsh but without `/`
+pub const WORDCHARS: &str = "*?_-.[]~=&;!#$%^(){}<>";
+
+///
+/// Whether a character is part of a word for Ctrl-W and Alt-Backspace.
+/// A `/` never is, so they cut a path one component at a time
+///
+/// Inputs:
+///   Character to test
+///   Characters besides letters and digits that count as part of a word
+///
+fn is_word_char(c: char, wordchars: &str) -> bool {
+    c != '/' && (c.is_alphanumeric() || wordchars.contains(c))
+}
+
+///
+/// A state of the line to go back to
+///
+struct Snapshot {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+///
+/// Earlier and undone states of the line, for Ctrl-_ and Alt-/
+///
+#[derive(Default)]
+struct Edits {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The last key typed a character, so the next one joins its undo step
+    typing: bool,
+}
+
+impl Edits {
+    ///
+    /// Remembers the line as it was before a key, if the key changed it.
+    /// A run of typed characters is undone as one step, as in readline
+    ///
+    /// Inputs:
+    ///   Line before the key
+    ///   Line after it
+    ///   Whether the key typed a character into the line
+    ///
+    fn record(&mut self, before: Snapshot, line: &Line, typing: bool) {
+        if line.chars != before.chars {
+            if !(typing && self.typing) {
+                self.undo.push(before);
+            }
+            self.redo.clear();
+        }
+        self.typing = typing;
+    }
+
+    fn undo(&mut self, line: &mut Line) {
+        if let Some(before) = self.undo.pop() {
+            self.redo.push(line.snapshot());
+            line.restore(before);
+        }
+        self.typing = false;
+    }
+
+    fn redo(&mut self, line: &mut Line) {
+        if let Some(after) = self.redo.pop() {
+            self.undo.push(line.snapshot());
+            line.restore(after);
+        }
+        self.typing = false;
+    }
+}
+
+/// How many kills the kill ring holds before dropping the oldest
+const KILL_RING_SIZE: usize = 16;
+
+///
+/// Text cut with Ctrl-K, Ctrl-U, Ctrl-W and Alt-Backspace, newest last, for Ctrl-Y to
+/// put back. It is kept from one line to the next
+///
+#[derive(Default)]
+struct KillRing {
+    kills: Vec<String>,
+}
+
+impl KillRing {
+    ///
+    /// Adds killed text to the ring
+    ///
+    /// Inputs:
+    ///   Text killed, ignored if empty
+    ///   Whether the key before was a kill too, in which case the text
+    ///   joins the newest kill instead, so Ctrl-Y brings both back at once
+    ///   Whether the text came from after the cursor, joining the end of
+    ///   the newest kill rather than its start
+    ///
+    fn kill(&mut self, text: String, join: bool, forward: bool) {
+        if text.is_empty() {
+            return;
+        }
+        match self.kills.last_mut() {
+            Some(newest) if join && forward => newest.push_str(&text),
+            Some(newest) if join => newest.insert_str(0, &text),
+            _ => {
+                if self.kills.len() == KILL_RING_SIZE {
+                    self.kills.remove(0);
+                }
+                self.kills.push(text);
+            }
+        }
+    }
+
+    ///
+    /// A kill counting back from the newest, going round the ring
+    ///
+    fn get(&self, back: usize) -> Option<String> {
+        let len = self.kills.len();
+        (len > 0).then(|| self.kills[len - 1 - back % len].clone())
+    }
+}
+
+///
+/// What a key did in vi's normal mode
+///
+enum Normal {
+    /// Handled; the editor stays in normal mode
+    Stay,
+    /// Handled, and typing goes back into the line
+    Insert,
+    /// Not a normal mode command; handled like this key instead
+    Key(Key),
+}
+
+///
+/// Where a vi motion takes the cursor
+///
+/// Output:
+///   The new position, and whether an operator over the motion takes in
+///   the character there too, as it does for `e` and `$`, or None if the
+///   key is not a motion
+///
+fn motion(line: &Line, c: char) -> Option<(usize, bool)> {
+    let end = line.chars.len();
+    Some(match c {
+        'h' => (line.pos.saturating_sub(1), false),
+        'l' | ' ' => ((line.pos + 1).min(end), false),
+        '0' | '^' => (0, false),
+        '$' => (end, false),
+        'w' => (line.next_word(), false),
+        'b' => (line.previous_word(), false),
+        'e' => (line.word_end(), true),
+        _ => return None,
+    })
+}
+
+///
+/// Runs a key typed in vi's normal mode
+///
+/// Motions (`h l w b e 0 ^ $`) move the cursor; `x`/`X` delete a
+/// character; `d` and `c` followed by a motion delete over it, `c` then
+/// going back to insert, with `dd`/`cc` taking the whole line and `D`/`C`
+/// the rest of it; `i a I A` start inserting, and `k`/`j` step through
+/// history
+///
+fn vi_normal(line: &mut Line, c: char) -> io::Result<Normal> {
+    if let Some((pos, _)) = motion(line, c) {
+        line.pos = pos;
+        return Ok(Normal::Stay);
+    }
+
+    let end = line.chars.len();
+    match c {
+        'i' => return Ok(Normal::Insert),
+        'a' => {
+            line.pos = (line.pos + 1).min(end);
+            return Ok(Normal::Insert);
+        }
+        'I' => {
+            line.pos = 0;
+            return Ok(Normal::Insert);
+        }
+        'A' => {
+            line.pos = end;
+            return Ok(Normal::Insert);
+        }
+        'x' => line.delete(),
+        'X' => line.backspace(),
+        'D' => line.chars.truncate(line.pos),
+        'C' => {
+            line.chars.truncate(line.pos);
+            return Ok(Normal::Insert);
+        }
+        'k' => return Ok(Normal::Key(Key::Up)),
+        'j' => return Ok(Normal::Key(Key::Down)),
+        'd' | 'c' => {
+            let Some(Key::Char(next)) = read_key()? else {
+                return Ok(Normal::Stay);
+            };
+            // `cw` changes to the end of the word, leaving the blanks
+            let over = if c == 'c' && next == 'w' { 'e' } else { next };
+            let range = if next == c {
+                0..end
+            } else {
+                let Some((pos, inclusive)) = motion(line, over) else {
+                    return Ok(Normal::Stay);
+                };
+                let (start, stop) = (line.pos.min(pos), line.pos.max(pos));
+                start..(stop + usize::from(inclusive)).min(end)
+            };
+            line.pos = range.start;
+            line.chars.drain(range);
+            if c == 'c' {
+                return Ok(Normal::Insert);
+            }
+        }
+        _ => {}
+    }
+    Ok(Normal::Stay)
+}
+
+///
+/// Interactive line editor
+///
+/// Supports moving and editing within the line (arrows, Home/End,
+/// Ctrl-A/E/B/F, Backspace/Delete, Ctrl-K/U/W), undoing edits with Ctrl-_
+/// and redoing them with Alt-/, yanking back what Ctrl-K/U/W cut with
+/// Ctrl-Y and Alt-Y to step back through earlier cuts, stepping through
+/// earlier lines with Up/Down, searching them with Ctrl-R, and Tab
+/// completion. A second Tab opens a menu of the candidates below the line.
+/// The line is coloured as it is typed, and while typing at its end the
+/// rest of the newest earlier line starting with it is suggested in dim
+/// text, which Right or Ctrl-E takes. The colours come from the theme
+///
+#[derive(Default)]
+pub struct Editor {
+    pub completion: Completion,
+    /// Keys work as in vi, from `set -o vi`, rather than as in Emacs
+    pub vi: bool,
+    /// Characters besides letters and digits inside a word for Ctrl-W and
+    /// Alt-Backspace, from `$WORDCHARS`
+    pub wordchars: String,
+    /// Colours for the line and suggestions, and for the prompt
+    pub theme: Theme,
+    /// Drawn against the right edge on the line being edited, while the
+    /// line leaves room for it
+    pub rprompt: Prompt,
+    kill_ring: KillRing,
+}
+
+impl Editor {
+    ///
+    /// Shows a prompt and reads one edited line from the terminal
+    ///
+    /// Inputs:
+    ///   Prompt to draw in front of the line, repainted along with the
+    ///   right prompt as their slow segments are filled in
+    ///   Earlier lines, oldest first, for Up/Down
+    ///
+    /// Output:
+    ///   The line with a trailing newline, None at end of input (Ctrl-D on
+    ///   an empty line), or Interrupted if the line was dropped with Ctrl-C
+    ///
+    pub fn read_line(&mut self, prompt: &mut Prompt, history: &[String]) -> io::Result<Option<String>> {
+        let raw = RawMode::enable()?;
+        let mut line = Line::default();
+        // Position in history while browsing with Up/Down; len() is the
+        // line being typed, which is kept aside as draft
+        let mut index = history.len();
+        let mut draft = String::new();
+        // A second Tab in a row opens the menu of candidates
+        let mut tabbed = false;
+        // In vi mode, whether keys are commands rather than typed in
+        let mut normal = false;
+        let mut edits = Edits::default();
+        // The key before this one, as a run of kills joins up and Alt-Y
+        // only follows a yank
+        let mut last = None;
+        // Where the last Ctrl-Y or Alt-Y put its text, and which kill it was
+        let mut yanked: Option<(Range<usize>, usize)> = None;
+
+        // Only the last line of a multi-line prompt is redrawn while editing
+        let (above, shown) = split_prompt(prompt.text());
+        let (mut above, mut shown) = (above.map(str::to_string), shown.to_string());
+        if let Some(above) = &above {
+            println!("{above}");
+        }
+
+        self.refresh(&shown, &line, suggest(&line, history))?;
+        loop {
+            while (prompt.is_pending() || self.rprompt.is_pending()) && !ready(PROMPT_POLL_MS)? {
+                if !(prompt.poll() | self.rprompt.poll()) {
+                    continue;
+                }
+                let (new_above, new_shown) = split_prompt(prompt.text());
+                if let (Some(old), Some(new)) = (&above, new_above)
+                    && old != new {
+                    // Back up over the lines above and draw them again
+                    print!("\r\x1b[{}A", old.split('\n').count());
+                    for row in new.split('\n') {
+                        println!("{row}\x1b[K");
+                    }
+                }
+                (above, shown) = (new_above.map(str::to_string), new_shown.to_string());
+                let hint = if normal { None } else { suggest(&line, history) };
+                self.refresh(&shown, &line, hint)?;
+            }
+
+            let Some(mut key) = read_key()? else {
+                if line.chars.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            };
+            let previous = last.replace(key);
+            let before = line.snapshot();
+
+            if key == Key::Ctrl('r') {
+                let ended = search(&mut line, history, &mut index, &mut draft)?;
+                // Undo steps belong to the line they were made on
+                edits = Edits::default();
+                self.refresh(&shown, &line, suggest(&line, history))?;
+                match ended {
+                    Some(ended) => key = ended,
+                    None => continue,
+                }
+            }
+
+            if normal
+                && let Key::Char(c) = key {
+                match vi_normal(&mut line, c)? {
+                    Normal::Stay => {}
+                    Normal::Insert => normal = false,
+                    // `k`/`j` go on to step through history like Up/Down
+                    Normal::Key(mapped) => key = mapped,
+                }
+                if let Key::Char(_) = key {
+                    if normal {
+                        line.clamp();
+                    }
+                    edits.record(before, &line, false);
+                    self.refresh(&shown, &line, None)?;
+                    continue;
+                }
+            }
+
+            let listing = tabbed;
+            tabbed = key == Key::Ctrl('i');
+            if key == Key::Ctrl('i')
+                && let Some((start, candidates)) = self.complete(&mut line, listing) {
+                tabbed = false;
+                match self.menu(&shown, &mut line, start, &candidates)? {
+                    Some(ended) => key = ended,
+                    None => {
+                        edits.record(before, &line, false);
+                        self.refresh(&shown, &line, suggest(&line, history))?;
+                        continue;
+                    }
+                }
+            }
+            let killing = matches!(previous, Some(Key::Ctrl('k' | 'u' | 'w') | Key::AltBackspace));
+            let yanking = matches!(previous, Some(Key::Ctrl('y') | Key::Alt('y')));
+
+            match key {
+                Key::Escape if self.vi && !normal => {
+                    normal = true;
+                    line.pos = line.pos.saturating_sub(1);
+                }
+                Key::Enter => break,
+                Key::Ctrl('i') => {}
+                Key::Ctrl('c') => {
+                    print!("^C");
+                    drop(raw);
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Ctrl('d') if line.chars.is_empty() => return Ok(None),
+                Key::Ctrl('d') | Key::Delete => line.delete(),
+                Key::Backspace | Key::Ctrl('h') => line.backspace(),
+                Key::Right | Key::Ctrl('f' | 'e') if let Some(rest) = suggest(&line, history) => {
+                    line.set(&(line.text() + rest));
+                }
+                Key::Left | Key::Ctrl('b') => line.pos = line.pos.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => line.pos = (line.pos + 1).min(line.chars.len()),
+                Key::Home | Key::Ctrl('a') => line.pos = 0,
+                Key::End | Key::Ctrl('e') => line.pos = line.chars.len(),
+                Key::Ctrl('k') => {
+                    let killed = line.cut(line.pos..line.chars.len());
+                    self.kill_ring.kill(killed, killing, true);
+                }
+                Key::Ctrl('u') => {
+                    let killed = line.cut(0..line.pos);
+                    self.kill_ring.kill(killed, killing, false);
+                }
+                Key::Ctrl('w') | Key::AltBackspace => {
+                    let killed = line.cut(line.kill_word_start(&self.wordchars)..line.pos);
+                    self.kill_ring.kill(killed, killing, false);
+                }
+                Key::Ctrl('y') => {
+                    yanked = self.kill_ring.get(0).map(|text| (line.paste(&text), 0));
+                }
+                // Swaps the text just yanked for the kill before it
+                Key::Alt('y') if yanking
+                    && let Some((range, back)) = yanked.take()
+                    && let Some(text) = self.kill_ring.get(back + 1) => {
+                    line.cut(range);
+                    yanked = Some((line.paste(&text), back + 1));
+                }
+                Key::Ctrl('_') => {
+                    edits.undo(&mut line);
+                    self.refresh(&shown, &line, suggest(&line, history))?;
+                    continue;
+                }
+                Key::Alt('/') => {
+                    edits.redo(&mut line);
+                    self.refresh(&shown, &line, suggest(&line, history))?;
+                    continue;
+                }
+                Key::Up if index > 0 => {
+                    if index == history.len() {
+                        draft = line.text();
+                    }
+                    index -= 1;
+                    line.set(&history[index]);
+                    edits = Edits::default();
+                }
+                Key::Down if index < history.len() => {
+                    index += 1;
+                    match history.get(index) {
+                        Some(entry) => line.set(entry),
+                        None => line.set(&draft),
+                    }
+                    edits = Edits::default();
+                }
+                Key::Char(c) => line.insert(c),
+                _ => continue,
+            }
+            if normal {
+                line.clamp();
+            }
+            if !matches!(key, Key::Up | Key::Down) {
+                edits.record(before, &line, matches!(key, Key::Char(_)));
+            }
+            let hint = if normal { None } else { suggest(&line, history) };
+            self.refresh(&shown, &line, hint)?;
+        }
+
+        // Leave the cursor after the whole line before moving on
+        line.pos = line.chars.len();
+        self.refresh(&shown, &line, None)?;
+        print!("\r\n");
+        io::stdout().flush()?;
+
+        let mut text = line.text();
+        text.push('\n');
+        Ok(Some(text))
+    }
+
+    ///
+    /// Redraws the prompt and line, coloured as it would run, then puts the
+    /// cursor where it belongs
+    ///
+    /// Inputs:
+    ///   Prompt in front of the line
+    ///   Line and cursor position
+    ///   Suggested rest of the line, drawn dim after it
+    ///
+    fn refresh(&self, prompt: &str, line: &Line, hint: Option<&str>) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        let known = |name: &str| self.completion.names.iter().any(|known| known == name);
+        write!(out, "\r{prompt}{}", highlight(&line.text(), known, &self.theme))?;
+        let hint = hint.unwrap_or_default();
+        write!(out, "{}", paint(&self.theme.suggestion, hint))?;
+        write!(out, "\x1b[K")?;
+
+        let before = visible_width(prompt);
+        let rprompt = self.rprompt.text();
+        let right = visible_width(rprompt);
+        let width = terminal_size().0;
+        // One column kept free, since writing the last one can wrap
+        if right > 0 && before + line.chars.len() + hint.chars().count() + right + 1 < width {
+            write!(out, "\x1b[{}G{rprompt}", width - right)?;
+            write!(out, "\x1b[{}G", before + line.pos + 1)?;
+        } else {
+            let back = line.chars.len() - line.pos + hint.chars().count();
+            if back > 0 {
+                write!(out, "\x1b[{back}D")?;
+            }
+        }
+        out.flush()
+    }
+
+    ///
+    /// Completes the word before the cursor
+    ///
+    /// A single candidate replaces the word, followed by a space unless it
+    /// is a directory. Several are narrowed to what they have in common
+    ///
+    /// Output:
+    ///   Where the word starts and the candidates, for the menu, if that
+    ///   added nothing and listing is set
+    ///
+    fn complete(&self, line: &mut Line, listing: bool) -> Option<(usize, Vec<String>)> {
+        let (start, candidates) = self.completion.complete(&line.chars[..line.pos]);
+        let replacement = match candidates.as_slice() {
+            [] => return None,
+            [only] if only.ends_with('/') => only.clone(),
+            [only] => format!("{only} "),
+            _ => complete::common_prefix(&candidates).to_string(),
+        };
+
+        let typed = line.pos - start;
+        if replacement.chars().count() > typed {
+            line.chars.splice(start..line.pos, replacement.chars());
+            line.pos = start + replacement.chars().count();
+        } else if listing {
+            return Some((start, candidates));
+        }
+        None
+    }
+
+    ///
+    /// Lets one of several completions be picked from a menu drawn below
+    /// the line, each put in place of the word as it is selected
+    ///
+    /// Tab, Shift-Tab and the arrows move through the menu, Enter takes
+    /// the selection, and Esc or Ctrl-G puts the word back as it was. Any
+    /// other key takes the selection and then acts as usual
+    ///
+    /// Inputs:
+    ///   Prompt in front of the line
+    ///   Line being edited, with the cursor at the end of the word
+    ///   Where the word starts, and its completions
+    ///
+    /// Output:
+    ///   Key that closed the menu for read_line to act on, or None if the
+    ///   menu used it
+    ///
+    fn menu(&self, prompt: &str, line: &mut Line, start: usize, candidates: &[String]) -> io::Result<Option<Key>> {
+        let original = line.snapshot();
+        let descriptions = candidates.iter()
+            .map(|candidate| self.completion.describe(&line.chars[..start], candidate))
+            .collect();
+        let mut menu = Menu::new(candidates, descriptions);
+        let mut end = line.pos;
+
+        let ended = loop {
+            line.cut(start..end);
+            end = line.paste(&candidates[menu.selected]).end;
+
+            // The menu goes below the line, which is then drawn over it
+            let mut out = io::stdout().lock();
+            let rows = menu.draw(&self.theme, &mut out)?;
+            write!(out, "\x1b[{rows}A")?;
+            drop(out);
+            self.refresh(prompt, line, None)?;
+
+            let Some(key) = read_key()? else {
+                break None;
+            };
+            match key {
+                Key::Enter => {
+                    if !candidates[menu.selected].ends_with('/') {
+                        line.insert(' ');
+                    }
+                    break None;
+                }
+                Key::Escape | Key::Ctrl('g') => {
+                    line.restore(original);
+                    break None;
+                }
+                key if menu.step(key) => {}
+                key => break Some(key),
+            }
+        };
+
+        // Clear the menu away, leaving the cursor on the line
+        let mut out = io::stdout().lock();
+        write!(out, "\r\n\x1b[J\x1b[A")?;
+        out.flush()?;
+        Ok(ended)
+    }
+}
+
+///
+/// Completions laid out in columns below the line, one of them selected
+///
+struct Menu {
+    /// Last path component of each completion, as shown
+    names: Vec<String>,
+    descriptions: Vec<Option<String>>,
+    /// Width of each column, including the gap after it
+    width: usize,
+    columns: usize,
+    selected: usize,
+    /// First row shown, when there are more than fit on the screen
+    top: usize,
+}
+
+impl Menu {
+    fn new(candidates: &[String], descriptions: Vec<Option<String>>) -> Menu {
+        let names: Vec<String> = candidates.iter()
+            .map(|candidate| {
+                let trimmed = candidate.strip_suffix('/').unwrap_or(candidate);
+                let name_at = trimmed.rfind('/').map_or(0, |i| i + 1);
+                candidate[name_at..].to_string()
+            })
+            .collect();
+        let width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0) + 2;
+        // Described completions get a row each, with room for the text
+        let columns = if descriptions.iter().any(Option::is_some) {
+            1
+        } else {
+            (terminal_size().0 / width).max(1)
+        };
+        Menu { names, descriptions, width, columns, selected: 0, top: 0 }
+    }
+
+    fn rows(&self) -> usize {
+        self.names.len().div_ceil(self.columns)
+    }
+
+    ///
+    /// Moves the selection for a key, wrapping round at the edges
+    ///
+    /// Output:
+    ///   Whether the key moves the selection
+    ///
+    fn step(&mut self, key: Key) -> bool {
+        let count = self.names.len();
+        let columns = self.columns;
+        self.selected = match key {
+            Key::Ctrl('i') | Key::Right => (self.selected + 1) % count,
+            Key::BackTab | Key::Left => (self.selected + count - 1) % count,
+            Key::Down if self.selected + columns < count => self.selected + columns,
+            Key::Down => self.selected % columns,
+            Key::Up if self.selected >= columns => self.selected - columns,
+            // Up from the top row goes to the bottom of the same column
+            Key::Up => {
+                let bottom = self.selected + (self.rows() - 1) * columns;
+                if bottom < count { bottom } else { bottom - columns }
+            }
+            _ => return false,
+        };
+        true
+    }
+
+    ///
+    /// Draws the menu on the lines below the cursor
+    ///
+    /// Output:
+    ///   How many lines it took, for the cursor to go back up
+    ///
+    fn draw(&mut self, theme: &Theme, out: &mut impl Write) -> io::Result<usize> {
+        let (width, height) = terminal_size();
+        // The line being edited keeps its place on the screen, and a row
+        // is given up to say where the menu is scrolled to if it must be
+        let rows = self.rows();
+        let fits = height.saturating_sub(1).max(1);
+        let shown = if rows > fits { (fits - 1).max(1) } else { rows };
+        let row = self.selected / self.columns;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + shown {
+            self.top = row + 1 - shown;
+        }
+
+        for row in self.top..self.top + shown {
+            write!(out, "\r\n")?;
+            let cells = row * self.columns..((row + 1) * self.columns).min(self.names.len());
+            for index in cells {
+                let name = &self.names[index];
+                let pad = self.width - name.chars().count();
+                if index == self.selected {
+                    write!(out, "\x1b[7m{name}\x1b[0m{:pad$}", "")?;
+                } else {
+                    write!(out, "{name}{:pad$}", "")?;
+                }
+                if let Some(description) = &self.descriptions[index] {
+                    let room = width.saturating_sub(self.width + 4);
+                    let description: String = description.chars().take(room).collect();
+                    write!(out, "{}", paint(&theme.suggestion, &format!("-- {description}")))?;
+                }
+            }
+            write!(out, "\x1b[K")?;
+        }
+        let mut lines = shown;
+        if shown < rows {
+            let status = format!("rows {}-{} of {rows}", self.top + 1, self.top + shown);
+            write!(out, "\r\n{}\x1b[K", paint(&theme.suggestion, &status))?;
+            lines += 1;
+        }
+        write!(out, "\x1b[J")?;
+        Ok(lines)
+    }
+}
+
+///
+/// Reverse incremental search through history, started by Ctrl-R
+///
+/// Typing narrows the search to earlier lines containing what was typed,
+/// newest first, and Ctrl-R again moves on to an older match. Esc or
+/// Ctrl-G gives up and leaves the line as it was
+///
+/// Inputs:
+///   Line being edited, replaced by the match when the search is accepted
+///   Earlier lines, oldest first
+///   Position in history and the line put aside, as kept by read_line, so
+///   Up/Down carry on from the match
+///
+/// Output:
+///   Key that ended the search for read_line to act on (Enter runs the
+///   match), or None if it was given up
+///
+fn search(line: &mut Line, history: &[String], index: &mut usize, draft: &mut String) -> io::Result<Option<Key>> {
+    let mut query = String::new();
+    // Entry shown; kept while a longer query finds nothing
+    let mut found: Option<usize> = None;
+    let mut failed = false;
+
+    let older = |before: usize, query: &str, skip: Option<&str>| {
+        (0..before).rev().find(|&i| history[i].contains(query) && Some(history[i].as_str()) != skip)
+    };
+
+    loop {
+        let shown = found.map_or(String::new(), |i| history[i].clone());
+        let status = if failed { "failed reverse-i-search" } else { "reverse-i-search" };
+        let mut out = io::stdout().lock();
+        write!(out, "\r({status})`{query}': {shown}\x1b[K")?;
+        out.flush()?;
+        drop(out);
+
+        let Some(key) = read_key()? else {
+            return Ok(None);
+        };
+        match key {
+            Key::Char(c) => {
+                query.push(c);
+                // The match shown may still contain the longer query
+                match older(found.map_or(history.len(), |i| i + 1), &query, None) {
+                    Some(i) => found = Some(i),
+                    None => failed = true,
+                }
+            }
+            Key::Backspace | Key::Ctrl('h') => {
+                query.pop();
+                found = if query.is_empty() { None } else { older(history.len(), &query, None) };
+                failed = !query.is_empty() && found.is_none();
+            }
+            Key::Ctrl('r') if !query.is_empty() => {
+                // Lines repeating the one shown are passed over
+                match older(found.unwrap_or(history.len()), &query, Some(&shown)) {
+                    Some(i) => {
+                        found = Some(i);
+                        failed = false;
+                    }
+                    None => failed = true,
+                }
+            }
+            Key::Ctrl('r') => {}
+            Key::Escape | Key::Ctrl('g') => return Ok(None),
+            Key::Ctrl('c') => return Ok(Some(key)),
+            _ => {
+                if let Some(i) = found {
+                    if *index == history.len() {
+                        *draft = line.text();
+                    }
+                    *index = i;
+                    line.set(&history[i]);
+                }
+                return Ok(Some(key));
+            }
+        }
+    }
+}
+
+///
+/// Columns text takes up once its escape sequences are left out
+///
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // A CSI sequence such as a colour ends at its first letter
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    chars.by_ref().find(|c| c.is_ascii_alphabetic());
+                }
+            }
+            c if c.is_control() => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+///
+/// Columns and rows of the terminal, 80 by 24 if it cannot be asked
+///
+pub fn terminal_size() -> (usize, usize) {
+    // SAFETY: TIOCGWINSZ only writes a winsize into the struct we own
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        (size.ws_col as usize, (size.ws_row as usize).max(1))
+    } else {
+        (80, 24)
+    }
+}
+
+///
+/// Rest of the newest earlier line that starts with the line being typed,
+/// offered only while the cursor is at its end
+///
+fn suggest<'a>(line: &Line, history: &'a [String]) -> Option<&'a str> {
+    if line.chars.is_empty() || line.pos < line.chars.len() {
+        return None;
+    }
+    let text = line.text();
+    history.iter()
+        .rev()
+        .find_map(|entry| entry.strip_prefix(&text).filter(|rest| !rest.is_empty()))
+}
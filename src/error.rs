@@ -0,0 +1,156 @@
+use std::fmt;
+use std::io;
+
+use crate::lexer::LexError;
+use crate::parser::ParseError;
+use crate::State;
+
+///
+/// Something that went wrong running a command, reported by the shell
+/// rather than by the command itself
+///
+#[derive(Debug)]
+pub enum ShellError {
+    /// Input that could not be split into tokens
+    Lex(LexError),
+    /// Input that is not valid syntax, with the line it went wrong on when
+    /// it came from a script or file, counting from 1
+    Syntax { error: ParseError, line: Option<usize> },
+    /// A command that could not be started
+    Spawn { command: String, error: io::Error },
+    /// A redirection whose target could not be used, by path or fd
+    Redirect { target: String, error: io::Error },
+    /// An expansion such as `$((1/0))` or `${x:?}` that failed, with what
+    /// was being expanded. It stops the command, and a shell that is not
+    /// interactive
+    Expansion { text: String, message: String },
+    /// A pattern that matched nothing under `set -o failglob`
+    NoMatch(String),
+    /// An assignment to a variable `declare -r` made readonly
+    Readonly(String),
+    /// Something restricted mode does not allow, completing `cannot ...`
+    Restricted(String),
+    /// A call to the system the shell needs, such as pipe or fork, failing
+    System(io::Error),
+    /// A builtin that could not do what it was asked, with its name
+    Builtin { name: String, message: String },
+    /// A builtin given arguments it does not take
+    Usage { name: String, message: String },
+}
+
+impl ShellError {
+    ///
+    /// Exit status the error leaves in `$?`
+    ///
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::Lex(_) | ShellError::Syntax { .. } => 2,
+            ShellError::Spawn { error, .. } if error.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::Spawn { .. } => 126,
+            ShellError::Redirect { .. }
+            | ShellError::Expansion { .. }
+            | ShellError::NoMatch(_)
+            | ShellError::Readonly(_)
+            | ShellError::Restricted(_)
+            | ShellError::System(_)
+            | ShellError::Builtin { .. } => 1,
+            ShellError::Usage { .. } => 2,
+        }
+    }
+
+    ///
+    /// Whether the error ends a shell that is not interactive, as bash
+    /// leaves a script at a failed expansion
+    ///
+    pub fn ends_script(&self) -> bool {
+        matches!(self, ShellError::Expansion { .. })
+    }
+
+    ///
+    /// Prints the error as `rust-shell: <context>: <cause>`
+    ///
+    pub fn print(&self) {
+        eprintln!("rust-shell: {self}");
+    }
+}
+
+///
+/// Describes an io error the way the C library would, without the
+/// `(os error N)` Rust adds
+///
+pub fn cause(error: &io::Error) -> String {
+    let text = error.to_string();
+    match text.rfind(" (os error ") {
+        Some(end) if error.raw_os_error().is_some() => text[..end].to_string(),
+        _ => text,
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Lex(error) => write!(f, "{error}"),
+            ShellError::Syntax { error, line: Some(line) } => write!(f, "line {line}: {error}"),
+            ShellError::Syntax { error, line: None } => write!(f, "{error}"),
+            ShellError::Spawn { command, error } => write!(f, "{command}: {}", cause(error)),
+            ShellError::Redirect { target, error } => write!(f, "{target}: {}", cause(error)),
+            ShellError::Expansion { text, message } => write!(f, "{text}: {message}"),
+            ShellError::NoMatch(pattern) => write!(f, "{pattern}: no match"),
+            ShellError::Readonly(name) => write!(f, "{name}: readonly variable"),
+            ShellError::Restricted(action) => write!(f, "restricted: cannot {action}"),
+            ShellError::System(error) => f.write_str(&cause(error)),
+            ShellError::Builtin { name, message } | ShellError::Usage { name, message } => {
+                write!(f, "{name}: {message}")
+            }
+        }
+    }
+}
+
+impl From<LexError> for ShellError {
+    fn from(error: LexError) -> Self {
+        ShellError::Lex(error)
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(error: io::Error) -> Self {
+        ShellError::System(error)
+    }
+}
+
+///
+/// Reports a builtin failing, as `rust-shell: <name>: <message>`
+///
+/// Output:
+///   1, for the builtin to return
+///
+pub fn builtin(name: &str, message: impl fmt::Display) -> i32 {
+    let error = ShellError::Builtin { name: name.to_string(), message: message.to_string() };
+    error.print();
+    error.status()
+}
+
+///
+/// Reports a builtin being given arguments it does not take
+///
+/// Output:
+///   2, for the builtin to return
+///
+pub fn usage(name: &str, message: impl fmt::Display) -> i32 {
+    let error = ShellError::Usage { name: name.to_string(), message: message.to_string() };
+    error.print();
+    error.status()
+}
+
+///
+/// Prints an error and sets `$?` to the status it calls for
+///
+/// Output:
+///   That status, for callers that also return it
+///
+pub fn report(state: &mut State, error: impl Into<ShellError>) -> i32 {
+    let error = error.into();
+    error.print();
+    state.last_status = error.status();
+    state.last_status
+}
@@ -0,0 +1,1130 @@
+use std::env; 
+use std::fs::File; 
+use std::io::{self, ErrorKind, Write}; 
+use std::ops::ControlFlow; 
+use std::os::fd::{AsRawFd, OwnedFd, RawFd}; 
+use std::os::unix::process::CommandExt; 
+use std::path::{Path, PathBuf}; 
+use std::process::{self, Child, Stdio}; 
+use std::rc::Rc; 
+use std::time::{Duration, Instant}; 
+
+use crate::lexer::{RedirectOp, Token, Word}; 
+use crate::parser::{Command, Compound, Connector, List, Pipeline}; 
+use crate::redirect::{self, Redirect, SavedFds, Source}; 
+use crate::error::{self, ShellError}; 
+use crate::{builtins, jobs, options, platform, signals, trap, State, RESTRICTED_VARS}; 
+use crate::{assign, command_not_found, expand_args, expand_args_by_word, expand_value, expand_word, lookup, matches_pattern, readonly, restricted, suggestion}; 
+
+///
+/// Runs parsed commands in a shell's state 
+///
+pub struct Executor<'a> {
+    state: &'a mut State, 
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(state: &'a mut State) -> Self {
+        Executor { state }
+    }
+
+    ///
+    /// Runs a list of pipelines from the parser 
+    ///
+    /// Output: 
+    ///   Break with the exit status if it ran `exit`, Continue else 
+    ///
+    pub fn run(&mut self, list: &List) -> ControlFlow<i32> {
+        run_list(self.state, list)
+    }
+}
+
+///
+/// Runs a parsed command line 
+///
+/// Inputs: 
+///   Shell state shared across commands, whose last status is updated 
+///   List of pipelines from the parser 
+///
+/// Returns: 
+///   Break with the exit status if exit was specified, Continue else 
+///
+pub fn run_list(state: &mut State, list: &List) -> ControlFlow<i32> {
+    // Skipped pipelines leave the status alone, so `a && b || c` runs c 
+    // when either a or b fails 
+    for (index, item) in list.items.iter().enumerate() {
+        let run = match item.connector {
+            Connector::Always => true, 
+            Connector::IfSuccess => state.last_status == 0, 
+            Connector::IfFailure => state.last_status != 0, 
+        };
+        if !run {
+            continue; 
+        }
+        let substitutions = state.substitutions.len(); 
+        let flow = if item.pipeline.timed && !item.background {
+            run_timed(state, &item.pipeline)
+        } else {
+            run_pipeline(state, &item.pipeline, item.background)
+        }; 
+        finish_substitutions(state, substitutions); 
+        flow?; 
+        trap::run_pending(state)?; 
+
+        // With `set -e` a failure ends the shell, after the ERR trap, unless 
+        // it is tested: in a condition, or anywhere but last in a `&&`/`||` 
+        // chain 
+        let tested = state.in_condition > 0 || list.items.get(index + 1)
+            .is_some_and(|next| next.connector != Connector::Always); 
+        if state.last_status != 0 && !item.background && !tested {
+            trap::on_err(state)?; 
+            if state.options.errexit {
+                return ControlFlow::Break(state.last_status); 
+            }
+        }
+    }
+
+    ControlFlow::Continue(())
+}
+
+///
+/// Closes the shell's ends of the process substitutions started since 
+/// the given count, so each sees end of input or a broken pipe, then 
+/// waits for them to finish 
+///
+fn finish_substitutions(state: &mut State, from: usize) {
+    if state.substitutions.len() <= from {
+        return; 
+    }
+    let pids: Vec<libc::pid_t> = state.substitutions.drain(from..).map(|(_, pid)| pid).collect(); 
+    for pid in pids {
+        let mut raw = 0; 
+        // SAFETY: waitpid only writes through the status pointer we own 
+        while unsafe { libc::waitpid(pid, &mut raw, 0) } < 0 
+            && io::Error::last_os_error().kind() == ErrorKind::Interrupted {}
+    }
+}
+
+///
+/// Whether the last command was killed by Ctrl-C, which stops any loop 
+/// it ran in rather than leaving the user to interrupt every iteration 
+///
+fn interrupted(state: &State) -> bool {
+    state.last_status == 128 + libc::SIGINT 
+}
+
+///
+/// Runs the condition of an if, while or until 
+///
+fn run_condition(state: &mut State, condition: &List) -> ControlFlow<i32> {
+    state.in_condition += 1; 
+    let flow = run_list(state, condition); 
+    state.in_condition -= 1; 
+    flow 
+}
+
+///
+/// Runs a control structure in the shell process 
+///
+/// Inputs: 
+///   Shell state shared across commands, whose last status is updated 
+///   Structure to run 
+///
+/// Returns: 
+///   Break with the exit status if exit was specified, Continue else 
+///
+fn run_compound(state: &mut State, compound: &Compound) -> ControlFlow<i32> {
+    match compound {
+        Compound::If { branches, otherwise } => {
+            for (condition, body) in branches {
+                run_condition(state, condition)?; 
+                if state.last_status == 0 {
+                    return run_list(state, body); 
+                }
+            }
+            // No branch taken and no else is still a success 
+            match otherwise {
+                Some(body) => run_list(state, body)?, 
+                None => state.last_status = 0, 
+            }
+        },
+        Compound::While { condition, body, until } => {
+            state.loop_depth += 1; 
+            let flow = run_while(state, condition, body, *until); 
+            state.loop_depth -= 1; 
+            flow?; 
+        },
+        Compound::Group(body) => run_list(state, body)?, 
+        // Only reached in the child forked for it 
+        Compound::Subshell(body) => run_list(state, body)?, 
+        Compound::Case { word, branches } => {
+            let subject = match expand_value(state, word) {
+                Ok(subject) => subject, 
+                Err(e) => {
+                    expansion_failed(state, e)?; 
+                    return ControlFlow::Continue(()); 
+                },
+            }; 
+            // No branch matching is still a success 
+            state.last_status = 0; 
+            for (patterns, body) in branches {
+                for pattern in patterns {
+                    match matches_pattern(state, pattern, &subject) {
+                        Ok(true) => return run_list(state, body), 
+                        Ok(false) => {}, 
+                        Err(e) => {
+                            expansion_failed(state, e)?; 
+                            return ControlFlow::Continue(()); 
+                        },
+                    }
+                }
+            }
+        },
+        Compound::For { name, items, body } => {
+            if RESTRICTED_VARS.contains(&name.as_str()) && restricted(state, &format!("set {name}")) {
+                state.last_status = 1; 
+                return ControlFlow::Continue(()); 
+            }
+
+            // Without `in` the loop is over the positional parameters 
+            let values = match items.as_ref().map(|words| expand_args(state, words)) {
+                Some(Ok(values)) => values, 
+                Some(Err(e)) => {
+                    expansion_failed(state, e)?; 
+                    return ControlFlow::Continue(()); 
+                },
+                None => state.positional.clone(), 
+            };
+
+            state.last_status = 0; 
+            state.loop_depth += 1; 
+            let flow = run_for(state, name, values, body); 
+            state.loop_depth -= 1; 
+            flow?; 
+        },
+    }
+    ControlFlow::Continue(())
+}
+
+///
+/// Catches a `break` or `continue` meant for the loop running a list 
+///
+/// Inputs: 
+///   Shell state, with the loops `break` or `continue` still has to leave 
+///   How the condition or body of the loop finished 
+///
+/// Returns: 
+///   Continue with whether the loop goes on, or Break to unwind further: 
+///   out of an outer loop, or because the shell is exiting 
+///
+fn caught(state: &mut State, flow: ControlFlow<i32>) -> ControlFlow<i32, bool> {
+    match flow {
+        ControlFlow::Break(code) if state.breaking > 0 => {
+            state.breaking -= 1; 
+            if state.breaking > 0 {
+                return ControlFlow::Break(code); 
+            }
+            state.last_status = code; 
+            ControlFlow::Continue(std::mem::take(&mut state.continuing))
+        },
+        ControlFlow::Break(code) => ControlFlow::Break(code), 
+        ControlFlow::Continue(()) => ControlFlow::Continue(true), 
+    }
+}
+
+///
+/// Runs a while or until loop, as many times as its condition allows 
+///
+fn run_while(state: &mut State, condition: &List, body: &List, until: bool) -> ControlFlow<i32> {
+    let mut status = 0; 
+    loop {
+        let flow = run_condition(state, condition); 
+        if !caught(state, flow)? {
+            break; 
+        }
+        if interrupted(state) || (state.last_status == 0) == until {
+            break; 
+        }
+        let flow = run_list(state, body); 
+        let go_on = caught(state, flow)?; 
+        status = state.last_status; 
+        if !go_on || interrupted(state) {
+            break; 
+        }
+    }
+    state.last_status = status; 
+    ControlFlow::Continue(())
+}
+
+///
+/// Runs a for loop's body once for each of its values 
+///
+fn run_for(state: &mut State, name: &str, values: Vec<String>, body: &List) -> ControlFlow<i32> {
+    for value in values {
+        if let Err(e) = assign(state, name, value) {
+            expansion_failed(state, e)?; 
+            break; 
+        }
+        let flow = run_list(state, body); 
+        if !caught(state, flow)? || interrupted(state) {
+            break; 
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+///
+/// Expands and opens the redirection targets of a command 
+///
+/// Inputs: 
+///   Shell state, for restricted mode 
+///   Redirections in the order they were written 
+///
+/// Output: 
+///   Target fd and opened file per redirection, or None after reporting 
+///   the first one that failed 
+///
+fn open_redirects(state: &mut State, redirects: &[Redirect]) -> Option<Vec<(i32, Source)>> {
+    let mut files = Vec::with_capacity(redirects.len()); 
+    let bad_fd = |target: String| ShellError::Redirect { target, error: io::Error::from_raw_os_error(libc::EBADF) }; 
+
+    for redirect in redirects {
+        if redirect.writes() && restricted(state, "redirect output") {
+            return None; 
+        }
+
+        if redirect.fd > 9 {
+            error::report(state, bad_fd(redirect.fd.to_string())); 
+            return None; 
+        }
+
+        // The text itself, expanded as one word as in double quotes 
+        if redirect.is_here() {
+            let mut text = match expand_value(state, &redirect.target) {
+                Ok(text) => text, 
+                Err(e) => {
+                    error::report(state, e); 
+                    return None; 
+                },
+            }; 
+            if redirect.op == RedirectOp::HereString {
+                text.push('\n');
+            }
+            match redirect::here_doc(&text) {
+                Ok(file) => files.push((redirect.fd, Source::File(file))),
+                Err(e) => {
+                    error::report(state, e); 
+                    return None; 
+                }
+            }
+            continue; 
+        }
+
+        let mut targets = Vec::new(); 
+        if let Err(e) = expand_word(state, &redirect.target, &mut targets) {
+            error::report(state, e); 
+            return None; 
+        }
+        let [path] = targets.as_slice() else {
+            let target = Token::Word(redirect.target.clone()).to_string(); 
+            error::report(state, ShellError::Redirect { target, error: io::Error::other("ambiguous redirect") }); 
+            return None; 
+        };
+
+        if redirect.duplicates() {
+            match path.parse::<i32>() {
+                _ if path == "-" => files.push((redirect.fd, Source::Closed)),
+                // Open if it is standard, kept by `exec`, or redirected just before 
+                Ok(source) if source <= 2 
+                    || state.open_fds.contains(&source) 
+                    || files.iter().any(|(fd, _)| *fd == source) => {
+                    files.push((redirect.fd, Source::Fd(source)))
+                },
+                _ => {
+                    error::report(state, bad_fd(path.clone())); 
+                    return None; 
+                }
+            }
+            continue; 
+        }
+
+        match redirect::open(redirect.op, path, state.options.noclobber) {
+            Ok(file) => files.push((redirect.fd, Source::File(file))),
+            Err(error) => {
+                error::report(state, ShellError::Redirect { target: path.clone(), error }); 
+                return None; 
+            }
+        }
+    }
+
+    Some(files)
+}
+
+///
+/// Program to run for a pipeline stage, once expanded 
+///
+struct External {
+    /// Program name and arguments 
+    argv: Vec<String>,
+    /// Full path of the program, or None to let `$PATH` be searched for it 
+    program: Option<PathBuf>,
+    /// Temporary `NAME=value` assignments for its environment only 
+    assignments: Vec<(String, String)>,
+}
+
+///
+/// Spawns an external command as one stage of a pipeline 
+///
+/// Inputs: 
+///   Shell state, for a `command_not_found_handle` if it is not found 
+///   Program to run 
+///   Read end of the pipe from the previous stage, to become its stdin 
+///   Write end of the pipe to the next stage, to become its stdout 
+///   Opened redirections, which take precedence over the pipes 
+///   Process group to put the child in (0 for a new one led by it), or 
+///   None to stay in the shell's 
+///   Whether the child should take the terminal for its group, as the 
+///   foreground job of an interactive shell 
+///
+/// Output: 
+///   Spawned child, or the exit status to report if it could not be 
+///   started (127 not found, 126 otherwise). Either way the pipe ends are 
+///   closed in the shell by the time it returns 
+///
+fn spawn_external(
+    state: &mut State, 
+    external: &External, 
+    stdin: Option<OwnedFd>, 
+    stdout: Option<OwnedFd>,
+    files: Vec<(i32, Source)>,
+    group: Option<libc::pid_t>,
+    take_terminal: bool
+) -> std::result::Result<Child, i32> {
+    let (command, args) = (&external.argv[0], &external.argv[1..]); 
+
+    // Pipes are set up here; redirections are applied over them in the child 
+    let stdin = stdin.map_or(Stdio::inherit(), Stdio::from); 
+    let stdout = stdout.map_or(Stdio::inherit(), Stdio::from); 
+
+    // Run by its full path when known, still seeing its name as argv[0] 
+    let program = external.program.as_deref().unwrap_or(Path::new(command)); 
+    let mut cmd = process::Command::new(program); 
+    cmd.arg0(command)
+        .args(args)
+        .envs(external.assignments.iter().map(|(name, value)| (name, value)))
+        .stdin(stdin)
+        .stdout(stdout);
+
+    if let Some(pgid) = group {
+        cmd.process_group(pgid);
+    }
+
+    // SAFETY: runs between fork and exec, and only makes async-signal-safe 
+    // calls. Taking the terminal in the child as well as the parent means 
+    // it cannot read before it owns the terminal, whichever runs first. 
+    // Redirections go after the pipes, in order, so `>out 2>&1` sends both 
+    // to out while `2>&1 >out` leaves stderr where stdout was 
+    unsafe {
+        cmd.pre_exec(move || {
+            if take_terminal {
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            }
+            signals::reset_for_child(); 
+            redirect::apply_in_child(&files)
+        });
+    }
+
+    let output = cmd.spawn(); 
+    
+    // If command is an error, handle 
+    match output { 
+        Ok(output) => Ok(output),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(command_not_found(state, command, args, None)),
+        Err(error) => {
+            let error = ShellError::Spawn { command: command.clone(), error }; 
+            error.print(); 
+            Err(error.status())
+        }
+    }
+}
+
+///
+/// Commands run by the shell itself rather than as a separate program 
+///
+enum InShell<'a> {
+    /// Builtin's name and arguments, and assignments in effect while it runs 
+    Builtin(Vec<String>, Vec<(String, String)>),
+    Compound(&'a Compound),
+    /// Function body, and the arguments it was called with, name first 
+    Function(Rc<Command>, Vec<String>),
+}
+
+///
+/// What one stage of a pipeline turned out to be once its words and 
+/// redirections were expanded 
+///
+enum Launch<'a> {
+    /// Nothing left to run, with the status of the stage 
+    Done(i32),
+    /// An expansion failed in a shell that is not interactive, which exits 
+    /// with this status 
+    Exit(i32),
+    Builtin(Vec<String>, Vec<(String, String)>),
+    InShell(InShell<'a>),
+    External(External),
+}
+
+///
+/// Reports an expansion that failed, which ends the command it was in 
+///
+/// Returns: 
+///   Break with the status if the error also ends the shell, not being 
+///   interactive, Continue with it else 
+///
+fn expansion_failed(state: &mut State, error: ShellError) -> ControlFlow<i32, i32> {
+    let ends = error.ends_script() && !jobs::interactive(); 
+    let status = error::report(state, error); 
+    if ends { ControlFlow::Break(status) } else { ControlFlow::Continue(status) }
+}
+
+///
+/// What a pipeline stage comes to when its expansion failed 
+///
+fn failed<'a>(state: &mut State, error: ShellError) -> Launch<'a> {
+    match expansion_failed(state, error) {
+        ControlFlow::Break(code) => Launch::Exit(code), 
+        ControlFlow::Continue(code) => Launch::Done(code), 
+    }
+}
+
+///
+/// Expands a pipeline stage and works out how to run it 
+///
+/// Inputs: 
+///   Shell state shared across commands 
+///   Command of the stage 
+///
+/// Output: 
+///   How to run it and its opened redirections. Stages that only define 
+///   a function, set variables, or failed to expand are Done, or Exit for 
+///   a failed expansion that ends the shell 
+///
+fn prepare<'a>(state: &mut State, command: &'a Command) -> (Launch<'a>, Vec<(i32, Source)>) {
+    let stage = match command {
+        Command::Simple(simple) => simple, 
+        Command::Compound(compound, redirects) => return match open_redirects(state, redirects) {
+            Some(files) => (Launch::InShell(InShell::Compound(compound)), files), 
+            None => (Launch::Done(1), Vec::new()), 
+        },
+        Command::Function(name, body) => {
+            state.functions.insert(name.clone(), Rc::clone(body));
+            return (Launch::Done(0), Vec::new()); 
+        },
+    };
+
+    let bare = stage.words.iter().all(|word| word.assignment().is_some()); 
+    let assignments = expand_assignments(state, &stage.words, bare); 
+    state.pending.clear(); 
+    let assignments = match assignments {
+        Ok(assignments) => assignments, 
+        Err(launch) => return (launch, Vec::new()), 
+    }; 
+    let (argv, arg_words) = match expand_args_by_word(state, &stage.words[assignments.len()..]) {
+        Ok(expanded) => expanded, 
+        Err(e) => return (failed(state, e), Vec::new()), 
+    }; 
+    let Some(files) = open_redirects(state, &stage.redirects) else {
+        return (Launch::Done(1), Vec::new()); 
+    };
+    if state.options.xtrace && !(assignments.is_empty() && argv.is_empty()) {
+        options::trace(state, &assignments, &argv); 
+    }
+
+    // Without a command, assignments set shell variables and a bare 
+    // redirection like `> file` has done its job once opened 
+    let Some(command) = argv.first() else {
+        for (name, value) in assignments.into_iter().filter(|_| !bare) {
+            if let Err(e) = assign(state, &name, value) {
+                return (failed(state, e), files); 
+            }
+        }
+        return (Launch::Done(0), files); 
+    }; 
+
+    let launch = match command.as_str() {
+        // Functions come first, so one can wrap a command of the same name 
+        name if let Some(body) = state.functions.get(name) => Launch::InShell(InShell::Function(Rc::clone(body), argv)),
+        // Built-In commands, unless switched off by enable 
+        name if builtins::is_enabled(state, name) => {
+            state.arg_words = arg_words; 
+            Launch::Builtin(argv, assignments)
+        },
+        name if platform::is_path(name) 
+            && restricted(state, "specify '/' in command names") => Launch::Done(1), 
+        // A `PATH=...` assignment leaves the lookup to the new path 
+        _ if assignments.iter().any(|(name, _)| name == "PATH") => {
+            Launch::External(External { argv, program: None, assignments })
+        },
+        name => match state.path_table.lookup(name) {
+            Some(program) => Launch::External(External { argv, program: Some(program), assignments }), 
+            None if platform::is_path(name) => Launch::External(External { argv, program: None, assignments }), 
+            None => {
+                let suggestion = suggestion(state, name); 
+                Launch::Done(command_not_found(state, name, &argv[1..], suggestion.as_deref()))
+            },
+        }, 
+    };
+    (launch, files)
+}
+
+///
+/// Expands the leading `NAME=value` words of a command, which are 
+/// assignments rather than the command, `NAME+=value` adding to what the 
+/// variable already holds. Each is expanded once the ones before it hold, 
+/// so `a=1 b=$a` gives b 1 
+///
+/// Inputs: 
+///   Shell state, whose pending assignments hold the ones before a command 
+///   Words of the command 
+///   Whether the words are all assignments, so each is set in the shell 
+///   as soon as it is expanded 
+///
+/// Output: 
+///   Names and values to set, or how the stage ends if one could not be 
+///   expanded or set 
+///
+fn expand_assignments<'a>(state: &mut State, words: &[Word], bare: bool) -> Result<Vec<(String, String)>, Launch<'a>> {
+    let mut assignments = Vec::new(); 
+    for (name, append, value) in words.iter().map_while(Word::assignment) {
+        if RESTRICTED_VARS.contains(&name.as_str()) && restricted(state, &format!("set {name}")) {
+            return Err(Launch::Done(1)); 
+        }
+        // Not even for one command 
+        if readonly(state, &name) {
+            return Err(failed(state, ShellError::Readonly(name))); 
+        }
+        let value = expand_value(state, &value).map_err(|e| failed(state, e))?; 
+        let value = match append.then(|| lookup(state, &name)).flatten() {
+            // An integer adds rather than appends 
+            Some(old) if state.attributes.get(&name).is_some_and(|attributes| attributes.integer) => {
+                format!("{old}+({value})")
+            },
+            Some(old) => old + &value, 
+            None => value, 
+        }; 
+        if bare {
+            assign(state, &name, value.clone()).map_err(|e| failed(state, e))?; 
+        } else {
+            state.pending.insert(name.clone(), value.clone());
+        }
+        assignments.push((name, value));
+    }
+    Ok(assignments)
+}
+
+///
+/// Runs a builtin with `NAME=value` assignments written before it, as in 
+/// `IFS=: read a b`, holding only until it finishes 
+///
+fn run_builtin_with(state: &mut State, argv: &[String], assignments: Vec<(String, String)>) -> ControlFlow<i32, i32> {
+    let saved: Vec<_> = assignments.iter()
+        .map(|(name, _)| (name.clone(), state.vars.get(name).cloned(), env::var_os(name)))
+        .collect(); 
+    let mut flow = ControlFlow::Continue(0); 
+    for (name, value) in assignments {
+        if let Err(e) = assign(state, &name, value) {
+            flow = expansion_failed(state, e).map_continue(|_| 1); 
+            break; 
+        }
+    }
+    if flow == ControlFlow::Continue(0) {
+        flow = builtins::run(state, argv); 
+    }
+
+    for (name, var, exported) in saved.into_iter().rev() {
+        match (var, exported) {
+            (_, Some(value)) => platform::set_env(&name, value), 
+            (Some(value), None) => {
+                state.vars.insert(name, value);
+            },
+            (None, None) => {
+                state.vars.remove(&name);
+            },
+        }
+    }
+    flow 
+}
+
+///
+/// Runs commands that belong to the shell itself 
+///
+fn run_in_shell(state: &mut State, what: InShell) -> ControlFlow<i32> {
+    match what {
+        InShell::Builtin(argv, assignments) => {
+            state.last_status = run_builtin_with(state, &argv, assignments)?; 
+            ControlFlow::Continue(())
+        },
+        InShell::Compound(compound) => run_compound(state, compound), 
+        InShell::Function(body, argv) => call_function(state, &body, argv), 
+    }
+}
+
+///
+/// Runs a function with the call's arguments as positional parameters 
+///
+/// Inputs: 
+///   Shell state, whose positional parameters are put back afterwards 
+///   Body of the function 
+///   Arguments it was called with, its name first 
+///
+/// Returns: 
+///   Continue once the function finishes or returns, Break if it exited 
+///
+pub fn call_function(state: &mut State, body: &Command, mut argv: Vec<String>) -> ControlFlow<i32> {
+    let Command::Compound(compound, redirects) = body else {
+        unreachable!("function bodies are compound commands");
+    };
+
+    argv.remove(0); 
+    let saved = std::mem::replace(&mut state.positional, argv); 
+    // Loops around the call are out of reach of `break` in its body 
+    let loop_depth = std::mem::take(&mut state.loop_depth); 
+    state.function_depth += 1; 
+
+    let flow = match open_redirects(state, redirects).map(SavedFds::apply) {
+        Some(Ok(_saved)) => run_compound(state, compound), 
+        Some(Err(e)) => {
+            error::report(state, e); 
+            ControlFlow::Continue(())
+        },
+        None => {
+            state.last_status = 1; 
+            ControlFlow::Continue(())
+        },
+    };
+
+    state.function_depth -= 1; 
+    state.loop_depth = loop_depth; 
+    state.positional = saved; 
+
+    match flow {
+        ControlFlow::Break(code) if state.returning => {
+            state.returning = false; 
+            state.last_status = code; 
+            ControlFlow::Continue(())
+        },
+        flow => flow, 
+    }
+}
+
+///
+/// Forks a copy of the shell to run one stage of a pipeline, for commands 
+/// the shell runs itself that must not hold up the other stages 
+///
+/// Inputs: 
+///   Shell state, copied into the child 
+///   What the child runs 
+///   Pipe ends to become the child's stdin and stdout, as for spawn_external 
+///   Pipe ends the shell still holds for other stages, which the child 
+///   closes so their readers see end of file once the writers are done 
+///   Opened redirections, which take precedence over the pipes 
+///   Process group and whether to take the terminal, as for spawn_external 
+///
+/// Output: 
+///   Pid of the child, or the exit status to report if it could not be 
+///   started. Either way its pipe ends are closed in the shell 
+///
+#[allow(clippy::too_many_arguments)]
+fn fork_stage(
+    state: &mut State, 
+    what: InShell, 
+    stdin: Option<OwnedFd>, 
+    stdout: Option<OwnedFd>, 
+    others: &[RawFd], 
+    files: Vec<(i32, Source)>,
+    group: Option<libc::pid_t>,
+    take_terminal: bool
+) -> std::result::Result<libc::pid_t, i32> {
+    // Anything still buffered would otherwise be written twice 
+    io::stdout().flush().ok(); 
+    io::stderr().flush().ok(); 
+
+    // SAFETY: other threads only hold the prompt's git lock, which the child 
+    // never takes, so it can keep running Rust code after fork. It never 
+    // returns from this branch 
+    match unsafe { libc::fork() } {
+        -1 => Err(error::report(state, io::Error::last_os_error())),
+        0 => unsafe {
+            if let Some(pgid) = group {
+                libc::setpgid(0, pgid);
+            }
+            if take_terminal {
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            }
+            signals::reset_for_child(); 
+            jobs::set_noninteractive(); 
+            trap::clear_for_subshell(state); 
+
+            for &fd in others {
+                libc::close(fd);
+            }
+            if let Some(stdin) = stdin {
+                libc::dup2(stdin.as_raw_fd(), libc::STDIN_FILENO);
+            }
+            if let Some(stdout) = stdout {
+                libc::dup2(stdout.as_raw_fd(), libc::STDOUT_FILENO);
+            }
+
+            let code = match redirect::apply_in_child(&files) {
+                Ok(()) => match run_in_shell(state, what) {
+                    ControlFlow::Break(code) => code, 
+                    ControlFlow::Continue(()) => state.last_status, 
+                },
+                Err(e) => error::report(state, e), 
+            };
+            io::stdout().flush().ok(); 
+            io::stderr().flush().ok(); 
+            libc::_exit(code)
+        },
+        pid => {
+            // Done here as well as in the child, so the group exists before 
+            // a later stage asks to join it 
+            if let Some(pgid) = group {
+                unsafe { libc::setpgid(pid, if pgid == 0 { pid } else { pgid }) };
+            }
+            Ok(pid)
+        }
+    }
+}
+
+///
+/// Raw descriptors of the pipe ends not yet handed to a stage 
+///
+fn open_ends(pipes: &[(Option<OwnedFd>, Option<OwnedFd>)]) -> Vec<RawFd> {
+    pipes.iter()
+        .flat_map(|(reader, writer)| [reader, writer])
+        .flatten()
+        .map(AsRawFd::as_raw_fd)
+        .collect()
+}
+
+///
+/// CPU time used so far by the shell and the children it has waited for, 
+/// as (user, sys) 
+///
+fn cpu_times() -> (Duration, Duration) {
+    let mut total = (Duration::ZERO, Duration::ZERO); 
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        // SAFETY: getrusage only writes into the struct we own 
+        let usage = unsafe {
+            let mut usage: libc::rusage = std::mem::zeroed(); 
+            libc::getrusage(who, &mut usage);
+            usage 
+        }; 
+        let duration = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        }; 
+        total.0 += duration(usage.ru_utime); 
+        total.1 += duration(usage.ru_stime); 
+    }
+    total 
+}
+
+/// Report `time` gives when `$TIMEFORMAT` is not set 
+const TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS"; 
+
+///
+/// Fills in a `$TIMEFORMAT` report 
+///
+/// Inputs: 
+///   Format, where `%R`, `%U` and `%S` are the real, user and sys time 
+///   in seconds and `%P` the percentage of real time spent on the CPU. 
+///   An optional digit between `%` and the letter gives the decimal 
+///   places, 3 by default or 2 for `%P`, and an `l` writes minutes as 
+///   well, as in `1m2.500s`. 
+///   `%%` is a lone `%` 
+///   Real, user and sys time 
+///
+/// Output: 
+///   Report, without a trailing newline 
+///
+fn time_report(format: &str, real: Duration, user: Duration, sys: Duration) -> String {
+    let mut report = String::new(); 
+    let mut chars = format.chars().peekable(); 
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            report.push(c); 
+            continue; 
+        }
+        let given = chars.next_if(|c| c.is_ascii_digit())
+            .and_then(|digit| digit.to_digit(10))
+            .map(|digit| digit.min(3) as usize); 
+        let places = given.unwrap_or(3); 
+        let long = chars.next_if_eq(&'l').is_some(); 
+        let time = match chars.next() {
+            Some('R') => real, 
+            Some('U') => user, 
+            Some('S') => sys, 
+            Some('P') => {
+                let share = (user + sys).as_secs_f64() / real.as_secs_f64().max(f64::MIN_POSITIVE); 
+                let places = given.unwrap_or(2); 
+                report.push_str(&format!("{:.places$}", share * 100.0)); 
+                continue; 
+            },
+            Some('%') => {
+                report.push('%'); 
+                continue; 
+            },
+            // Anything else is written as it was 
+            other => {
+                report.push('%'); 
+                report.extend(other); 
+                continue; 
+            }
+        }; 
+        // Truncated rather than rounded, as bash does 
+        let scale = 10_u64.pow(places as u32); 
+        let fraction = u64::from(time.subsec_millis()) * scale / 1000; 
+        let seconds = time.as_secs(); 
+        let whole = if long {
+            report.push_str(&format!("{}m", seconds / 60)); 
+            seconds % 60 
+        } else {
+            seconds 
+        }; 
+        report.push_str(&whole.to_string()); 
+        if places > 0 {
+            report.push_str(&format!(".{fraction:0places$}")); 
+        }
+        if long {
+            report.push('s'); 
+        }
+    }
+    report 
+}
+
+///
+/// Runs a foreground pipeline started with `time`, then reports the real, 
+/// user and sys time it took on stderr as `$TIMEFORMAT` lays out, as bash 
+/// does, an empty format reporting nothing 
+///
+fn run_timed(state: &mut State, pipeline: &Pipeline) -> ControlFlow<i32> {
+    let start = Instant::now(); 
+    let (user, sys) = cpu_times(); 
+    let flow = run_pipeline(state, pipeline, false); 
+    let real = start.elapsed(); 
+    let (user_after, sys_after) = cpu_times(); 
+
+    let format = lookup(state, "TIMEFORMAT").unwrap_or_else(|| TIMEFORMAT.to_string()); 
+    if !format.is_empty() {
+        eprintln!("{}", time_report(&format, real, user_after.saturating_sub(user), sys_after.saturating_sub(sys)));
+    }
+    flow 
+}
+
+///
+/// Runs one pipeline, waiting for its last stage unless in the background 
+///
+/// Inputs: 
+///   Shell state shared across commands, whose last status is updated 
+///   Pipeline to run, its commands connected stdout to stdin 
+///   Whether to run it as a background job in its own process group 
+///
+/// Returns: 
+///   Break with the exit status if exit was specified, Continue else 
+///
+fn run_pipeline(state: &mut State, pipeline: &Pipeline, background: bool) -> ControlFlow<i32> {
+    // The DEBUG trap runs in the shell before each simple command, so 
+    // before any stage starts 
+    for command in &pipeline.commands {
+        if let Command::Simple(_) = command {
+            trap::on_debug(state)?; 
+        }
+    }
+
+    let text = pipeline.to_string(); 
+    let count = pipeline.commands.len(); 
+    // Builtins, functions and control structures run in the shell itself 
+    // only as a lone foreground command, where they can change the shell's 
+    // state 
+    let in_process = count == 1 && !background; 
+
+    // An interactive shell gives every pipeline its own process group so 
+    // the terminal can be handed to it and Ctrl-C/Ctrl-Z reach only it 
+    let interactive = jobs::interactive(); 
+    let own_group = background || interactive; 
+    let take_terminal = interactive && !background; 
+
+    // Every pipe is made before any stage starts, each stage taking its ends 
+    // as it is spawned so the shell holds none of them once all are running. 
+    // Readers then see end of file as soon as their writer exits, like one 
+    // that failed to start or ran nothing 
+    let mut pipes = Vec::with_capacity(count.saturating_sub(1)); 
+    for _ in 1..count {
+        match io::pipe() {
+            Ok((reader, writer)) => pipes.push((Some(OwnedFd::from(reader)), Some(OwnedFd::from(writer)))), 
+            Err(e) => {
+                state.last_status = error::report(state, e); 
+                return ControlFlow::Continue(());
+            }
+        }
+    }
+
+    // Status of the last stage if it was not a process we can wait for 
+    let mut status = None; 
+    // Status of the last such stage before it to fail, for `set -o pipefail` 
+    let mut failed = None; 
+    let mut group: Option<libc::pid_t> = None; 
+    let mut pids = Vec::new(); 
+    
+    for (index, command) in pipeline.commands.iter().enumerate() { 
+        let stdin = index.checked_sub(1).and_then(|previous| pipes[previous].0.take()); 
+        let stdout = pipes.get_mut(index).and_then(|(_, writer)| writer.take()); 
+        // Without a terminal to stop it, a background job must not read 
+        // the shell's own input 
+        let no_input = index == 0 && background && !interactive; 
+
+        let (launch, mut files) = prepare(state, command); 
+        if no_input 
+            && !matches!(launch, Launch::Done(_) | Launch::Exit(_)) 
+            && !files.iter().any(|(fd, _)| *fd == 0) 
+            && let Ok(null) = File::open("/dev/null") {
+            files.insert(0, (0, Source::File(null)));
+        }
+
+        let join = own_group.then_some(group.unwrap_or(0)); 
+        if let Some(code) = status.take() 
+            && code != 0 {
+            failed = Some(code); 
+        }
+        match launch {
+            Launch::Done(code) => status = Some(code), 
+            Launch::Exit(code) => return ControlFlow::Break(code), 
+
+            // `exec` keeps its redirections for the rest of the shell 
+            Launch::Builtin(argv, assignments) if in_process && argv[0] == "exec" => {
+                if let Err(e) = redirect::keep(&files) {
+                    status = Some(error::report(state, e)); 
+                    continue; 
+                }
+                for (fd, source) in &files {
+                    if matches!(source, Source::Closed) {
+                        state.open_fds.remove(fd);
+                    } else if *fd > 2 {
+                        state.open_fds.insert(*fd);
+                    }
+                }
+                match run_builtin_with(state, &argv, assignments) {
+                    ControlFlow::Continue(code) => status = Some(code), 
+                    ControlFlow::Break(code) => return ControlFlow::Break(code),
+                }
+            },
+
+            Launch::Builtin(argv, assignments) if in_process => {
+                let _saved = match SavedFds::apply(files) {
+                    Ok(saved) => saved, 
+                    Err(e) => {
+                        status = Some(error::report(state, e)); 
+                        continue; 
+                    }
+                };
+                match run_builtin_with(state, &argv, assignments) {
+                    ControlFlow::Continue(code) => status = Some(code), 
+                    ControlFlow::Break(code) => return ControlFlow::Break(code),
+                }
+            },
+
+            // Forked like the other stages, so its output goes down the pipe 
+            Launch::Builtin(argv, assignments) => {
+                let others = open_ends(&pipes); 
+                let what = InShell::Builtin(argv, assignments); 
+                match fork_stage(state, what, stdin, stdout, &others, files, join, take_terminal) {
+                    Ok(pid) => {
+                        group.get_or_insert(pid);
+                        pids.push(pid);
+                    },
+                    Err(code) => status = Some(code), 
+                }
+            },
+
+            // A subshell is always forked, so it cannot change the shell 
+            Launch::InShell(what) if in_process && !matches!(what, InShell::Compound(Compound::Subshell(_))) => {
+                let _saved = match SavedFds::apply(files) {
+                    Ok(saved) => saved, 
+                    Err(e) => {
+                        status = Some(error::report(state, e)); 
+                        continue; 
+                    }
+                };
+                run_in_shell(state, what)?; 
+                status = Some(state.last_status); 
+            },
+
+            Launch::InShell(what) => {
+                let others = open_ends(&pipes); 
+                match fork_stage(state, what, stdin, stdout, &others, files, join, take_terminal) {
+                    Ok(pid) => {
+                        group.get_or_insert(pid);
+                        pids.push(pid);
+                    },
+                    Err(code) => status = Some(code), 
+                }
+            },
+
+            Launch::External(external) => {
+                match spawn_external(state, &external, stdin, stdout, files, join, take_terminal) {
+                    Ok(child) => {
+                        let pid = child.id() as libc::pid_t; 
+                        group.get_or_insert(pid);
+                        pids.push(pid);
+                    }, 
+                    Err(code) => status = Some(code), 
+                }
+            }
+        }
+    } 
+
+    // Children are waited on through the jobs table from here on, every 
+    // stage's status being collected there 
+    let Some(leader) = group else {
+        state.last_status = status.unwrap_or(0); 
+        if state.options.pipefail && state.last_status == 0 {
+            state.last_status = failed.unwrap_or(0); 
+        }
+        return ControlFlow::Continue(());
+    };
+
+    // SAFETY: getpgrp has no memory safety requirements 
+    let pgid = if own_group { leader } else { unsafe { libc::getpgrp() } }; 
+    let id = state.jobs.add(pgid, pids, text, state.options.pipefail).id; 
+
+    if background {
+        if let Some(job) = state.jobs.get(id) {
+            state.last_background = Some(job.last_pid()); 
+            // Like bash, scripts start jobs quietly 
+            if interactive {
+                println!("[{}] {}", job.id, job.last_pid());
+            }
+        }
+        state.last_status = 0; 
+        return ControlFlow::Continue(());
+    }
+
+    let waited = if take_terminal {
+        state.jobs.wait_in_foreground(id, false)
+    } else {
+        state.jobs.wait(id)
+    }; 
+
+    // Pipeline status is that of its last stage 
+    let job_status = match waited {
+        Ok(job_state) => builtins::foreground_status(state, id, job_state), 
+        Err(_) => 1, 
+    };
+    state.last_status = status.unwrap_or(job_status); 
+    if state.options.pipefail && state.last_status == 0 {
+        state.last_status = failed.unwrap_or(0); 
+    }
+    
+    ControlFlow::Continue(())
+}
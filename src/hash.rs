@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::*;
+
+use crate::error;
+use crate::platform::{self, is_executable};
+
+///
+/// Every command on `$PATH` by name, so running one does not search the
+/// directories each time
+///
+/// The table is filled by scanning `$PATH` when first used, again whenever
+/// `$PATH` changes, and on `hash -r`/`rehash`
+///
+#[derive(Default)]
+pub struct PathTable {
+    /// `$PATH` the table was built from, None before the first scan
+    path: Option<OsString>,
+    /// Full path of each command, from the first directory that has it
+    commands: HashMap<String, PathBuf>,
+    /// Commands run or hashed since the last rehash, with how many times
+    /// each was run
+    hits: HashMap<String, usize>,
+}
+
+impl PathTable {
+    ///
+    /// Forgets the commands run so far and scans `$PATH` again
+    ///
+    pub fn rehash(&mut self) {
+        self.hits.clear();
+        self.scan();
+    }
+
+    ///
+    /// Scans the directories of `$PATH`, keeping the first of each name
+    ///
+    fn scan(&mut self) {
+        let path = env::var_os("PATH").unwrap_or_default();
+        self.commands.clear();
+
+        for dir in env::split_paths(&path) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                // Follows symlinks, as running the command would
+                if !is_executable(&entry.path()) {
+                    continue;
+                }
+                self.commands.entry(name).or_insert_with(|| entry.path());
+            }
+        }
+        self.path = Some(path);
+    }
+
+    fn current(&mut self) {
+        if self.path.as_deref() != Some(env::var_os("PATH").unwrap_or_default().as_os_str()) {
+            self.rehash();
+        }
+    }
+
+    ///
+    /// Finds the program a command name would run
+    ///
+    /// Input:
+    ///   Command name; one containing `/` is a path and not looked up
+    ///
+    /// Output:
+    ///   Path of the executable file, or None. A command removed or added
+    ///   since the last scan causes a rescan rather than a wrong answer
+    ///
+    pub fn find(&mut self, name: &str) -> Option<PathBuf> {
+        if platform::is_path(name) {
+            let path = PathBuf::from(name);
+            return is_executable(&path).then_some(path);
+        }
+
+        self.current();
+        if let Some(path) = self.commands.get(name)
+            && is_executable(path) {
+            return Some(path.clone());
+        }
+        self.scan();
+        self.commands.get(name).cloned()
+    }
+
+    ///
+    /// Finds the program for a command about to be run, counting the hit
+    ///
+    pub fn lookup(&mut self, name: &str) -> Option<PathBuf> {
+        let path = self.find(name)?;
+        if !platform::is_path(name) {
+            *self.hits.entry(name.to_string()).or_default() += 1;
+        }
+        Some(path)
+    }
+
+    ///
+    /// How many times a command has been run since the last rehash, or
+    /// None if it has not been run or hashed
+    ///
+    pub fn hits(&self, name: &str) -> Option<usize> {
+        self.hits.get(name).copied()
+    }
+
+    ///
+    /// Names of every command on `$PATH`
+    ///
+    pub fn names(&mut self) -> impl Iterator<Item = &String> {
+        self.current();
+        self.commands.keys()
+    }
+
+    ///
+    /// Builtin hash: shows or updates the commands the shell remembers
+    ///
+    /// Input:
+    ///   Arguments after `hash`. With none it lists each command run since
+    ///   the last rehash and its hit count; `-r` forgets them and rescans
+    ///   `$PATH`; names are looked up and remembered
+    ///
+    /// Output:
+    ///   Exit status, 1 if any name was not found
+    ///
+    pub fn builtin_hash(&mut self, args: &[String]) -> i32 {
+        if args.first().is_some_and(|arg| arg == "-r") {
+            self.rehash();
+            return 0;
+        }
+
+        if args.is_empty() {
+            if self.hits.is_empty() {
+                println!("hash: hash table empty");
+                return 0;
+            }
+            let mut remembered: Vec<_> = self.hits.iter().collect();
+            remembered.sort_unstable();
+            println!("hits\tcommand");
+            for (name, hits) in remembered {
+                let path = self.commands.get(name).map_or(name.clone(), |path| path.display().to_string());
+                println!("{hits:4}\t{path}");
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for name in args {
+            match self.find(name) {
+                Some(_) if !platform::is_path(name) => {
+                    self.hits.entry(name.clone()).or_default();
+                }
+                Some(_) => {}
+                None => {
+                    status = error::builtin("hash", format!("{name}: not found"));
+                }
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use crate::platform::is_executable;
+use crate::lexer::is_name;
+use crate::parser::RESERVED;
+use crate::theme::{paint, Theme};
+
+/// Characters that end a word and start an operator
+const OPERATORS: &str = "|&;<>()";
+
+///
+/// Colours a command line for the editor to draw
+///
+/// Command names take the theme's command colour if they would run and its
+/// unknown colour if not, and quoted text and operators have colours of
+/// their own; by default green, red, yellow and cyan. Only escape
+/// sequences are added, so the text takes up as many columns as before
+///
+/// Inputs:
+///   Line as typed so far, which may stop anywhere, even inside quotes
+///   Whether a name is a command the shell knows, such as a builtin or a
+///   program on `$PATH`
+///   Colours to use
+///
+/// Output:
+///   The line with colours added
+///
+pub fn highlight(text: &str, known: impl Fn(&str) -> bool, theme: &Theme) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len() * 2);
+    // Whether the next word names a command, as at the start or after `|`
+    let mut command = true;
+    // Whether the next word is where a redirection points
+    let mut target = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+
+        if OPERATORS.contains(c) {
+            let start = i;
+            while i < chars.len() && OPERATORS.contains(chars[i]) && i - start < 3 {
+                i += 1;
+            }
+            let op: String = chars[start..i].iter().collect();
+            target = op.contains(['<', '>']);
+            command = !target;
+            out.push_str(&paint(&theme.operator, &op));
+            continue;
+        }
+
+        let start = i;
+        let word = scan_word(&chars, &mut i);
+        let colours = colour_word(&chars[start..i], &theme.quote);
+
+        if command && !target {
+            let plain: String = chars[start..i].iter().filter(|c| !"'\"\\".contains(**c)).collect();
+            let assignment = plain
+                .split_once('=')
+                .is_some_and(|(name, _)| is_name(name.strip_suffix('+').unwrap_or(name)));
+            if assignment {
+                out.push_str(&colours);
+            } else {
+                let runs = RESERVED.contains(&plain.as_str())
+                    || known(&plain)
+                    || (plain.contains('/') && is_executable(Path::new(&plain)));
+                out.push_str(&paint(if runs { &theme.command } else { &theme.unknown }, &word));
+                // A reserved word like `then` is followed by another command
+                command = RESERVED.contains(&plain.as_str());
+            }
+        } else {
+            out.push_str(&colours);
+            target = false;
+        }
+    }
+    out
+}
+
+///
+/// Moves past one word, quotes and escapes included
+///
+/// Output:
+///   The word's text as typed
+///
+fn scan_word(chars: &[char], i: &mut usize) -> String {
+    let start = *i;
+    let mut quote = None;
+    while *i < chars.len() {
+        let c = chars[*i];
+        match (c, quote) {
+            ('\\', q) if q != Some('\'') => *i += 1,
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            // An extended pattern such as `@(a|b)` belongs to the word
+            ('?' | '*' | '+' | '@' | '!', None) if chars.get(*i + 1) == Some(&'(') => {
+                let mut depth = 0;
+                *i += 1;
+                while *i < chars.len() {
+                    match chars[*i] {
+                        '(' => depth += 1,
+                        ')' if depth == 1 => break,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    *i += 1;
+                }
+            }
+            (c, None) if c.is_whitespace() || OPERATORS.contains(c) => break,
+            _ => {}
+        }
+        *i += 1;
+    }
+    *i = (*i).min(chars.len());
+    chars[start..*i].iter().collect()
+}
+
+///
+/// Colours the quoted parts of a word that is not a command name
+///
+fn colour_word(chars: &[char], colour: &str) -> String {
+    let (start, reset) = match colour {
+        "" => (String::new(), ""),
+        colour => (format!("\x1b[{colour}m"), "\x1b[0m"),
+    };
+    let mut out = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for &c in chars {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', q) if q != Some('\'') => escaped = true,
+            ('\'' | '"', None) => {
+                quote = Some(c);
+                out.push_str(&start);
+            }
+            (c, Some(q)) if c == q => {
+                quote = None;
+                out.push(c);
+                out.push_str(reset);
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    if quote.is_some() {
+        out.push_str(reset);
+    }
+    out
+}
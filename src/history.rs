@@ -0,0 +1,374 @@
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::pattern::Pattern;
+use crate::platform;
+
+/// Entries kept when `$HISTSIZE` is unset or not a number
+const DEFAULT_SIZE: usize = 1000;
+
+///
+/// Location of the history file, `~/.rust_shell_history`
+///
+fn file_path() -> Option<PathBuf> {
+    platform::home_file(".rust_shell_history")
+}
+
+///
+/// Most entries to keep
+///
+/// Input:
+///   `$HISTSIZE`, a number of entries
+///
+pub fn max_size(size: Option<&str>) -> usize {
+    size.and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_SIZE)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+///
+/// Formats a time as strftime(3) does, for `$HISTTIMEFORMAT`
+///
+/// Inputs:
+///   Seconds since the epoch
+///   Format such as `%F %T `
+///
+pub fn format_time(time: u64, format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut buf = [0u8; 256];
+    // SAFETY: localtime_r only writes into the tm we own, and strftime
+    // writes at most buf.len() bytes into buf
+    let len = unsafe {
+        let time = time as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+///
+/// Lines kept out of the history, from `$HISTCONTROL` and `$HISTIGNORE`
+///
+#[derive(Default)]
+pub struct Filter {
+    /// Lines starting with a space, from `ignorespace` or `ignoreboth`
+    ignore_space: bool,
+    /// Earlier copies of a line are dropped when it is added again, from
+    /// `erasedups`
+    erase_dups: bool,
+    /// Patterns matching whole lines to leave out
+    patterns: Vec<Pattern>,
+}
+
+impl Filter {
+    ///
+    /// Inputs:
+    ///   `$HISTCONTROL`, a colon-separated list of `ignorespace`,
+    ///   `ignoredups`, `ignoreboth` and `erasedups`. Repeats of the last
+    ///   line are left out whatever it says
+    ///   `$HISTIGNORE`, a colon-separated list of patterns
+    ///
+    pub fn new(control: Option<&str>, ignore: Option<&str>) -> Filter {
+        let control: Vec<&str> = control.unwrap_or_default().split(':').collect();
+        Filter {
+            ignore_space: control.iter().any(|word| matches!(*word, "ignorespace" | "ignoreboth")),
+            erase_dups: control.contains(&"erasedups"),
+            patterns: ignore.unwrap_or_default()
+                .split(':')
+                .filter(|pattern| !pattern.is_empty())
+                .map(Pattern::new)
+                .collect(),
+        }
+    }
+
+    fn ignores(&self, line: &str) -> bool {
+        (self.ignore_space && line.starts_with(' ')) || self.patterns.iter().any(|pattern| pattern.matches(line))
+    }
+}
+
+///
+/// Commands entered so far, oldest first, with when they were entered
+///
+/// Once loaded from the history file, every new entry is appended to it as
+/// it is added, so history survives the shell being killed. In the file
+/// each entry follows a `#` line giving its time in seconds since the
+/// epoch, as bash writes it, so an entry that kept a newline, inside
+/// quotes or a here-document, is every line up to the next time
+///
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// When each entry was added, 0 if its file line had no time
+    times: Vec<u64>,
+    /// File entries are saved to, None until loaded
+    file: Option<PathBuf>,
+}
+
+impl History {
+    ///
+    /// Reads the history file and keeps saving to it from now on
+    ///
+    /// A file grown past size entries is cut back to the newest ones. A
+    /// missing or unreadable file starts an empty history
+    ///
+    pub fn load(&mut self, size: usize) {
+        let Some(path) = file_path() else {
+            return;
+        };
+
+        if let Ok(contents) = fs::read_to_string(&path) {
+            let mut time = None;
+            // Whether the last entry had a time, so the lines up to the
+            // next time are more of it
+            let mut timed = false;
+            for line in contents.lines() {
+                if let Some(stamp) = line.strip_prefix('#')
+                    && let Ok(stamp) = stamp.parse() {
+                    time = Some(stamp);
+                    continue;
+                }
+                if time.is_none() && timed
+                    && let Some(entry) = self.entries.last_mut() {
+                    entry.push('\n');
+                    entry.push_str(line);
+                    continue;
+                }
+                timed = time.is_some();
+                self.entries.push(line.to_string());
+                self.times.push(time.take().unwrap_or(0));
+            }
+            if self.trim(size) {
+                self.rewrite(&path);
+            }
+        }
+        self.file = Some(path);
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    ///
+    /// When each entry was added, in seconds since the epoch, 0 if unknown
+    ///
+    pub fn times(&self) -> &[u64] {
+        &self.times
+    }
+
+    ///
+    /// Records an entered line, unless it is blank, repeats the last one or
+    /// is one the filter leaves out, dropping the oldest beyond size entries
+    ///
+    pub fn add(&mut self, line: &str, filter: &Filter, size: usize) {
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) || filter.ignores(line) {
+            return;
+        }
+
+        if filter.erase_dups && self.entries.iter().any(|entry| entry == line) {
+            let times = std::mem::take(&mut self.times);
+            (self.entries, self.times) = self.entries.drain(..)
+                .zip(times)
+                .filter(|(entry, _)| entry != line)
+                .unzip();
+            if let Some(path) = &self.file {
+                self.rewrite(path);
+            }
+        }
+
+        let time = now();
+        self.entries.push(line.to_string());
+        self.times.push(time);
+        self.trim(size);
+
+        // Not being able to save history should never get in the way of a command
+        if let Some(path) = &self.file
+            && let Ok(mut file) = OpenOptions::new().append(true).create(true).open(path) {
+            let _ = writeln!(file, "#{time}\n{line}");
+        }
+    }
+
+    ///
+    /// Forgets every entry, in the history file too
+    ///
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.times.clear();
+        if let Some(path) = &self.file {
+            let _ = fs::write(path, "");
+        }
+    }
+
+    ///
+    /// Drops the oldest entries beyond size
+    ///
+    /// Output:
+    ///   true if any were dropped
+    ///
+    fn trim(&mut self, size: usize) -> bool {
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+        self.times.drain(..excess);
+        excess > 0
+    }
+
+    fn rewrite(&self, path: &Path) {
+        let contents: String = self.entries.iter()
+            .zip(&self.times)
+            .map(|(entry, time)| match time {
+                0 => format!("{entry}\n"),
+                time => format!("#{time}\n{entry}\n"),
+            })
+            .collect();
+        let _ = fs::write(path, contents);
+    }
+
+    ///
+    /// Finds the entry a `!` event refers to
+    ///
+    /// Input:
+    ///   Event after the `!`: `!` for the last entry, `n` for entry n,
+    ///   `-n` for the nth last, otherwise a prefix of the newest entry that
+    ///   starts with it
+    ///
+    fn event(&self, event: &str) -> Option<&String> {
+        let count = self.entries.len();
+        let index = if event == "!" {
+            count.checked_sub(1)?
+        } else if let Some(back) = event.strip_prefix('-') {
+            count.checked_sub(back.parse().ok()?)?
+        } else if let Ok(number) = event.parse::<usize>() {
+            number.checked_sub(1).filter(|&index| index < count)?
+        } else {
+            return self.entries.iter().rev().find(|entry| entry.starts_with(event));
+        };
+        self.entries.get(index)
+    }
+
+    ///
+    /// Replaces history references in a line before it is parsed, as csh
+    /// does
+    ///
+    /// `!!` is the last command, `!n` entry n, `!-n` the nth last and
+    /// `!prefix` the newest starting with prefix. `!$`, `!^` and `!*` are
+    /// the last argument, the first, and all of them, of the last command.
+    /// A `!` in single quotes, after a backslash, or before a space, `=`
+    /// or `(` is left alone
+    ///
+    /// Input:
+    ///   Line as entered
+    ///
+    /// Output:
+    ///   The line with references replaced, None if it had none, or the
+    ///   message to report for an event that is not in the history
+    ///
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let mut out = String::with_capacity(line.len());
+        let mut chars = line.chars().peekable();
+        let (mut single, mut double, mut expanded) = (false, false, false);
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if !single => {
+                    out.push(c);
+                    out.extend(chars.next());
+                    continue;
+                }
+                '\'' if !double => single = !single,
+                '"' if !single => double = !double,
+                '!' if !single => {
+                    let Some(&next) = chars.peek() else {
+                        out.push(c);
+                        continue;
+                    };
+                    // `$!` is a parameter, not a reference
+                    if next.is_whitespace() || matches!(next, '=' | '(' | '"') || out.ends_with('$') {
+                        out.push(c);
+                        continue;
+                    }
+
+                    let last = || self.entries.last().ok_or_else(|| format!("!{next}: event not found"));
+                    let text = match next {
+                        '$' | '^' | '*' => {
+                            chars.next();
+                            let words = words(last()?);
+                            let args = words.get(1..).unwrap_or_default();
+                            match next {
+                                '$' => words.last().cloned().unwrap_or_default(),
+                                '^' => args.first().cloned().unwrap_or_default(),
+                                _ => args.join(" "),
+                            }
+                        }
+                        _ => {
+                            let mut event = String::new();
+                            if next == '!' || next == '-' {
+                                event.push(next);
+                                chars.next();
+                            }
+                            if event != "!" {
+                                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !";|&<>()'\"".contains(c)) {
+                                    event.push(c);
+                                }
+                            }
+                            self.event(&event)
+                                .ok_or_else(|| format!("!{event}: event not found"))?
+                                .clone()
+                        }
+                    };
+                    out.push_str(&text);
+                    expanded = true;
+                    continue;
+                }
+                _ => {}
+            }
+            out.push(c);
+        }
+
+        Ok(expanded.then_some(out))
+    }
+}
+
+///
+/// Splits an entry into words at unquoted blanks, keeping the quotes
+///
+fn words(entry: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = entry.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', q) if q != Some('\'') => {
+                word.push(c);
+                word.extend(chars.next());
+                continue;
+            }
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
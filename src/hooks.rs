@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+use crate::error;
+use crate::exec::call_function;
+use crate::State;
+
+///
+/// Points in the shell's life that run hooks
+///
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Event {
+    /// Before each prompt is drawn
+    Precmd,
+    /// After a line is read at the prompt and before it runs
+    Preexec,
+    /// After `cd`, `pushd`, `popd` or `j` changes the current directory
+    Chpwd,
+}
+
+impl Event {
+    /// Every event, in the order `hook` lists them
+    const ALL: [Event; 3] = [Event::Precmd, Event::Preexec, Event::Chpwd];
+
+    ///
+    /// Name of the event, which is also the function it runs
+    ///
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Precmd => "precmd",
+            Event::Preexec => "preexec",
+            Event::Chpwd => "chpwd",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Event> {
+        Event::ALL.into_iter().find(|event| event.name() == name)
+    }
+}
+
+///
+/// Extends the shell from Rust, with code called on each event
+///
+/// Register one with `Shell::add_plugin` before passing the shell to
+/// `start_with`. Every method does nothing unless it is overridden
+///
+pub trait Plugin {
+    fn precmd(&mut self, _state: &mut State) {}
+
+    ///
+    /// Input:
+    ///   Line about to run, without its newline
+    ///
+    fn preexec(&mut self, _state: &mut State, _command: &str) {}
+
+    fn chpwd(&mut self, _state: &mut State) {}
+}
+
+///
+/// A prompt segment worked out in Rust, drawn where the prompt has
+/// `\{name}` for the name it was added with by `Shell::add_segment`
+///
+pub trait Segment {
+    fn render(&mut self, state: &State) -> String;
+}
+
+///
+/// Hooks registered from the rc file with `hook` and from Rust
+///
+#[derive(Default)]
+pub struct Hooks {
+    /// Functions added with `hook`, for each event
+    functions: HashMap<Event, Vec<String>>,
+    plugins: Vec<Box<dyn Plugin>>,
+    segments: HashMap<String, Box<dyn Segment>>,
+    /// A hook is running, so what it does runs no further hooks
+    running: bool,
+}
+
+impl Hooks {
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn add_segment(&mut self, name: &str, segment: Box<dyn Segment>) {
+        self.segments.insert(name.to_string(), segment);
+    }
+}
+
+///
+/// Runs the hooks for an event: the plugins first, then the function named
+/// after the event if there is one, then those added with `hook`, in the
+/// order they were added. Hooks leave `$?` as it was
+///
+/// Inputs:
+///   Shell state holding the hooks
+///   Event that happened
+///   Arguments for the hook functions: the command line for preexec
+///
+/// Output:
+///   Break with the exit status if a hook exited the shell
+///
+pub fn run(state: &mut State, event: Event, args: &[String]) -> ControlFlow<i32> {
+    if state.hooks.running {
+        return ControlFlow::Continue(());
+    }
+    state.hooks.running = true;
+    let status = state.last_status;
+
+    let mut plugins = std::mem::take(&mut state.hooks.plugins);
+    for plugin in &mut plugins {
+        match event {
+            Event::Precmd => plugin.precmd(state),
+            Event::Preexec => plugin.preexec(state, args.first().map_or("", String::as_str)),
+            Event::Chpwd => plugin.chpwd(state),
+        }
+    }
+    state.hooks.plugins = plugins;
+
+    let names = std::iter::once(event.name().to_string())
+        .chain(state.hooks.functions.get(&event).into_iter().flatten().cloned());
+    let mut flow = ControlFlow::Continue(());
+    for name in names.collect::<Vec<_>>() {
+        let Some(body) = state.functions.get(&name).map(Rc::clone) else {
+            continue;
+        };
+        let argv = std::iter::once(name).chain(args.iter().cloned()).collect();
+        flow = call_function(state, &body, argv);
+        if flow.is_break() {
+            break;
+        }
+    }
+
+    state.hooks.running = false;
+    state.last_status = status;
+    flow
+}
+
+///
+/// Text of a prompt segment added from Rust, None if there is none by
+/// that name
+///
+pub fn segment(state: &mut State, name: &str) -> Option<String> {
+    let mut segment = state.hooks.segments.remove(name)?;
+    let text = segment.render(state);
+    state.hooks.segments.insert(name.to_string(), segment);
+    Some(text)
+}
+
+///
+/// Builtin hook: runs functions on shell events
+///
+/// `hook EVENT FUNCTION...` adds functions to run on an event, `hook -d
+/// EVENT FUNCTION...` takes them off again, and `hook` on its own lists
+/// them. The events are precmd, before each prompt; preexec, before a
+/// line typed at the prompt runs, given the line as `$1`; and chpwd,
+/// after the current directory changes. A function named after an event
+/// runs on it without being added
+///
+/// Output:
+///   Exit status, 1 for an unknown event, 2 for bad usage
+///
+pub fn builtin_hook(state: &mut State, args: &[String]) -> i32 {
+    let (remove, args) = match args.split_first() {
+        Some((flag, rest)) if flag == "-d" => (true, rest),
+        _ => (false, args),
+    };
+
+    let [event, functions @ ..] = args else {
+        if remove {
+            return error::usage("hook", "usage: hook [-d] event function...");
+        }
+        for event in Event::ALL {
+            for function in state.hooks.functions.get(&event).into_iter().flatten() {
+                println!("hook {} {function}", event.name());
+            }
+        }
+        return 0;
+    };
+    let Some(event) = Event::parse(event) else {
+        let names: Vec<&str> = Event::ALL.iter().map(|event| event.name()).collect();
+        return error::builtin("hook", format!("{event}: unknown event, expected one of {}", names.join(", ")));
+    };
+    if functions.is_empty() {
+        return error::usage("hook", "usage: hook [-d] event function...");
+    }
+
+    let added = state.hooks.functions.entry(event).or_default();
+    for function in functions {
+        if remove {
+            added.retain(|name| name != function);
+        } else if !added.contains(function) {
+            added.push(function.clone());
+        }
+    }
+    0
+}
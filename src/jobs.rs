@@ -0,0 +1,543 @@
+use std::io;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use libc::pid_t;
+
+use crate::signals;
+
+///
+/// What a job is doing as last seen by waitpid
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Finished, with the exit status of its last process
+    Done(i32),
+}
+
+///
+/// One process of a job and how it last changed
+///
+struct Process {
+    pid: pid_t,
+    /// Exit status once it has finished
+    status: Option<i32>,
+    /// Signal that killed it, if one did
+    signal: Option<i32>,
+    stopped: bool,
+}
+
+///
+/// A pipeline running in its own process group
+///
+pub struct Job {
+    pub id: usize,
+    pub pgid: pid_t,
+    pub command: String,
+    pub state: JobState,
+    /// Stage processes in pipeline order
+    procs: Vec<Process>,
+    /// Whether the job's status is that of its last failing stage, for
+    /// `set -o pipefail`, rather than of its last stage
+    pipefail: bool,
+    /// Signal that killed the stage the job's status comes from
+    signal: Option<i32>,
+    /// State last shown to the user, so each change is reported once
+    reported: JobState,
+    /// Terminal settings the job had when it stopped in the foreground,
+    /// given back to it by `fg`
+    modes: Option<libc::termios>,
+}
+
+impl Job {
+    ///
+    /// Records a status change reported by waitpid
+    ///
+    /// Output:
+    ///   true if the pid belongs to this job
+    ///
+    fn update(&mut self, pid: pid_t, raw: i32) -> bool {
+        let Some(proc) = self.procs.iter_mut().find(|p| p.pid == pid) else {
+            return false;
+        };
+
+        if libc::WIFSTOPPED(raw) {
+            proc.stopped = true;
+        } else if libc::WIFCONTINUED(raw) {
+            proc.stopped = false;
+        } else {
+            proc.status = Some(decode_status(raw));
+            proc.signal = libc::WIFSIGNALED(raw).then(|| libc::WTERMSIG(raw));
+        }
+
+        // Done once every stage is, stopped if any unfinished stage is
+        self.state = if self.procs.iter().all(|p| p.status.is_some()) {
+            let mut finished = self.procs.iter().rev();
+            let last = if self.pipefail {
+                finished.find(|p| p.status != Some(0))
+            } else {
+                finished.next()
+            };
+            self.signal = last.and_then(|p| p.signal);
+            JobState::Done(last.and_then(|p| p.status).unwrap_or(0))
+        } else if self.procs.iter().any(|p| p.status.is_none() && p.stopped) {
+            JobState::Stopped
+        } else {
+            JobState::Running
+        };
+
+        true
+    }
+
+    ///
+    /// Pid of the last stage, which `$!` and `[1] pid` refer to
+    ///
+    pub fn last_pid(&self) -> pid_t {
+        self.procs.last().map_or(self.pgid, |p| p.pid)
+    }
+
+    pub fn pids(&self) -> impl Iterator<Item = pid_t> + '_ {
+        self.procs.iter().map(|p| p.pid)
+    }
+
+    ///
+    /// Status column of `jobs`, e.g. `Running`, `Exit 1`, or `Terminated`
+    /// for a job killed by a signal
+    ///
+    pub fn state_label(&self) -> String {
+        match (self.state, self.signal) {
+            (JobState::Running, _) => "Running".to_string(),
+            (JobState::Stopped, _) => "Stopped".to_string(),
+            (JobState::Done(_), Some(sig)) => signals::description(sig),
+            (JobState::Done(0), None) => "Done".to_string(),
+            (JobState::Done(code), None) => format!("Exit {code}"),
+        }
+    }
+}
+
+///
+/// Converts a raw waitpid status to a shell exit status, 128 + n for a
+/// process killed by signal n
+///
+pub fn decode_status(raw: i32) -> i32 {
+    if libc::WIFEXITED(raw) {
+        libc::WEXITSTATUS(raw)
+    } else if libc::WIFSIGNALED(raw) {
+        128 + libc::WTERMSIG(raw)
+    } else {
+        1
+    }
+}
+
+///
+/// Table of jobs started by this shell
+///
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    /// Job ids from least to most recently current; the last is `%+`
+    recent: Vec<usize>,
+}
+
+impl Jobs {
+    ///
+    /// Adds a freshly spawned pipeline as the current job
+    ///
+    /// Inputs:
+    ///   Process group every stage was put in
+    ///   Pids of the stages in pipeline order
+    ///   Command text to show in `jobs`
+    ///   Whether any failing stage fails the job, as with `set -o pipefail`
+    ///
+    /// Output:
+    ///   The new job
+    ///
+    pub fn add(&mut self, pgid: pid_t, pids: Vec<pid_t>, command: String, pipefail: bool) -> &Job {
+        let id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        let procs = pids.into_iter()
+            .map(|pid| Process { pid, status: None, signal: None, stopped: false })
+            .collect();
+
+        let state = JobState::Running;
+        self.jobs.push(Job { id, pgid, command, state, procs, pipefail, signal: None, reported: state, modes: None });
+        self.touch(id);
+        self.jobs.last().unwrap()
+    }
+
+    ///
+    /// Makes a job the current one (`%+`)
+    ///
+    pub fn touch(&mut self, id: usize) {
+        self.recent.retain(|&r| r != id);
+        self.recent.push(id);
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    ///
+    /// Id of the job a process belongs to
+    ///
+    pub fn with_pid(&self, pid: pid_t) -> Option<usize> {
+        self.jobs.iter().find(|j| j.pids().any(|p| p == pid)).map(|j| j.id)
+    }
+
+    ///
+    /// Marker shown after the job number: `+` current, `-` previous
+    ///
+    pub fn marker(&self, id: usize) -> char {
+        let mut recent = self.recent.iter().rev();
+        if recent.next() == Some(&id) {
+            '+'
+        } else if recent.next() == Some(&id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    ///
+    /// Records that a job's current state has been shown to the user
+    ///
+    pub fn seen(&mut self, id: usize) {
+        if let Some(job) = self.get_mut(id) {
+            job.reported = job.state;
+        }
+    }
+
+    ///
+    /// Jobs that finished or stopped since they were last shown, marking
+    /// them as shown
+    ///
+    pub fn unreported(&mut self) -> Vec<usize> {
+        let mut ids = Vec::new();
+        for job in &mut self.jobs {
+            if job.state != job.reported && job.state != JobState::Running {
+                job.reported = job.state;
+                ids.push(job.id);
+            }
+        }
+        ids
+    }
+
+    ///
+    /// Drops a job from the table
+    ///
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|j| j.id == id)?;
+        self.recent.retain(|&r| r != id);
+        Some(self.jobs.remove(index))
+    }
+
+    ///
+    /// Resolves a job spec to a job id
+    ///
+    /// Input:
+    ///   `%n`, `%%`/`%+` (current), `%-` (previous), `%prefix` (command
+    ///   starting with prefix), or None for the current job
+    ///
+    /// Output:
+    ///   Job id, or the message to report if there is no such job
+    ///
+    pub fn resolve(&self, spec: Option<&str>) -> Result<usize, String> {
+        let text = spec.unwrap_or("%+");
+        let body = text.strip_prefix('%').unwrap_or(text);
+
+        let found = match body {
+            "" | "%" | "+" => self.recent.last().copied(),
+            "-" => self.recent.iter().rev().nth(1).copied(),
+            n if n.bytes().all(|b| b.is_ascii_digit()) => {
+                n.parse().ok().filter(|&id| self.get(id).is_some())
+            }
+            prefix => self.jobs.iter()
+                .rev()
+                .find(|j| j.command.starts_with(prefix))
+                .map(|j| j.id),
+        };
+
+        found.ok_or_else(|| match spec {
+            None => "no current job".to_string(),
+            Some(spec) => format!("{spec}: no such job"),
+        })
+    }
+
+    ///
+    /// Collects status changes of any children without blocking
+    ///
+    /// Children that belong to no job (stages of foreground pipelines that
+    /// were never waited on) are simply reaped
+    ///
+    pub fn reap(&mut self) {
+        let mut raw = 0;
+        let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+
+        loop {
+            // SAFETY: waitpid only writes through the status pointer we own
+            let pid = unsafe { libc::waitpid(-1, &mut raw, flags) };
+            if pid <= 0 {
+                break;
+            }
+            self.update(pid, raw);
+        }
+    }
+
+    fn update(&mut self, pid: pid_t, raw: i32) {
+        let stopped = self.jobs.iter_mut()
+            .find_map(|job| job.update(pid, raw).then_some((job.id, job.state)));
+
+        if let Some((id, JobState::Stopped)) = stopped {
+            self.touch(id);
+        }
+    }
+
+    ///
+    /// Waits on a job in the foreground until it finishes or stops
+    ///
+    /// Each stage is waited on by pid, so this also works for a job that
+    /// shares the shell's process group. A Ctrl-C that reaches the shell
+    /// instead of the job (the job is not on the terminal) is passed on to
+    /// the job's group
+    ///
+    /// Output:
+    ///   State the job ended up in. A finished job is removed from the table
+    ///
+    pub fn wait(&mut self, id: usize) -> io::Result<JobState> {
+        loop {
+            let Some(job) = self.get(id) else {
+                return Ok(JobState::Done(0));
+            };
+            let pid = match job.state {
+                JobState::Running => job.procs.iter()
+                    .find(|p| p.status.is_none())
+                    .map_or(job.pgid, |p| p.pid),
+                JobState::Done(_) => {
+                    let state = job.state;
+                    self.remove(id);
+                    return Ok(state);
+                }
+                JobState::Stopped => return Ok(JobState::Stopped),
+            };
+            let pgid = job.pgid;
+
+            let mut raw = 0;
+            // SAFETY: waitpid only writes through the status pointer we own
+            let reaped = unsafe { libc::waitpid(pid, &mut raw, libc::WUNTRACED) };
+            if reaped < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    // SAFETY: getpgrp and kill have no memory safety requirements
+                    if signals::take_interrupt() && pgid != unsafe { libc::getpgrp() } {
+                        unsafe { libc::kill(-pgid, libc::SIGINT) };
+                    }
+                    continue;
+                }
+                // Nothing left to wait on; whatever was unreaped is gone
+                self.remove(id);
+                return Err(err);
+            }
+            self.update(reaped, raw);
+        }
+    }
+
+    ///
+    /// Runs a job in the foreground: hands it the terminal, waits until it
+    /// finishes or stops, then takes the terminal back
+    ///
+    /// A job that stops keeps the terminal settings it had, as an editor
+    /// in raw mode does, and the shell's own settings are put back; they
+    /// are swapped again when the job is resumed
+    ///
+    /// Inputs:
+    ///   Job to wait for
+    ///   Whether to continue it first, as `fg` does for a stopped job
+    ///
+    /// Output:
+    ///   State the job ended up in, as for wait
+    ///
+    pub fn wait_in_foreground(&mut self, id: usize, resume: bool) -> io::Result<JobState> {
+        let Some(job) = self.get_mut(id) else {
+            return Ok(JobState::Done(0));
+        };
+        let pgid = job.pgid;
+        if resume
+            && let Some(modes) = job.modes.take()
+            && interactive() {
+            // SAFETY: modes holds settings tcgetattr returned
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &modes) };
+        }
+
+        give_terminal(pgid);
+        let waited = if resume { self.resume(id) } else { Ok(()) }.and_then(|_| self.wait(id));
+        if let Ok(JobState::Stopped) = waited
+            && let Some(job) = self.get_mut(id) {
+            job.modes = terminal_modes();
+        }
+        reclaim_terminal();
+        waited
+    }
+
+    ///
+    /// Sends SIGCONT to a job's process group and marks it running
+    ///
+    pub fn resume(&mut self, id: usize) -> io::Result<()> {
+        let Some(job) = self.get_mut(id) else {
+            return Ok(());
+        };
+
+        // SAFETY: kill has no memory safety requirements
+        if unsafe { libc::kill(-job.pgid, libc::SIGCONT) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        job.state = JobState::Running;
+        job.reported = JobState::Running;
+        for proc in &mut job.procs {
+            proc.stopped = false;
+        }
+        self.touch(id);
+        Ok(())
+    }
+}
+
+/// Set when the shell runs commands it was given rather than typed ones
+static NONINTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Terminal settings of the interactive shell, put back whenever it takes
+/// the terminal from a job
+static SHELL_MODES: OnceLock<libc::termios> = OnceLock::new();
+
+/// Process group the shell was started in, 0 until take_control moves it
+/// out of it
+static ORIGINAL_GROUP: AtomicI32 = AtomicI32::new(0);
+
+///
+/// Whether the shell's stdin is a terminal, i.e. it is interactive.
+/// Never true once set_noninteractive has been called
+///
+pub fn interactive() -> bool {
+    // SAFETY: isatty has no memory safety requirements
+    !NONINTERACTIVE.load(Ordering::Relaxed) && unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+///
+/// Marks the shell as not interactive even on a terminal, as for a script,
+/// `-c` command or command substitution subshell: its pipelines stay in
+/// its process group and never take the terminal
+///
+pub fn set_noninteractive() {
+    NONINTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+///
+/// Runs f with the shell not interactive, as set_noninteractive makes it,
+/// and then puts back whether it was, for a shell embedded in a program
+/// whose stdin may be a terminal it never meant the shell to take
+///
+pub fn without_terminal<T>(f: impl FnOnce() -> T) -> T {
+    let was = NONINTERACTIVE.swap(true, Ordering::Relaxed);
+    let result = f();
+    NONINTERACTIVE.store(was, Ordering::Relaxed);
+    result
+}
+
+///
+/// Makes a process group the terminal's foreground group
+///
+/// A shell in the background would get SIGTTOU for doing this, so the
+/// signal is blocked around the call. Does nothing without a terminal
+///
+pub fn give_terminal(pgid: pid_t) {
+    if !interactive() {
+        return;
+    }
+
+    // SAFETY: the sigset is initialized by sigemptyset before use, and the
+    // old mask is restored before returning
+    unsafe {
+        let mut block: libc::sigset_t = std::mem::zeroed();
+        let mut old: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut block);
+        libc::sigaddset(&mut block, libc::SIGTTOU);
+        libc::sigprocmask(libc::SIG_BLOCK, &block, &mut old);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::sigprocmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+    }
+}
+
+///
+/// Hands the terminal back to the shell's own process group, with the
+/// settings the shell started with
+///
+pub fn reclaim_terminal() {
+    // SAFETY: getpgrp has no memory safety requirements
+    give_terminal(unsafe { libc::getpgrp() });
+    if interactive()
+        && let Some(modes) = SHELL_MODES.get() {
+        // SAFETY: modes holds settings tcgetattr returned
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes) };
+    }
+}
+
+///
+/// Current settings of the terminal, None without one
+///
+fn terminal_modes() -> Option<libc::termios> {
+    // SAFETY: tcgetattr fills the zeroed struct before it is read
+    let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+    (unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut modes) } == 0).then_some(modes)
+}
+
+///
+/// Sets up an interactive shell to control the terminal
+///
+/// A shell started in the background waits, stopped by SIGTTIN, until it
+/// is brought to the foreground. It then moves to a process group of its
+/// own, so the jobs it starts are not mixed up with whatever started it,
+/// takes the terminal and remembers the terminal's settings. Must run
+/// before the job control signals are ignored
+///
+pub fn take_control() {
+    if !interactive() {
+        return;
+    }
+
+    // SAFETY: getpgrp, tcgetpgrp, kill, getpid and setpgid have no memory
+    // safety requirements
+    unsafe {
+        while libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() {
+            libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+        }
+        // A session leader already leads its own group
+        let pid = libc::getpid();
+        let group = libc::getpgrp();
+        if group != pid && libc::setpgid(pid, pid) == 0 {
+            ORIGINAL_GROUP.store(group, Ordering::Relaxed);
+        }
+    }
+    reclaim_terminal();
+    if let Some(modes) = terminal_modes() {
+        SHELL_MODES.set(modes).ok();
+    }
+}
+
+///
+/// Gives the terminal back to the process group the shell was started in,
+/// as the shell exits
+///
+pub fn release_control() {
+    let group = ORIGINAL_GROUP.load(Ordering::Relaxed);
+    if group != 0 {
+        give_terminal(group);
+    }
+}
@@ -0,0 +1,247 @@
+use std::env;
+use std::fs;
+use std::path::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error;
+use crate::platform;
+
+/// Most directories remembered before the lowest ranked are dropped
+const MAX_ENTRIES: usize = 500;
+
+///
+/// One remembered directory with its visit count and last visit time
+///
+struct Entry {
+    path: String,
+    visits: u64,
+    last: u64,
+}
+
+impl Entry {
+    ///
+    /// Frecency of the entry, visits weighted by how recent the last one was
+    ///
+    fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last);
+        let weight = match age {
+            0..3_600       => 4.0,
+            3_600..86_400  => 2.0,
+            86_400..604_800 => 0.5,
+            _              => 0.25,
+        };
+        self.visits as f64 * weight
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+///
+/// Location of the database, `~/.rust_shell_jump`
+///
+fn db_path() -> Option<PathBuf> {
+    platform::home_file(".rust_shell_jump")
+}
+
+///
+/// Reads the database, one `path\tvisits\tlast` line per directory.
+/// Missing files and malformed lines are treated as empty
+///
+fn load() -> Vec<Entry> {
+    let Some(contents) = db_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    contents.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let path   = fields.next()?.to_string();
+            let visits = fields.next()?.parse().ok()?;
+            let last   = fields.next()?.parse().ok()?;
+            Some(Entry { path, visits, last })
+        })
+        .collect()
+}
+
+fn save(entries: &[Entry]) {
+    let Some(path) = db_path() else {
+        return;
+    };
+
+    let contents: String = entries.iter()
+        .map(|e| format!("{}\t{}\t{}\n", e.path, e.visits, e.last))
+        .collect();
+
+    // Failing to remember a directory should never get in the way of cd
+    let _ = fs::write(path, contents);
+}
+
+///
+/// Records a visit to a directory
+///
+/// Input:
+///   Directory just changed into. Should be absolute so the same directory
+///   is not remembered under several names
+///
+pub fn record(dir: &Path) {
+    let dir = dir.to_string_lossy();
+    let now = now();
+    let mut entries = load();
+
+    match entries.iter_mut().find(|e| e.path == dir) {
+        Some(entry) => {
+            entry.visits += 1;
+            entry.last = now;
+        }
+        None => entries.push(Entry { path: dir.into_owned(), visits: 1, last: now }),
+    }
+
+    if entries.len() > MAX_ENTRIES {
+        entries.sort_by(|a, b| b.score(now).total_cmp(&a.score(now)));
+        entries.truncate(MAX_ENTRIES);
+    }
+
+    save(&entries);
+}
+
+///
+/// Finds the best remembered directory for a fragment
+///
+/// Input:
+///   Bookmark name, or substring to look for anywhere in the remembered path
+///
+/// Output:
+///   The bookmarked directory, if the fragment is a bookmark name. Otherwise
+///   the highest frecency directory containing the fragment that still exists,
+///   skipping the current directory so repeated jumps make progress
+///
+pub fn best_match(fragment: &str) -> Option<PathBuf> {
+    if let Some(dir) = bookmark(fragment) {
+        return Some(dir);
+    }
+
+    let now = now();
+    let cwd = env::current_dir().ok();
+
+    load().into_iter()
+        .filter(|e| e.path.contains(fragment))
+        .filter(|e| cwd.as_deref() != Some(Path::new(&e.path)))
+        .filter(|e| Path::new(&e.path).is_dir())
+        .max_by(|a, b| a.score(now).total_cmp(&b.score(now)))
+        .map(|e| PathBuf::from(e.path))
+}
+
+///
+/// Location of the named bookmarks, `~/.rust_shell_bookmarks`
+///
+fn bookmarks_path() -> Option<PathBuf> {
+    platform::home_file(".rust_shell_bookmarks")
+}
+
+///
+/// Reads the bookmarks, one `name\tpath` line each, sorted by name
+///
+pub fn bookmarks() -> Vec<(String, String)> {
+    let Some(contents) = bookmarks_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    let mut marks: Vec<(String, String)> = contents.lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, path)| (name.to_string(), path.to_string()))
+        .collect();
+    marks.sort();
+    marks
+}
+
+fn save_bookmarks(marks: &[(String, String)]) -> std::io::Result<()> {
+    let Some(path) = bookmarks_path() else {
+        return Err(std::io::Error::other("HOME not set"));
+    };
+
+    let contents: String = marks.iter()
+        .map(|(name, path)| format!("{name}\t{path}\n"))
+        .collect();
+    fs::write(path, contents)
+}
+
+///
+/// Directory a bookmark names, None if there is no such bookmark
+///
+pub fn bookmark(name: &str) -> Option<PathBuf> {
+    bookmarks().into_iter()
+        .find(|(mark, _)| mark == name)
+        .map(|(_, path)| PathBuf::from(path))
+}
+
+///
+/// Runs the `bookmark` builtin
+///
+/// `bookmark` lists every bookmark, `bookmark NAME [DIR]` names DIR, or
+/// the current directory, so `j NAME` goes straight there, and
+/// `bookmark -d NAME...` forgets names
+///
+/// Output:
+///   Exit status: 1 if a bookmark could not be saved or found, 2 for bad
+///   usage
+///
+pub fn builtin_bookmark(args: &[String]) -> i32 {
+    let mut marks = bookmarks();
+
+    match args {
+        [] => {
+            for (name, path) in &marks {
+                println!("{name}\t{path}");
+            }
+            return 0;
+        }
+        [flag, names @ ..] if flag == "-d" => {
+            let mut status = 0;
+            for name in names {
+                match marks.iter().position(|(mark, _)| mark == name) {
+                    Some(index) => {
+                        marks.remove(index);
+                    }
+                    None => {
+                        status = error::builtin("bookmark", format!("{name}: no such bookmark"));
+                    }
+                }
+            }
+            if let Err(e) = save_bookmarks(&marks) {
+                return error::builtin("bookmark", error::cause(&e));
+            }
+            return status;
+        }
+        [name, ..] if name.is_empty() || name.starts_with('-') || name.contains(['/', '\t', '\n']) => {
+            return error::usage("bookmark", format!("{name}: invalid bookmark name"));
+        }
+        [_] | [_, _] => {}
+        _ => return error::usage("bookmark", "usage: bookmark [-d] [name [directory]]"),
+    }
+
+    // Stored absolute, so the bookmark works from anywhere
+    let dir = args.get(1).map_or_else(env::current_dir, |dir| {
+        fs::canonicalize(shellexpand::tilde(dir).as_ref())
+    });
+    let dir = match dir {
+        Ok(dir) if dir.is_dir() => dir,
+        Ok(dir) => return error::builtin("bookmark", format!("{}: not a directory", dir.display())),
+        Err(e) => {
+            let dir = args.get(1).map_or(".", String::as_str);
+            return error::builtin("bookmark", format!("{dir}: {}", error::cause(&e)));
+        }
+    };
+
+    let name = &args[0];
+    marks.retain(|(mark, _)| mark != name);
+    marks.push((name.clone(), dir.to_string_lossy().into_owned()));
+    if let Err(e) = save_bookmarks(&marks) {
+        return error::builtin("bookmark", error::cause(&e));
+    }
+    0
+}
@@ -0,0 +1,745 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+///
+/// How a piece of a word was quoted, which decides what expansion may do
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quoting {
+    /// Unquoted: tilde, variables and globs all expand
+    Bare,
+    /// Inside "...": variables expand, globs stay literal
+    Double,
+    /// Inside '...' or escaped with a backslash: taken exactly as written
+    Single,
+}
+
+///
+/// Run of characters in a word sharing the same quoting
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    pub quoting: Quoting,
+}
+
+///
+/// One shell word, e.g. `"$HOME"/*.txt` is a Double segment then a Bare one.
+/// Quotes themselves are already removed from the segment text
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Word {
+    pub segments: Vec<Segment>,
+}
+
+impl Word {
+    ///
+    /// Unquoted word made of the given text
+    ///
+    pub fn bare(text: &str) -> Word {
+        Word { segments: vec![Segment { text: text.to_string(), quoting: Quoting::Bare }] }
+    }
+
+    fn push(&mut self, c: char, quoting: Quoting) {
+        match self.segments.last_mut() {
+            Some(seg) if seg.quoting == quoting => seg.text.push(c),
+            _ => self.segments.push(Segment { text: c.to_string(), quoting }),
+        }
+    }
+
+    ///
+    /// Registers a quoted region even if it held nothing, so `""` is still
+    /// a word (an empty argument) rather than no word at all
+    ///
+    fn mark_quoted(&mut self, quoting: Quoting) {
+        if self.segments.last().is_none_or(|seg| seg.quoting != quoting) {
+            self.segments.push(Segment { text: String::new(), quoting });
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    fn ends_with_bare(&self, c: char) -> bool {
+        self.segments.last().is_some_and(|seg| seg.quoting == Quoting::Bare && seg.text.ends_with(c))
+    }
+
+    ///
+    /// File descriptor number if the word is an unquoted run of digits,
+    /// as in the `2` of `2>err.txt`
+    ///
+    fn as_fd(&self) -> Option<i32> {
+        match self.segments.as_slice() {
+            [seg] if seg.quoting == Quoting::Bare
+                && seg.text.bytes().all(|b| b.is_ascii_digit()) => seg.text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Text of the word if it is entirely unquoted, as an alias name must be
+    ///
+    pub fn bare_text(&self) -> Option<&str> {
+        match self.segments.as_slice() {
+            [seg] if seg.quoting == Quoting::Bare => Some(&seg.text),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Splits a `NAME=value` word into the name and the value still to be
+    /// expanded. The name and `=` must be unquoted, so `"A=b"` is an
+    /// ordinary word
+    ///
+    /// Output:
+    ///   Name, whether it was `NAME+=value`, which appends to the value
+    ///   already set, and the value
+    ///
+    pub fn assignment(&self) -> Option<(String, bool, Word)> {
+        let first = self.segments.first().filter(|seg| seg.quoting == Quoting::Bare)?;
+        let (name, value) = first.text.split_once('=')?;
+        let (name, append) = match name.strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        if !is_name(name) {
+            return None;
+        }
+
+        let mut rest = Word::default();
+        if !value.is_empty() {
+            rest.segments.push(Segment { text: value.to_string(), quoting: Quoting::Bare });
+        }
+        rest.segments.extend(self.segments[1..].iter().cloned());
+        Some((name.to_string(), append, rest))
+    }
+}
+
+///
+/// Whether text is a valid variable name: a letter or `_`, then letters,
+/// digits and `_`
+///
+pub fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl fmt::Display for Word {
+    ///
+    /// Writes the word back out with quoting that reads back the same
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for seg in &self.segments {
+            match seg.quoting {
+                Quoting::Bare => f.write_str(&seg.text)?,
+                Quoting::Double => {
+                    f.write_str("\"")?;
+                    for c in seg.text.chars() {
+                        if matches!(c, '"' | '\\' | '$' | '`') {
+                            f.write_str("\\")?;
+                        }
+                        write!(f, "{c}")?;
+                    }
+                    f.write_str("\"")?;
+                }
+                Quoting::Single => write!(f, "'{}'", seg.text.replace('\'', "'\\''"))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// What a redirection does with its target file
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `<`: read from the file
+    Read,
+    /// `>`: truncate and write to the file
+    Write,
+    /// `>>`: append to the file
+    Append,
+    /// `>|`: truncate and write to the file even under `set -o noclobber`
+    Clobber,
+    /// `<&`: read from the fd named by the target, a copy of it
+    DupIn,
+    /// `>&`: write to the fd named by the target, as in `2>&1`
+    DupOut,
+    /// `<<`, or `<<-` which strips leading tabs: read the lines that follow,
+    /// which the lexer puts in place of the delimiter word
+    HereDoc { strip_tabs: bool },
+    /// `<<<`: read the target word itself, plus a newline
+    HereString,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    Word(Word),
+    Pipe,
+    /// `|&`, piping stderr along with stdout
+    PipeAll,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `;`
+    Semi,
+    /// `;;`, ending a branch of `case`
+    DoubleSemi,
+    /// `&` on its own, running the pipeline before it in the background
+    Amp,
+    /// End of a line, which separates commands like `;`
+    Newline,
+    /// `(`, as in the `()` of a function definition
+    LParen,
+    /// `)`
+    RParen,
+    /// Redirection operator, with the fd written in front of it if any
+    Redirect { fd: Option<i32>, op: RedirectOp },
+}
+
+impl fmt::Display for Token {
+    ///
+    /// Writes operators as they appear in the input, for syntax errors
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Pipe => f.write_str("|"),
+            Token::PipeAll => f.write_str("|&"),
+            Token::And => f.write_str("&&"),
+            Token::Or => f.write_str("||"),
+            Token::Semi => f.write_str(";"),
+            Token::DoubleSemi => f.write_str(";;"),
+            Token::Amp => f.write_str("&"),
+            Token::Newline => f.write_str("newline"),
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+            Token::Redirect { fd, op } => {
+                if let Some(fd) = fd {
+                    write!(f, "{fd}")?;
+                }
+                f.write_str(match op {
+                    RedirectOp::Read => "<",
+                    RedirectOp::Write => ">",
+                    RedirectOp::Append => ">>",
+                    RedirectOp::Clobber => ">|",
+                    RedirectOp::DupIn => "<&",
+                    RedirectOp::DupOut => ">&",
+                    RedirectOp::HereDoc { strip_tabs: false } => "<<",
+                    RedirectOp::HereDoc { strip_tabs: true } => "<<-",
+                    RedirectOp::HereString => "<<<",
+                })
+            }
+        }
+    }
+}
+
+///
+/// Tracks nesting inside `$(...)` to find the `)` that closes it, or
+/// inside `${...}` to find the `}`
+///
+/// Quotes and backslashes inside are followed just far enough that a `)`
+/// within them does not count, and in `$(...)` so are the words of `case`,
+/// whose patterns end in a `)` of their own
+///
+struct Substitution {
+    open: char,
+    close: char,
+    depth: usize,
+    quote: Option<char>,
+    escaped: bool,
+    /// Unquoted word being read, to spot `case`, `in` and `esac`
+    word: String,
+    /// Whether the word is where a command starts, so `case` is a keyword
+    start: bool,
+    /// Cases open inside, closed by `esac`
+    cases: usize,
+    /// After `case`, until its `in`
+    subject: bool,
+    /// Where a case pattern goes, after `in` or `;;`, until its `)`
+    pattern: bool,
+    /// Whether the last character was an unquoted `;`
+    semi: bool,
+}
+
+impl Substitution {
+    fn new(open: char, close: char) -> Self {
+        Substitution {
+            open,
+            close,
+            depth: 0,
+            quote: None,
+            escaped: false,
+            word: String::new(),
+            start: true,
+            cases: 0,
+            subject: false,
+            pattern: false,
+            semi: false,
+        }
+    }
+
+    ///
+    /// Follows `case ... in pattern) ... ;; esac` at the end of each word
+    ///
+    fn end_word(&mut self) {
+        if self.word.is_empty() {
+            return;
+        }
+        match self.word.as_str() {
+            "case" if self.start && !self.pattern => {
+                self.cases += 1;
+                self.subject = true;
+            }
+            "in" if self.subject => {
+                self.subject = false;
+                self.pattern = true;
+            }
+            "esac" if self.cases > 0 => {
+                self.cases -= 1;
+                self.pattern = false;
+            }
+            _ => {}
+        }
+        // Reserved words like `then` are followed by another command
+        self.start = matches!(self.word.as_str(), "if" | "then" | "else" | "elif" | "while" | "until" | "do" | "!");
+        self.word.clear();
+    }
+
+    ///
+    /// Takes a character outside quotes inside `$(...)`, following the
+    /// `case` words
+    ///
+    /// Output:
+    ///   true if it was a pattern's `(` or `)`, which does not nest
+    ///
+    fn case_syntax(&mut self, c: char) -> bool {
+        if !c.is_whitespace() && !";&|()'\"`\\".contains(c) {
+            self.word.push(c);
+            return false;
+        }
+        self.end_word();
+
+        let semi = std::mem::replace(&mut self.semi, c == ';');
+        if semi && c == ';' && self.cases > 0 {
+            self.pattern = true;
+        }
+        match c {
+            // A pattern may start with `(` as well
+            '(' if self.pattern => true,
+            ')' if self.pattern => {
+                self.pattern = false;
+                self.start = true;
+                true
+            }
+            ';' | '&' | '|' | '(' | ')' | '\n' => {
+                self.start = true;
+                false
+            }
+            // A quoted word is never a keyword
+            c if !c.is_whitespace() => {
+                self.start = false;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    ///
+    /// Takes the next character after the opening `$(` or `${`
+    ///
+    /// Output:
+    ///   true if it was the closing `)` or `}`
+    ///
+    fn feed(&mut self, c: char) -> bool {
+        if self.escaped {
+            self.escaped = false;
+            return false;
+        }
+
+        match (self.quote, c) {
+            (Some('\''), '\'') => self.quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => self.escaped = true,
+            (Some(q), c) if c == q => self.quote = None,
+            (Some(_), _) => {}
+            (None, c) if self.open == '(' && self.case_syntax(c) => {}
+            (None, '\'' | '"' | '`') => self.quote = Some(c),
+            (None, c) if c == self.open => self.depth += 1,
+            (None, c) if c == self.close && self.depth == 0 => return true,
+            (None, c) if c == self.close => self.depth -= 1,
+            _ => {}
+        }
+        false
+    }
+}
+
+///
+/// Finds the end of a command substitution
+///
+/// Input:
+///   Text following the opening `$(`
+///
+/// Output:
+///   Byte index of the closing `)`, or None if it is never closed
+///
+pub fn substitution_end(text: &str) -> Option<usize> {
+    let mut sub = Substitution::new('(', ')');
+    text.char_indices().find(|&(_, c)| sub.feed(c)).map(|(i, _)| i)
+}
+
+///
+/// Finds the end of a braced parameter expansion
+///
+/// Input:
+///   Text following the opening `${`
+///
+/// Output:
+///   Byte index of the closing `}`, or None if it is never closed
+///
+pub fn parameter_end(text: &str) -> Option<usize> {
+    let mut sub = Substitution::new('{', '}');
+    text.char_indices().find(|&(_, c)| sub.feed(c)).map(|(i, _)| i)
+}
+
+///
+/// Reads a braced parameter expansion into the word, as written, so any
+/// quotes and spaces in it are left for expansion to deal with
+///
+/// Inputs:
+///   Characters after the opening `${`
+///   Word being built and the quoting the expansion appeared in
+///
+fn read_parameter(chars: &mut Peekable<Chars>, word: &mut Word, quoting: Quoting) -> Result<(), LexError> {
+    word.push('$', quoting);
+    word.push('{', quoting);
+    let mut sub = Substitution::new('{', '}');
+    loop {
+        match chars.next() {
+            Some(c) => {
+                word.push(c, quoting);
+                if sub.feed(c) {
+                    return Ok(());
+                }
+            }
+            None => return Err(LexError::UnterminatedQuote('}')),
+        }
+    }
+}
+
+///
+/// Reasons a line could not be split into tokens
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LexError {
+    /// Input ended inside a '...' or "..." string
+    UnterminatedQuote(char),
+    /// Input ended with a backslash that has nothing to escape
+    TrailingBackslash,
+    /// Input ended before the line closing a here-document
+    UnterminatedHereDoc(String),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::UnterminatedQuote(q) => {
+                write!(f, "unexpected end of input while looking for matching `{q}'")
+            }
+            LexError::TrailingBackslash => write!(f, "unexpected end of input after `\\'"),
+            LexError::UnterminatedHereDoc(delimiter) => {
+                write!(f, "unexpected end of input while looking for here-document delimiter `{delimiter}'")
+            }
+        }
+    }
+}
+
+///
+/// Copies a command substitution into a word as written, `$(` through `)`,
+/// so expansion can find and run it. Backticks are rewritten to `$(...)`
+/// with their escapes removed. Process substitutions `<(...)` and `>(...)`
+/// are copied the same way
+///
+/// Inputs:
+///   Characters after the opening `$(`, `<(`, `>(` or backtick
+///   Word being built and the quoting the substitution appeared in
+///   Character it opened with: `$`, `<`, `>` or a backtick
+///
+fn read_substitution(
+    chars: &mut Peekable<Chars>,
+    word: &mut Word,
+    quoting: Quoting,
+    opener: char,
+) -> Result<(), LexError> {
+    let backtick = opener == '`';
+    word.push(if backtick { '$' } else { opener }, quoting);
+    word.push('(', quoting);
+
+    if backtick {
+        loop {
+            match chars.next() {
+                Some('`') => break,
+                Some('\\') => match chars.next() {
+                    Some(e @ ('`' | '\\' | '$')) => word.push(e, quoting),
+                    Some(other) => {
+                        word.push('\\', quoting);
+                        word.push(other, quoting);
+                    }
+                    None => return Err(LexError::UnterminatedQuote('`')),
+                },
+                Some(c) => word.push(c, quoting),
+                None => return Err(LexError::UnterminatedQuote('`')),
+            }
+        }
+    } else {
+        let mut sub = Substitution::new('(', ')');
+        loop {
+            match chars.next() {
+                Some(c) if sub.feed(c) => break,
+                Some(c) => word.push(c, quoting),
+                None => return Err(LexError::UnterminatedQuote(')')),
+            }
+        }
+    }
+
+    word.push(')', quoting);
+    Ok(())
+}
+
+///
+/// Copies an extended pattern such as `@(a|b)` into a word, so its
+/// parentheses and bars are not taken as operators
+///
+/// Inputs:
+///   Characters after the opening `(`
+///   Word being built
+///   Character before the `(`: `?`, `*`, `+`, `@` or `!`
+///
+fn read_pattern_group(chars: &mut Peekable<Chars>, word: &mut Word, opener: char) -> Result<(), LexError> {
+    word.push(opener, Quoting::Bare);
+    word.push('(', Quoting::Bare);
+    let mut sub = Substitution::new('(', ')');
+    loop {
+        match chars.next() {
+            Some(c) => {
+                word.push(c, Quoting::Bare);
+                if sub.feed(c) {
+                    return Ok(());
+                }
+            }
+            None => return Err(LexError::UnterminatedQuote(')')),
+        }
+    }
+}
+
+///
+/// Reads the bodies of the here-documents started on a line
+///
+/// Inputs:
+///   Tokens of the line, where each `<<` is followed by its delimiter word,
+///   which is replaced by the body. A quoted delimiter makes the body
+///   literal; otherwise variables and command substitutions in it expand
+///   Input following the line, taken up to each delimiter line in turn
+///
+/// Output:
+///   Error if the input ends before a delimiter line
+///
+fn read_here_docs(line: &mut [Token], chars: &mut Peekable<Chars>) -> Result<(), LexError> {
+    for i in 1..line.len() {
+        let Token::Redirect { op: RedirectOp::HereDoc { strip_tabs }, .. } = line[i - 1] else {
+            continue;
+        };
+        let Token::Word(delimiter) = &line[i] else {
+            continue;
+        };
+
+        let quoted = delimiter.segments.iter().any(|seg| seg.quoting != Quoting::Bare);
+        let delimiter: String = delimiter.segments.iter().map(|seg| seg.text.as_str()).collect();
+        let mut body = String::new();
+        loop {
+            if chars.peek().is_none() {
+                return Err(LexError::UnterminatedHereDoc(delimiter));
+            }
+            let mut text: String = std::iter::from_fn(|| chars.next_if(|&c| c != '\n')).collect();
+            chars.next();
+            if strip_tabs {
+                text = text.trim_start_matches('\t').to_string();
+            }
+            if text == delimiter {
+                break;
+            }
+            body.push_str(&text);
+            body.push('\n');
+        }
+
+        let quoting = if quoted { Quoting::Single } else { Quoting::Double };
+        line[i] = Token::Word(Word { segments: vec![Segment { text: body, quoting }] });
+    }
+    Ok(())
+}
+
+///
+/// Splits a command line into words and operators
+///
+/// Input:
+///   Raw command line, possibly ending in a newline
+///
+/// Output:
+///   Tokens in order. Whitespace separates words unless quoted or escaped,
+///   and a newline ends the command like `;` does. `\<newline>` joins
+///   lines, and `#` at the start of a word comments out the rest of the
+///   line. Digits directly before `<` or `>` name the fd
+///   being redirected rather than forming a word, and `>&`/`<&` duplicate
+///   the fd named after them. `$(...)`, `${...}`, backticks, `<(...)` and
+///   `>(...)` are kept whole inside the word they appear in. The lines
+///   after one with `<<WORD` on it, up to a line reading WORD, are the
+///   body of the here-document and not tokens
+///
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut word = Word::default();
+    let mut chars = input.chars().peekable();
+    // Where the current line's tokens start, to find its here-documents
+    let mut line_start = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                read_here_docs(&mut tokens[line_start..], &mut chars)?;
+                tokens.push(Token::Newline);
+                line_start = tokens.len();
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+            }
+            '#' if word.is_empty() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '|' | ';' | '(' | ')' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                tokens.push(match c {
+                    ';' if chars.next_if_eq(&';').is_some() => Token::DoubleSemi,
+                    ';' => Token::Semi,
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    _ if chars.next_if_eq(&'|').is_some() => Token::Or,
+                    _ if chars.next_if_eq(&'&').is_some() => Token::PipeAll,
+                    _ => Token::Pipe,
+                });
+            }
+            '&' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                tokens.push(match chars.next_if_eq(&'&') {
+                    Some(_) => Token::And,
+                    None => Token::Amp,
+                });
+            }
+            // Process substitution, kept in the word like `$(...)`
+            '<' | '>' if chars.next_if_eq(&'(').is_some() => {
+                read_substitution(&mut chars, &mut word, Quoting::Bare, c)?;
+            }
+            '<' | '>' => {
+                let fd = word.as_fd();
+                if fd.is_some() {
+                    word = Word::default();
+                } else if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+
+                let op = match c {
+                    '<' if chars.next_if_eq(&'<').is_some() => {
+                        if chars.next_if_eq(&'<').is_some() {
+                            RedirectOp::HereString
+                        } else {
+                            RedirectOp::HereDoc { strip_tabs: chars.next_if_eq(&'-').is_some() }
+                        }
+                    }
+                    '<' if chars.next_if_eq(&'&').is_some() => RedirectOp::DupIn,
+                    '<' => RedirectOp::Read,
+                    _ if chars.next_if_eq(&'>').is_some() => RedirectOp::Append,
+                    _ if chars.next_if_eq(&'&').is_some() => RedirectOp::DupOut,
+                    _ if chars.next_if_eq(&'|').is_some() => RedirectOp::Clobber,
+                    _ => RedirectOp::Write,
+                };
+                tokens.push(Token::Redirect { fd, op });
+            }
+            '$' if chars.next_if_eq(&'(').is_some() => {
+                read_substitution(&mut chars, &mut word, Quoting::Bare, '$')?;
+            }
+            '$' if chars.next_if_eq(&'{').is_some() => read_parameter(&mut chars, &mut word, Quoting::Bare)?,
+            '`' => read_substitution(&mut chars, &mut word, Quoting::Bare, '`')?,
+            // `$?(` is `$?` then a bracket, not a pattern
+            '?' | '*' | '+' | '@' | '!' if chars.peek() == Some(&'(') && !word.ends_with_bare('$') => {
+                chars.next();
+                read_pattern_group(&mut chars, &mut word, c)?;
+            }
+            '\\' => match chars.next() {
+                // Joining nothing means the line is still to come
+                Some('\n') if chars.peek().is_none() => return Err(LexError::TrailingBackslash),
+                Some('\n') => {}
+                Some(escaped) => word.push(escaped, Quoting::Single),
+                None => return Err(LexError::TrailingBackslash),
+            },
+            '\'' => {
+                word.mark_quoted(Quoting::Single);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c, Quoting::Single),
+                        None => return Err(LexError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                word.mark_quoted(Quoting::Double);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('$') if chars.next_if_eq(&'(').is_some() => {
+                            read_substitution(&mut chars, &mut word, Quoting::Double, '$')?;
+                        }
+                        Some('$') if chars.next_if_eq(&'{').is_some() => {
+                            read_parameter(&mut chars, &mut word, Quoting::Double)?;
+                        }
+                        Some('`') => read_substitution(&mut chars, &mut word, Quoting::Double, '`')?,
+                        // Only these lose their backslash inside double quotes
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(e @ ('$' | '`' | '"' | '\\')) => word.push(e, Quoting::Single),
+                            Some(other) => {
+                                word.push('\\', Quoting::Double);
+                                word.push(other, Quoting::Double);
+                            }
+                            None => return Err(LexError::UnterminatedQuote('"')),
+                        },
+                        Some(c) => word.push(c, Quoting::Double),
+                        None => return Err(LexError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            c => word.push(c, Quoting::Bare),
+        }
+    }
+
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    // A here-document on the last line has no lines left to read
+    read_here_docs(&mut tokens[line_start..], &mut chars)?;
+
+    Ok(tokens)
+}
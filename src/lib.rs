@@ -0,0 +1,1624 @@
+use std::collections::{HashMap, HashSet}; 
+use std::env;
+use std::fs::{self, File}; 
+use std::ops::ControlFlow; 
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd}; 
+use std::os::unix::process::ExitStatusExt; 
+use std::path::*;
+use std::rc::Rc; 
+use std::time::{Duration, Instant}; 
+use std::io::*; 
+use std::process::*; 
+
+
+use builtins::BUILTINS; 
+use editor::Editor; 
+use history::History; 
+use jobs::Jobs; 
+use lexer::{Quoting, Token, Word}; 
+
+pub use error::ShellError; 
+pub use exec::Executor; 
+pub use hooks::{Plugin, Segment}; 
+pub use parser::List; 
+
+mod arith; 
+mod brace; 
+mod builtins; 
+mod complete; 
+mod condition; 
+mod editor; 
+mod error; 
+mod hash; 
+mod exec; 
+mod highlight; 
+mod history; 
+mod hooks; 
+mod jobs; 
+mod jump; 
+mod lexer; 
+mod options; 
+mod param; 
+mod parser; 
+mod pattern; 
+mod platform; 
+mod prompt; 
+mod redirect; 
+mod signals; 
+mod theme; 
+mod trap; 
+
+/// Prompt for the lines that continue an unfinished command, unless `$PS2` 
+/// gives another 
+const CONTINUATION_PROMPT: &str = "> "; 
+
+/// Shortest run `\D` shows in the prompt, unless `$PROMPT_DURATION` gives 
+/// another 
+const DURATION_THRESHOLD: Duration = Duration::from_secs(2); 
+
+///
+/// State carried between commands 
+///
+#[derive(Default)]
+pub struct State {
+    /// Builtins switched off with `enable -n`, run as external commands 
+    disabled: HashSet<String>,
+    /// Set by `-r`/`--restricted` at startup and never cleared 
+    restricted: bool,
+    /// Exit status of the last pipeline, `$?` 
+    last_status: i32,
+    /// Shell variables not exported; exported ones live in the environment 
+    vars: HashMap<String, String>,
+    /// Assignments written before a command, such as the `a=1` of 
+    /// `a=1 b=$a cmd`, while the ones after them are expanded 
+    pending: HashMap<String, String>,
+    /// How many arguments each word of the builtin being run expanded to, 
+    /// its name first, so cd can tell the paths one glob matched from 
+    /// operands written apart 
+    arg_words: Vec<usize>,
+    /// Attributes `declare` gave variables, exported or not 
+    attributes: HashMap<String, Attributes>,
+    /// Command names replaced by other text before parsing 
+    aliases: HashMap<String, String>,
+    /// Background and stopped pipelines 
+    jobs: Jobs,
+    /// Line editor for interactive input 
+    editor: Editor,
+    /// Lines entered at the prompt 
+    history: History,
+    /// Git status shown by `\g` in the prompt 
+    git: prompt::GitCache,
+    /// Directories saved by pushd, most recent last 
+    dir_stack: Vec<PathBuf>,
+    /// Functions by name, each body a compound command 
+    functions: HashMap<String, Rc<parser::Command>>,
+    /// Name of the shell or of the script it runs, `$0` 
+    name: String, 
+    /// Positional parameters `$1` onwards: the arguments of the script, or 
+    /// of the function or sourced file being run 
+    positional: Vec<String>,
+    /// How many function calls are running, so `return` knows it is in one 
+    function_depth: usize,
+    /// Set by `return` while it unwinds to the function it returns from 
+    returning: bool,
+    /// How many loops are running in the current function or at top level, 
+    /// so `break` and `continue` know how far they can reach 
+    loop_depth: usize,
+    /// Loops `break` or `continue` still has to unwind out of 
+    breaking: usize,
+    /// The innermost loop `breaking` reaches carries on rather than ends 
+    continuing: bool,
+    /// Where each command on `$PATH` is, so it is not searched for 
+    path_table: hash::PathTable,
+    /// Options switched with `set` 
+    options: options::Options,
+    /// How many if/while/until conditions are running, where a failure 
+    /// does not trip `set -e` 
+    in_condition: usize,
+    /// Process substitutions still running, with the shell's end of the 
+    /// pipe to each, oldest first 
+    substitutions: Vec<(OwnedFd, libc::pid_t)>,
+    /// Commands set with `trap` 
+    traps: trap::Traps,
+    /// Fds above 2 opened for the shell by `exec`, which the commands it 
+    /// runs inherit 
+    open_fds: HashSet<i32>,
+    /// Line of the script or file the running command starts on, counting 
+    /// from 1, or 0 for commands typed at the prompt 
+    line: usize,
+    /// Started as a login shell, so it reads the profile files and runs 
+    /// `~/.rsh_logout` when it exits 
+    login: bool,
+    /// How long the last line typed at the prompt took to run, for `\D` 
+    duration: Duration,
+    /// Pid of the last stage of the newest background job, `$!` 
+    last_background: Option<libc::pid_t>,
+    /// Functions and plugins run on shell events, and prompt segments 
+    hooks: hooks::Hooks,
+}
+
+impl State {
+    ///
+    /// Exit status of the last pipeline, `$?` 
+    ///
+    pub fn last_status(&self) -> i32 {
+        self.last_status 
+    }
+
+    ///
+    /// Value of a variable, shell variables first, then the environment 
+    ///
+    pub fn var(&self, name: &str) -> Option<String> {
+        lookup(self, name)
+    }
+
+    ///
+    /// Sets a variable, in the environment if it is already exported 
+    ///
+    /// Output: 
+    ///   An error, leaving it as it was, if `declare` made it readonly, or 
+    ///   an integer and the value is not a valid expression 
+    ///
+    pub fn set_var(&mut self, name: &str, value: &str) -> std::result::Result<(), ShellError> {
+        assign(self, name, value.to_string())
+    }
+}
+
+///
+/// A shell that can be given commands to run, as a script would be, in 
+/// the calling program's process 
+///
+/// Commands change the process environment, as `export` and `cd` do, so 
+/// the program must not read or write it from other threads while one runs 
+///
+#[derive(Default)]
+pub struct Shell {
+    state: State,
+}
+
+impl Shell {
+    ///
+    /// Creates a shell with no variables, functions or aliases of its own 
+    ///
+    pub fn new() -> Self {
+        Shell::default()
+    }
+
+    ///
+    /// Runs commands one line at a time, as a script's lines would be 
+    ///
+    /// They run as in a script even when the program's stdin is a 
+    /// terminal: pipelines stay in the program's process group, never 
+    /// taking the terminal, and there is no job control or history 
+    ///
+    /// Input: 
+    ///   Commands, which may span several lines 
+    ///
+    /// Output: 
+    ///   Break with the exit status if they ran `exit`, leaving it to the 
+    ///   caller whether to exit; Continue else, with the status of the 
+    ///   last command in last_status 
+    ///
+    pub fn run(&mut self, text: &str) -> ControlFlow<i32> {
+        jobs::without_terminal(|| run_lines(&mut self.state, text))
+    }
+
+    ///
+    /// Exit status of the last pipeline, `$?` 
+    ///
+    pub fn last_status(&self) -> i32 {
+        self.state.last_status 
+    }
+
+    ///
+    /// Variables and status of the shell, to read between runs 
+    ///
+    pub fn state(&self) -> &State {
+        &self.state 
+    }
+
+    ///
+    /// Variables of the shell, to set before a run, such as with set_var 
+    ///
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state 
+    }
+
+    ///
+    /// Adds Rust code to run on the shell's events, after any added before 
+    ///
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        self.state.hooks.add_plugin(plugin); 
+    }
+
+    ///
+    /// Adds a prompt segment drawn where a prompt has `\{name}`, in place of 
+    /// any segment or function of that name 
+    ///
+    pub fn add_segment(&mut self, name: &str, segment: Box<dyn Segment>) {
+        self.state.hooks.add_segment(name, segment); 
+    }
+}
+
+///
+/// Turns command text into the syntax tree the executor runs, with the 
+/// aliases of a shell replaced first 
+///
+pub struct Parser<'a> {
+    state: &'a State,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(state: &'a State) -> Self {
+        Parser { state }
+    }
+
+    ///
+    /// Parses one or more complete commands 
+    ///
+    /// Input: 
+    ///   Command text, here-document bodies included 
+    ///
+    /// Output: 
+    ///   The list of pipelines to run, or what is wrong with the text. The 
+    ///   line of a syntax error counts from the line of the script being 
+    ///   run, if there is one 
+    ///
+    pub fn parse(&self, text: &str) -> std::result::Result<List, ShellError> {
+        let tokens = lexer::tokenize(text)?; 
+        let tokens = expand_aliases(self.state, tokens)?; 
+        parser::parse(tokens).map_err(|e| match e {
+            ShellError::Syntax { error, line } => ShellError::Syntax {
+                error, 
+                line: line.filter(|_| self.state.line > 0).map(|line| line + self.state.line - 1), 
+            },
+            e => e, 
+        })
+    }
+}
+
+///
+/// Checks whether restricted mode forbids an action 
+///
+/// Inputs: 
+///   Shell state 
+///   What is being attempted, completing `cannot ...` 
+///
+/// Output: 
+///   true (after printing the error) if the shell is restricted 
+///
+fn restricted(state: &State, action: &str) -> bool {
+    if state.restricted {
+        ShellError::Restricted(action.to_string()).print(); 
+    }
+    state.restricted 
+}
+
+///
+/// Variables restricted mode does not let the user change 
+///
+const RESTRICTED_VARS: &[&str] = &["PATH", "SHELL", "ENV"]; 
+
+///
+/// What `declare` has made of a variable 
+///
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Attributes {
+    /// `-i`: values assigned are evaluated as arithmetic 
+    integer: bool,
+    /// `-r`: no longer assigned or unset 
+    readonly: bool,
+}
+
+///
+/// Whether `declare -r` has made a variable readonly 
+///
+fn readonly(state: &State, name: &str) -> bool {
+    state.attributes.get(name).is_some_and(|attributes| attributes.readonly)
+}
+
+///
+/// Looks up a variable: an assignment still pending before a command 
+/// first, then shell variables, then the environment 
+///
+fn lookup(state: &State, name: &str) -> Option<String> {
+    state.pending.get(name).or_else(|| state.vars.get(name)).cloned().or_else(|| env::var(name).ok())
+}
+
+///
+/// Sets a variable, updating the environment if it is already exported 
+///
+/// Inputs: 
+///   Shell state holding the shell variables 
+///   Variable name and its new value, evaluated as arithmetic first if 
+///   `declare -i` made it an integer 
+///
+/// Output: 
+///   An error, leaving the variable as it was, if it is readonly or its 
+///   value is not a valid expression 
+///
+fn assign(state: &mut State, name: &str, value: String) -> std::result::Result<(), ShellError> {
+    let attributes = state.attributes.get(name).copied().unwrap_or_default(); 
+    if attributes.readonly {
+        return Err(ShellError::Readonly(name.to_string())); 
+    }
+    let value = if attributes.integer { arithmetic(state, &value)? } else { value }; 
+
+    if env::var_os(name).is_some() {
+        platform::set_env(name, value); 
+    } else {
+        state.vars.insert(name.to_string(), value);
+    }
+    Ok(())
+}
+
+///
+/// Substitutes variable references and command substitutions in a piece 
+/// of text 
+///
+/// Inputs: 
+///   Shell state, for special parameters and to run substitutions 
+///   Text that may contain `$NAME`, `${NAME}`, `$?`, `$(command)`, 
+///   `$((expression))`, or the positional parameters `$1`, `${10}`, `$#`, 
+///   `$@` and `$*`, and the `${...}` operators in the param module 
+///   Whether the text is unquoted, so substitution output and parameters 
+///   are split into fields at `$IFS` characters, blanks by default 
+///
+/// Output: 
+///   Substituted text as one or more fields, or None if there was nothing 
+///   to substitute. Unset variables become empty, or an error under 
+///   `set -u`; a `$` not followed by a 
+///   name is kept. `$@` gives each parameter its own field even when quoted, 
+///   and `"$*"` joins them with the first `$IFS` character. 
+///   An error for an arithmetic or `${...}` expansion that failed 
+///
+fn expand_vars(state: &mut State, text: &str, split: bool) -> std::result::Result<Option<Vec<String>>, ShellError> {
+    Ok(expand_fields(state, text, split)?.map(|(fields, _)| fields))
+}
+
+/// Fields of substituted text, and which of them are kept even if empty 
+type Fields = (Vec<String>, Vec<usize>); 
+
+///
+/// Substitutes in text as expand_vars does, also telling which fields a 
+/// `$IFS` character other than a blank ended, which are kept even if they 
+/// are empty, as the middle one of `a,,b` with `IFS=,` 
+///
+fn expand_fields(state: &mut State, text: &str, split: bool) -> std::result::Result<Option<Fields>, ShellError> {
+    let Some(start) = text.find('$') else {
+        return Ok(None); 
+    };
+    let mut fields = vec![String::with_capacity(text.len())]; 
+    fields[0].push_str(&text[..start]);
+    let mut kept = Vec::new(); 
+
+    let ifs = split.then(|| lookup(state, "IFS").unwrap_or_else(|| " \t\n".to_string())); 
+    let split = ifs.as_deref(); 
+    let mut rest = &text[start..]; 
+    while let Some(after) = rest.strip_prefix('$') {
+        let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_'; 
+
+        let tail = if let Some(inner) = after.strip_prefix("((") 
+            && let Some(end) = lexer::substitution_end(inner) 
+            && inner[end + 1..].starts_with(')') {
+            // `$((...))` only when the inner parentheses close together 
+            let output = arithmetic(state, &inner[..end])?; 
+            push_fields(&mut fields, &mut kept, &output, split);
+            &inner[end + 2..]
+        } else if let Some(inner) = after.strip_prefix('(') 
+            && let Some(end) = lexer::substitution_end(inner) {
+            let output = command_substitution(state, &inner[..end]); 
+            push_fields(&mut fields, &mut kept, &output, split);
+            &inner[end + 1..]
+        } else {
+            let (name, tail) = if let Some(braced) = after.strip_prefix('{') 
+                && let Some(end) = lexer::parameter_end(braced) {
+                (&braced[..end], &braced[end + 1..])
+            } else if after.starts_with(['?', '#', '@', '*', '!']) || after.starts_with(|c: char| c.is_ascii_digit()) {
+                after.split_at(1)
+            } else {
+                after.split_at(after.find(|c| !is_name(c)).unwrap_or(after.len()))
+            };
+
+            match name {
+                "" if !after.starts_with('{') => fields.last_mut().unwrap().push('$'),
+                body if after.starts_with('{') && !param::is_plain(body) => {
+                    let output = param::expand(state, body)?; 
+                    push_fields(&mut fields, &mut kept, &output, split);
+                },
+                "?" => fields.last_mut().unwrap().push_str(&state.last_status.to_string()),
+                "#" => fields.last_mut().unwrap().push_str(&state.positional.len().to_string()),
+                "0" => fields.last_mut().unwrap().push_str(&state.name),
+                "!" => fields.last_mut().unwrap().push_str(&state.last_background.map(|pid| pid.to_string()).unwrap_or_default()),
+                "@" if split.is_none() => {
+                    for (i, param) in state.positional.iter().enumerate() {
+                        if i > 0 {
+                            fields.push(String::new());
+                        }
+                        fields.last_mut().unwrap().push_str(param);
+                    }
+                },
+                // Unquoted, each parameter is split on its own 
+                "@" | "*" if split.is_some() => {
+                    for (i, param) in state.positional.iter().enumerate() {
+                        if i > 0 {
+                            fields.push(String::new());
+                        }
+                        push_fields(&mut fields, &mut kept, param, split);
+                    }
+                },
+                "@" | "*" => {
+                    let separator = lookup(state, "IFS").map_or(Some(' '), |ifs| ifs.chars().next()); 
+                    let joined = state.positional.join(&separator.map(String::from).unwrap_or_default()); 
+                    fields.last_mut().unwrap().push_str(&joined);
+                },
+                name if name.bytes().all(|b| b.is_ascii_digit()) => {
+                    let value = name.parse::<usize>().ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|i| state.positional.get(i))
+                        .cloned(); 
+                    let value = param::required(state, name, value)?; 
+                    push_fields(&mut fields, &mut kept, &value, split);
+                },
+                name => {
+                    let value = param::required(state, name, lookup(state, name))?; 
+                    push_fields(&mut fields, &mut kept, &value, split);
+                },
+            }
+            tail 
+        };
+
+        // Copy up to the next reference 
+        let next = tail.find('$').unwrap_or(tail.len()); 
+        fields.last_mut().unwrap().push_str(&tail[..next]);
+        rest = &tail[next..]; 
+    }
+
+    Ok(Some((fields, kept)))
+}
+
+///
+/// Appends substituted text to the last field, starting a new field at 
+/// each `$IFS` delimiter if it is unquoted, given as split. Empty fields 
+/// are dropped later, except those recorded in kept as ended by an `$IFS` 
+/// character other than a blank 
+///
+fn push_fields(fields: &mut Vec<String>, kept: &mut Vec<usize>, text: &str, split: Option<&str>) {
+    let Some(ifs) = split.filter(|ifs| !ifs.is_empty()) else {
+        fields.last_mut().unwrap().push_str(text);
+        return; 
+    };
+    let blank = |c: char| c.is_whitespace() && ifs.contains(c); 
+
+    let mut chars = text.chars().peekable(); 
+    while let Some(c) = chars.next() {
+        if !ifs.contains(c) {
+            fields.last_mut().unwrap().push(c);
+            continue; 
+        }
+        // One delimiter is a run of blanks with at most one other `$IFS` 
+        // character among them 
+        let mut other = !blank(c); 
+        while let Some(&next) = chars.peek() 
+            && ifs.contains(next) 
+            && (blank(next) || !other) {
+            other |= !blank(next); 
+            chars.next();
+        }
+        if other {
+            kept.push(fields.len() - 1); 
+        }
+        fields.push(String::new());
+    }
+}
+
+///
+/// Evaluates the expression of an arithmetic expansion 
+///
+/// Output: 
+///   The value as text, or the error to stop the command with 
+///
+fn arithmetic(state: &mut State, expr: &str) -> std::result::Result<String, ShellError> {
+    // Parameters and substitutions inside are expanded first, as in quotes 
+    let expr = match expand_vars(state, expr, false)? {
+        Some(fields) => fields.join(" "), 
+        None => expr.to_string(), 
+    };
+    arith::eval(state, &expr)
+        .map(|value| value.to_string())
+        .map_err(|e| ShellError::Expansion { text: expr.trim().to_string(), message: e.to_string() })
+}
+
+///
+/// Runs a command substitution in a subshell and captures its output 
+///
+/// Inputs: 
+///   Shell state, copied into the subshell and given its exit status as `$?` 
+///   Command text between `$(` and `)` 
+///
+/// Output: 
+///   What the command wrote to stdout, without trailing newlines 
+///
+fn command_substitution(state: &mut State, command: &str) -> String {
+    let mut fds = [0; 2]; 
+    // SAFETY: pipe writes two descriptors into the array we own 
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        error::report(state, Error::last_os_error()); 
+        return String::new(); 
+    }
+
+    // Anything still buffered would otherwise be written twice 
+    stdout().flush().ok(); 
+    stderr().flush().ok(); 
+
+    // SAFETY: other threads only hold the prompt's git lock, which the child 
+    // never takes, so it can keep running Rust code after fork. It never 
+    // returns from this branch 
+    match unsafe { libc::fork() } {
+        -1 => {
+            error::report(state, Error::last_os_error()); 
+            unsafe {
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+            }
+            String::new()
+        },
+        0 => unsafe {
+            libc::close(fds[0]);
+            libc::dup2(fds[1], libc::STDOUT_FILENO);
+            libc::close(fds[1]);
+            jobs::set_noninteractive(); 
+            signals::reset_for_child(); 
+            trap::clear_for_subshell(state); 
+
+            let code = match shell_run(state, command.to_string()) {
+                ControlFlow::Break(code) => code, 
+                ControlFlow::Continue(()) => state.last_status, 
+            };
+            stdout().flush().ok(); 
+            stderr().flush().ok(); 
+            libc::_exit(code)
+        },
+        pid => {
+            // SAFETY: the write end belongs to the child now, and the read 
+            // end is owned by the File from here on 
+            unsafe { libc::close(fds[1]) };
+            let mut reader = unsafe { File::from_raw_fd(fds[0]) }; 
+            let mut output = Vec::new(); 
+            if let Err(e) = reader.read_to_end(&mut output) {
+                error::report(state, e); 
+            }
+
+            let mut raw = 0; 
+            // SAFETY: waitpid only writes through the status pointer we own 
+            while unsafe { libc::waitpid(pid, &mut raw, 0) } < 0 
+                && Error::last_os_error().kind() == ErrorKind::Interrupted {}
+            state.last_status = jobs::decode_status(raw); 
+
+            let mut text = String::from_utf8_lossy(&output).into_owned(); 
+            text.truncate(text.trim_end_matches('\n').len());
+            text 
+        }
+    }
+}
+
+///
+/// Starts a process substitution, connected to the command by a pipe 
+///
+/// Inputs: 
+///   Shell state, copied into the process, which keeps the shell's end of 
+///   the pipe until the command using it is done 
+///   Command text between the parentheses 
+///   Whether it is `<(...)`, which the command reads, rather than `>(...)` 
+///
+/// Output: 
+///   `/dev/fd/N` path naming the shell's end of the pipe, or None if it 
+///   could not be started 
+///
+fn process_substitution(state: &mut State, command: &str, readable: bool) -> Option<String> {
+    let mut fds = [0; 2]; 
+    // SAFETY: pipe writes two descriptors into the array we own 
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        error::report(state, Error::last_os_error()); 
+        return None; 
+    }
+    // The child writes what `<(...)` reads and reads what `>(...)` is sent 
+    let (ours, theirs, target) = if readable {
+        (fds[0], fds[1], libc::STDOUT_FILENO)
+    } else {
+        (fds[1], fds[0], libc::STDIN_FILENO)
+    };
+
+    stdout().flush().ok(); 
+    stderr().flush().ok(); 
+
+    // SAFETY: other threads only hold the prompt's git lock, which the child 
+    // never takes, so it can keep running Rust code after fork. It never 
+    // returns from this branch 
+    match unsafe { libc::fork() } {
+        -1 => {
+            error::report(state, Error::last_os_error()); 
+            unsafe {
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+            }
+            None 
+        },
+        0 => unsafe {
+            libc::dup2(theirs, target);
+            libc::close(ours);
+            libc::close(theirs);
+            jobs::set_noninteractive(); 
+            signals::reset_for_child(); 
+            trap::clear_for_subshell(state); 
+
+            let code = match shell_run(state, command.to_string()) {
+                ControlFlow::Break(code) => code, 
+                ControlFlow::Continue(()) => state.last_status, 
+            };
+            stdout().flush().ok(); 
+            stderr().flush().ok(); 
+            libc::_exit(code)
+        },
+        pid => {
+            // SAFETY: the child's end belongs to it now, and ours is owned 
+            // by the OwnedFd from here on. It stays open across exec so the 
+            // command can open it by path 
+            let fd = unsafe {
+                libc::close(theirs);
+                OwnedFd::from_raw_fd(ours)
+            };
+            let path = format!("/dev/fd/{}", fd.as_raw_fd()); 
+            state.substitutions.push((fd, pid));
+            Some(path)
+        }
+    }
+}
+
+///
+/// Replaces each `<(...)` and `>(...)` in unquoted text with the path of 
+/// a process substitution started for it 
+///
+/// Output: 
+///   The text with paths substituted, or None if it had none. Those inside 
+///   `$(...)` are left for the command substitution to start 
+///
+fn expand_process_substitutions(state: &mut State, text: &str) -> Option<String> {
+    if !text.contains("<(") && !text.contains(">(") {
+        return None; 
+    }
+
+    let mut out = String::with_capacity(text.len()); 
+    let mut rest = text; 
+    while let Some(start) = rest.find(['$', '<', '>']) {
+        out.push_str(&rest[..start]);
+        let (opener, after) = rest[start..].split_at(1); 
+        let found = after.strip_prefix('(') 
+            .and_then(|inner| Some((inner, lexer::substitution_end(inner)?))); 
+        let Some((inner, end)) = found else {
+            out.push_str(opener);
+            rest = after; 
+            continue; 
+        };
+
+        match opener {
+            "$" => out.push_str(&rest[start..start + end + 3]),
+            _ => out.push_str(&process_substitution(state, &inner[..end], opener == "<").unwrap_or_default()),
+        }
+        rest = &inner[end + 1..]; 
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+///
+/// Word part way through expansion, one of the arguments it will become 
+///
+#[derive(Default)]
+struct Field {
+    text: String, 
+    /// Text with quoted parts escaped, for glob matching 
+    pattern: String, 
+    globbing: bool, 
+    /// Kept even if empty, being quoted or ended by a non-blank `$IFS` 
+    /// character 
+    keep: bool, 
+}
+
+///
+/// Expands a single word into the arguments it stands for 
+///
+/// Inputs: 
+///   Shell state, for special parameters and command substitution 
+///   Word from the lexer with tilde, variables, and globs still unexpanded 
+///   Output vector the resulting arguments are appended to 
+///
+/// Tilde (leading and unquoted only), process substitutions, variables and 
+/// command substitutions are expanded first; unquoted substitution output is split into several 
+/// arguments at whitespace. Then glob patterns are matched in sorted order. 
+/// Single quoted text is left as is, and quoted glob characters match 
+/// literally. Leading dots must be matched literally so `*` skips dotfiles. 
+/// A pattern with no matches is kept as the expanded word, dropped with 
+/// `set -o nullglob` or an error with `set -o failglob`, and none are 
+/// matched with `set -f`. An unquoted word that expands to nothing is 
+/// dropped 
+///
+fn expand_word(state: &mut State, word: &Word, out: &mut Vec<String>) -> std::result::Result<(), ShellError> {
+    let mut fields = vec![Field::default()]; 
+
+    for (i, seg) in word.segments.iter().enumerate() {
+        // `"$@"` without parameters is no argument at all, not an empty one 
+        if seg.quoting == Quoting::Double 
+            && (seg.text == "$@" || seg.text == "${@}") 
+            && state.positional.is_empty() {
+            continue; 
+        }
+
+        // Borrowed when nothing was substituted, so plain text is copied once 
+        let mut raw = match seg.quoting {
+            Quoting::Bare if i == 0 => platform::tilde(&seg.text),
+            _ => seg.text.as_str().into(),
+        };
+        if seg.quoting == Quoting::Bare 
+            && let Some(text) = expand_process_substitutions(state, &raw) {
+            raw = text.into(); 
+        }
+        let expanded = match seg.quoting {
+            Quoting::Single => None, 
+            Quoting::Bare => expand_fields(state, &raw, true)?, 
+            Quoting::Double => expand_fields(state, &raw, false)?, 
+        };
+        let (pieces, kept) = expanded.unwrap_or_else(|| (vec![raw.into_owned()], Vec::new())); 
+
+        for (j, piece) in pieces.into_iter().enumerate() {
+            if j > 0 {
+                fields.push(Field::default());
+            }
+            let field = fields.last_mut().unwrap(); 
+            field.keep |= kept.contains(&j); 
+            if seg.quoting == Quoting::Bare {
+                field.globbing |= pattern::has_wildcards(&piece); 
+                field.pattern.push_str(&piece);
+            } else {
+                field.keep = true; 
+                field.pattern.push_str(&pattern::escape(&piece));
+            }
+            field.text.push_str(&piece);
+        }
+    }
+
+    for field in fields {
+        push_field(&state.options, field, out)?;
+    }
+    Ok(())
+}
+
+///
+/// Matches a fully expanded field against the filesystem if it is a glob 
+/// pattern, and appends the resulting arguments. Text such as a lone `[` 
+/// that only looked like a pattern is kept as it is 
+///
+/// Output: 
+///   An error for a pattern matching nothing under `set -o failglob` 
+///
+fn push_field(options: &options::Options, field: Field, out: &mut Vec<String>) -> std::result::Result<(), ShellError> {
+    if !field.globbing || options.noglob {
+        if field.keep || !field.text.is_empty() {
+            out.push(field.text);
+        }
+        return Ok(()); 
+    }
+
+    let paths = pattern::expand(&field.pattern, options.globstar); 
+    if !paths.is_empty() {
+        out.extend(paths); 
+    } else if pattern::Pattern::new(&field.pattern).literal().is_some() {
+        out.push(field.text);
+    } else if options.failglob {
+        return Err(ShellError::NoMatch(field.text)); 
+    } else if !options.nullglob {
+        out.push(field.text);
+    }
+    Ok(())
+}
+
+///
+/// Expands the value of an assignment: tilde, variables and command 
+/// substitution, but no globs and never more than one word 
+///
+/// Output: 
+///   The value, or the error from an expansion that failed 
+///
+fn expand_value(state: &mut State, word: &Word) -> std::result::Result<String, ShellError> {
+    let mut text = String::new(); 
+    for (i, seg) in word.segments.iter().enumerate() {
+        let raw = match seg.quoting {
+            Quoting::Bare if i == 0 => platform::tilde(&seg.text),
+            _ => seg.text.as_str().into(),
+        };
+        match seg.quoting {
+            Quoting::Single => text.push_str(&raw), 
+            Quoting::Bare | Quoting::Double => match expand_vars(state, &raw, false)? {
+                Some(fields) => text.push_str(&fields.concat()),
+                None => text.push_str(&raw),
+            },
+        }
+    }
+    Ok(text)
+}
+
+///
+/// Matches text against a `case` pattern, expanded like an assignment 
+/// value but with quoted parts matching literally 
+///
+/// Inputs: 
+///   Shell state, for variables and substitutions in the pattern 
+///   Pattern as written 
+///   Text to match, which `*` matches in full, slashes and dots included 
+///
+/// Output: 
+///   Whether it matched, or the error from an expansion that failed 
+///
+fn matches_pattern(state: &mut State, pattern: &Word, text: &str) -> std::result::Result<bool, ShellError> {
+    let mut glob = String::new(); 
+    for (i, seg) in pattern.segments.iter().enumerate() {
+        let raw = match seg.quoting {
+            Quoting::Bare if i == 0 => platform::tilde(&seg.text),
+            _ => seg.text.as_str().into(),
+        };
+        let expanded = match seg.quoting {
+            Quoting::Single => raw.into_owned(), 
+            Quoting::Bare | Quoting::Double => expand_vars(state, &raw, false)?
+                .map_or_else(|| raw.into_owned(), |fields| fields.concat()), 
+        }; 
+        if seg.quoting == Quoting::Bare {
+            glob.push_str(&expanded);
+        } else {
+            glob.push_str(&pattern::escape(&expanded));
+        }
+    }
+    Ok(pattern::Pattern::new(&glob).matches(text))
+}
+
+/// 
+/// Expands patterns in args to be used in command 
+///
+/// Input: 
+///   Shell state, for special parameters 
+///   Words of one command, patterns still unexpanded 
+///
+/// Output: 
+///   Vector of Strings where a single element is a single argument to consider. 
+///   Braces expand first, so `{a,b}*` globs for both `a*` and `b*`. An 
+///   error if a pattern matched nothing under `set -o failglob` 
+///
+fn expand_args(state: &mut State, words: &[Word]) -> std::result::Result<Vec<String>, ShellError> {
+    expand_args_by_word(state, words).map(|(args, _)| args)
+}
+
+///
+/// Expands the words of one command as expand_args does, also telling 
+/// how many arguments each word became 
+///
+fn expand_args_by_word(state: &mut State, words: &[Word]) -> std::result::Result<(Vec<String>, Vec<usize>), ShellError> {
+    // One argument per word unless globs match more 
+    let mut args_out = Vec::with_capacity(words.len()); 
+    let mut counts = Vec::with_capacity(words.len()); 
+    for word in words {
+        let before = args_out.len(); 
+        for word in brace::expand(word) {
+            expand_word(state, &word, &mut args_out)?;
+        }
+        counts.push(args_out.len() - before); 
+    }
+    Ok((args_out, counts))
+}
+
+///
+/// Splits a variable holding a command, such as `$PAGER`, into its 
+/// expanded words, as the shell would a command line 
+///
+/// Output: 
+///   The words, empty if the text has none or anything but words, such 
+///   as a `|`, or could not be expanded 
+///
+fn command_words(state: &mut State, text: &str) -> Vec<String> {
+    let Ok(tokens) = lexer::tokenize(text) else {
+        return Vec::new(); 
+    };
+    let words: Option<Vec<Word>> = tokens.into_iter()
+        .filter(|token| *token != Token::Newline)
+        .map(|token| match token {
+            Token::Word(word) => Some(word), 
+            _ => None, 
+        })
+        .collect(); 
+    words.and_then(|words| expand_args(state, &words).ok()).unwrap_or_default()
+}
+
+///
+/// Finds the command a mistyped name was most likely meant to be 
+///
+/// Output: 
+///   The closest builtin, alias, function or command on `$PATH`, if one is 
+///   close enough to be worth suggesting 
+///
+fn suggestion(state: &mut State, name: &str) -> Option<String> {
+    let State { aliases, functions, path_table, .. } = state; 
+    let candidates = BUILTINS.iter().copied()
+        .chain(aliases.keys().map(String::as_str))
+        .chain(functions.keys().map(String::as_str))
+        .chain(path_table.names().map(String::as_str))
+        .filter(|&candidate| candidate != name); 
+    complete::closest(name, candidates).map(str::to_string)
+}
+
+/// Function run in place of the message for a command that is not found 
+const NOT_FOUND_FUNCTION: &str = "command_not_found_handle"; 
+
+///
+/// Reports a command that could not be found on PATH 
+///
+/// A `command_not_found_handle` function, as in bash, is called with the 
+/// missing command and its arguments. Otherwise if `COMMAND_NOT_FOUND_CMD` 
+/// is set, its words are run with them appended (e.g. a package 
+/// suggestion tool). Falls back to the default message if there is 
+/// neither or the command itself cannot be run 
+///
+/// Inputs: 
+///   Shell state, for the function and variable 
+///   Command name and its arguments 
+///   Name to offer as what was probably meant, after the message 
+///
+/// Output: 
+///   The handler's exit status, or 127 if the message was printed 
+///
+fn command_not_found(state: &mut State, command: &str, argv: &[String], suggestion: Option<&str>) -> i32 {
+    if let Some(body) = state.functions.get(NOT_FOUND_FUNCTION).cloned() {
+        let argv = [NOT_FOUND_FUNCTION, command].into_iter().map(String::from).chain(argv.iter().cloned()).collect(); 
+        // An `exit` in it ends only the handler, which bash runs apart 
+        return match exec::call_function(state, &body, argv) {
+            ControlFlow::Continue(()) => state.last_status, 
+            ControlFlow::Break(code) => code, 
+        }; 
+    }
+    if let Some(handler) = lookup(state, "COMMAND_NOT_FOUND_CMD") {
+        let mut words = command_words(state, &handler).into_iter(); 
+        if let Some(program) = words.next() {
+            let status = Command::new(program)
+                .args(words)
+                .arg(command)
+                .args(argv)
+                .status(); 
+
+            if let Ok(status) = status {
+                return exit_code(status); 
+            }
+        }
+    }
+
+    eprintln!("rust-shell: {command}: command not found");
+    if let Some(suggestion) = suggestion {
+        eprintln!("rust-shell: did you mean: {suggestion}?");
+    }
+    127 
+}
+
+///
+/// Converts a child's exit status to a shell status, 128 + n if a signal 
+/// killed it 
+///
+fn exit_code(status: ExitStatus) -> i32 {
+    status.code()
+        .or_else(|| status.signal().map(|sig| 128 + sig))
+        .unwrap_or(1)
+}
+
+///
+/// Replaces aliased command names with their definitions 
+///
+/// A definition whose first word is itself an alias is expanded again, 
+/// but never with an alias already used for that command, so 
+/// `alias ls='ls --color'` stops after one round 
+///
+/// Inputs: 
+///   Shell state holding the aliases 
+///   Tokens of a line 
+///
+/// Output: 
+///   Tokens with aliases expanded, or the error lexing a definition 
+///
+fn expand_aliases(state: &State, tokens: Vec<Token>) -> std::result::Result<Vec<Token>, lexer::LexError> {
+    if state.aliases.is_empty() {
+        return Ok(tokens); 
+    }
+
+    let mut out = Vec::with_capacity(tokens.len()); 
+    let mut command_start = true; 
+    for token in tokens {
+        // Only the first word of each command can be an alias 
+        let is_word = matches!(token, Token::Word(_)); 
+        if !is_word || !command_start {
+            command_start = !is_word && !matches!(token, Token::Redirect { .. }); 
+            out.push(token); 
+            continue; 
+        }
+        command_start = false; 
+
+        // Words like `then` are followed by another command 
+        if let Token::Word(word) = &token 
+            && word.bare_text().is_some_and(|text| ["do", "elif", "else", "if", "then", "time", "until", "while", "{"].contains(&text)) {
+            command_start = true; 
+            out.push(token); 
+            continue; 
+        }
+
+        let mut expansion = vec![token.clone()]; 
+        let mut used = HashSet::new(); 
+        while let Some(Token::Word(first)) = expansion.first() 
+            && let Some(name) = first.bare_text() 
+            && let Some(value) = state.aliases.get(name) 
+            && used.insert(name.to_string()) {
+            let mut replaced = lexer::tokenize(value)?; 
+            replaced.extend(expansion.drain(1..));
+            expansion = replaced; 
+        }
+
+        out.extend(expansion);
+    }
+    Ok(out)
+}
+
+/// 
+/// Main handler to run shell commands 
+///
+/// Inputs: 
+///   Shell state shared across commands, whose last status is updated 
+///   Command line to tokenize and run 
+///
+/// Returns: 
+///   Break with the exit status if exit was specified, Continue else 
+///
+fn shell_run(state: &mut State, input: String) -> ControlFlow<i32> {
+    let list = match Parser::new(state).parse(&input) {
+        Ok(list) => list, 
+        Err(e) => {
+            error::report(state, e); 
+            return ControlFlow::Continue(());
+        }
+    };
+
+    exec::run_list(state, &list)
+}
+
+///
+/// Runs the lines of a file if it exists 
+///
+/// Output: 
+///   Break with the exit status if the file exited the shell 
+///
+fn run_file(state: &mut State, path: &Path) -> ControlFlow<i32> {
+    match fs::read_to_string(path) {
+        Ok(contents) => run_lines(state, &contents), 
+        Err(_) => ControlFlow::Continue(()), 
+    }
+}
+
+///
+/// Runs `/etc/profile` and then `~/.rsh_profile` for a login shell, 
+/// leaving if either exits 
+///
+fn run_profiles(state: &mut State) {
+    if !state.login {
+        return; 
+    }
+    for profile in [Some(PathBuf::from("/etc/profile")), platform::home_file(".rsh_profile")].into_iter().flatten() {
+        if let ControlFlow::Break(code) = run_file(state, &profile) {
+            leave(state, code); 
+        }
+    }
+}
+
+///
+/// Location of the startup file: `$XDG_CONFIG_HOME/rsh/rc` if it exists, 
+/// otherwise `~/.rshrc` 
+///
+fn rc_path() -> Option<PathBuf> {
+    if let Some(config) = env::var_os("XDG_CONFIG_HOME") {
+        let rc = PathBuf::from(config).join("rsh").join("rc"); 
+        if rc.is_file() {
+            return Some(rc); 
+        }
+    }
+    platform::home_file(".rshrc")
+}
+
+///
+/// Runs text one line at a time, as if each were typed at the prompt 
+///
+/// Inputs: 
+///   Shell state the lines run in and may change 
+///   Contents of a file such as the startup file 
+///
+/// Output: 
+///   Break with the exit status if a line exited the shell 
+///
+fn run_lines(state: &mut State, contents: &str) -> ControlFlow<i32> {
+    let outer = state.line; 
+    let flow = run_numbered_lines(state, contents); 
+    state.line = outer; 
+    flow 
+}
+
+fn run_numbered_lines(state: &mut State, contents: &str) -> ControlFlow<i32> {
+    let mut pending = String::new(); 
+    // Line the pending command started on 
+    let mut start = 1; 
+    for (i, line) in contents.lines().enumerate() {
+        if pending.is_empty() {
+            start = i + 1; 
+        }
+        pending.push_str(line);
+        pending.push('\n');
+        if !incomplete(state, &pending) {
+            state.line = start; 
+            shell_run(state, std::mem::take(&mut pending))?; 
+        }
+    }
+
+    // Still incomplete at the end of the file, which reports the error 
+    if !pending.is_empty() {
+        state.line = start; 
+        shell_run(state, pending)?; 
+    }
+    ControlFlow::Continue(())
+}
+
+///
+/// Whether text stops part way through a command that continues on the 
+/// next line, like `while true; do`, `ls &&`, `echo 'open quote`, a line 
+/// ending in `\` or `cat <<EOF` 
+///
+fn incomplete(state: &State, text: &str) -> bool {
+    // Every lex error is input that ended too soon: inside quotes, after a 
+    // backslash or before the body of a here-document 
+    let Ok(tokens) = lexer::tokenize(text) else {
+        return true; 
+    };
+    expand_aliases(state, tokens)
+        .ok()
+        .is_some_and(|tokens| parser::parse(tokens)
+            .is_err_and(|e| matches!(e, ShellError::Syntax { error: parser::ParseError::Incomplete, .. })))
+}
+
+///
+/// Reads one line of input, replacing history references like `!!` when 
+/// interactive, and showing a line they changed as it will run 
+///
+/// Inputs: 
+///   Shell state holding the editor 
+///   Prompt to show first 
+///
+/// Output: 
+///   The line, None at end of input, or Interrupted on Ctrl-C. A line 
+///   naming an event not in the history is reported and comes back empty 
+///
+fn read_line(state: &mut State, prompt: &mut prompt::Prompt) -> Result<Option<String>> {
+    let mut line = read_raw_line(state, prompt)?; 
+    if let Some(text) = &mut line 
+        && jobs::interactive() {
+        match state.history.expand(text) {
+            Ok(Some(expanded)) => {
+                print!("{expanded}");
+                *text = expanded; 
+            }, 
+            Ok(None) => {}, 
+            Err(e) => {
+                eprintln!("rust-shell: {e}");
+                return Ok(Some("\n".to_string())); 
+            }
+        }
+    }
+    Ok(line)
+}
+
+///
+/// Joins the lines of a command into one history entry, as bash does with 
+/// `cmdhist`: `; ` goes between lines that each end a command, a space 
+/// after one left open, like `then` or `|`, and a line ending in `\` runs 
+/// on into the next. A newline in quotes or a here-document is kept 
+///
+fn history_entry(lines: &[String]) -> String {
+    let mut entry = String::new(); 
+    for line in lines {
+        let line = line.trim_end_matches('\n'); 
+        if entry.is_empty() {
+            entry.push_str(line); 
+            continue; 
+        }
+        let separator = match lexer::tokenize(&entry) {
+            Err(lexer::LexError::TrailingBackslash) => {
+                entry.pop(); 
+                "" 
+            },
+            Err(_) => "\n", 
+            Ok(tokens) => match &tokens[..] {
+                [.., Token::Pipe | Token::PipeAll | Token::And | Token::Or | Token::Semi | Token::DoubleSemi 
+                    | Token::Amp | Token::LParen | Token::RParen] => " ", 
+                // `in` is only a keyword as the third word of `for` or `case` 
+                [.., Token::Word(leading), Token::Word(_), Token::Word(last)] if last.to_string() == "in" => {
+                    if matches!(leading.to_string().as_str(), "for" | "case") { " " } else { "; " }
+                },
+                [.., Token::Word(last)] if opens(&last.to_string()) => " ", 
+                _ => "; ", 
+            },
+        }; 
+        entry.push_str(separator); 
+        entry.push_str(line); 
+    }
+    entry 
+}
+
+///
+/// Whether a reserved word leaves the command open for what follows it 
+///
+fn opens(word: &str) -> bool {
+    matches!(word, "if" | "then" | "else" | "elif" | "while" | "until" | "do" | "case" | "{" | "!")
+}
+
+///
+/// Renders a prompt template with what it can show of the last command: 
+/// its status, and how long it took if that was at least 
+/// `$PROMPT_DURATION` seconds, 2 if that is not set. Its `\{name}` 
+/// segments are worked out first 
+///
+fn render_prompt(state: &mut State, template: &str) -> prompt::Prompt {
+    let threshold = lookup(state, "PROMPT_DURATION")
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or(DURATION_THRESHOLD); 
+    let last = prompt::Last {
+        status: state.last_status, 
+        duration: Some(state.duration).filter(|duration| *duration >= threshold), 
+    }; 
+    let mut segments = HashMap::new(); 
+    for name in prompt::segment_names(template) {
+        let text = hooks::segment(state, &name).unwrap_or_else(|| function_segment(state, &name)); 
+        segments.insert(name, text); 
+    }
+    prompt::render(template, &segments, &mut state.git, &state.editor.theme, &last)
+}
+
+///
+/// Text of a `\{name}` prompt segment no plugin provides: what the 
+/// function of that name prints, or nothing if there is no such function 
+///
+fn function_segment(state: &mut State, name: &str) -> String {
+    if !state.functions.contains_key(name) {
+        return String::new(); 
+    }
+    let status = state.last_status; 
+    let text = command_substitution(state, name); 
+    state.last_status = status; 
+    text 
+}
+
+///
+/// Reads one command, which may go on over several lines 
+///
+/// Inputs: 
+///   Shell state holding the editor 
+///   Prompt to show before the first line; later ones get `$PS2`, or 
+///   `> ` if it is not set 
+///
+/// Output: 
+///   The command's lines, None at end of input, or Interrupted on Ctrl-C. 
+///   If input ends part way through, what was read is returned so running 
+///   it reports the error. When interactive the whole command is recorded 
+///   in the history as one entry 
+///
+fn read_command(state: &mut State, prompt: &mut prompt::Prompt) -> Result<Option<String>> {
+    let Some(mut input) = read_line(state, prompt)? else {
+        return Ok(None); 
+    };
+    let mut lines = vec![input.clone()]; 
+
+    // The right prompt belongs to the first line only 
+    state.editor.rprompt = prompt::Prompt::default(); 
+    while incomplete(state, &input) {
+        let template = lookup(state, "PS2").unwrap_or_else(|| CONTINUATION_PROMPT.to_string()); 
+        let mut prompt = render_prompt(state, &template); 
+        match read_line(state, &mut prompt)? {
+            Some(more) => {
+                input.push_str(&more); 
+                lines.push(more); 
+            },
+            None => break, 
+        }
+    }
+
+    if jobs::interactive() {
+        let filter = history::Filter::new(lookup(state, "HISTCONTROL").as_deref(), lookup(state, "HISTIGNORE").as_deref()); 
+        let size = history::max_size(lookup(state, "HISTSIZE").as_deref()); 
+        state.history.add(&history_entry(&lines), &filter, size); 
+    }
+    Ok(Some(input))
+}
+
+///
+/// Reads one line of input, through the line editor when interactive, 
+/// which fills in slow prompt segments as they arrive. Otherwise the 
+/// prompt is printed to stderr once it is complete, as bash does, and 
+/// only if stdin is a terminal, so piped commands print just their output 
+///
+fn read_raw_line(state: &mut State, prompt: &mut prompt::Prompt) -> Result<Option<String>> {
+    if jobs::interactive() {
+        // Aliases may have changed since the last line 
+        state.editor.completion.names = BUILTINS.iter()
+            .map(|name| name.to_string())
+            .chain(state.aliases.keys().cloned())
+            .chain(state.functions.keys().cloned())
+            .chain(state.path_table.names().cloned())
+            .collect(); 
+        state.editor.completion.descriptions = BUILTINS.iter()
+            .filter_map(|name| Some((name.to_string(), builtins::summary(name)?.to_string())))
+            .chain(state.functions.keys().map(|name| (name.clone(), "shell function".to_string())))
+            .chain(state.aliases.iter().map(|(name, value)| (name.clone(), format!("alias for {value}"))))
+            .collect(); 
+        state.editor.completion.set_jobs(state.jobs.iter()
+            .map(|job| (format!("%{}", job.id), job.command.clone()))
+            .collect()); 
+        state.editor.vi = state.options.vi; 
+        state.editor.wordchars = lookup(state, "WORDCHARS").unwrap_or_else(|| editor::WORDCHARS.to_string()); 
+        return state.editor.read_line(prompt, state.history.entries()); 
+    }
+
+    // SAFETY: isatty has no memory safety requirements 
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+        eprint!("{}", prompt.wait());
+        stderr().flush()?; 
+    }
+    read_stdin_line()
+}
+
+///
+/// Reads a line of stdin without taking anything after it, so commands 
+/// the line runs read the rest, as in `printf 'read x\nhello\n' | shell`. 
+/// A file is read a block at a time and its offset put back to just 
+/// after the line; a pipe, which cannot be, is read a byte at a time 
+///
+/// Output: 
+///   The line with its newline, None at end of input 
+///
+fn read_stdin_line() -> Result<Option<String>> {
+    let mut bytes = Vec::new(); 
+    // SAFETY: lseek has no memory safety requirements; a zero offset from 
+    // the current one moves nothing 
+    let seekable = unsafe { libc::lseek(libc::STDIN_FILENO, 0, libc::SEEK_CUR) } >= 0; 
+    if seekable {
+        let mut block = [0u8; 4096]; 
+        loop {
+            // SAFETY: reads at most the length of a buffer we own 
+            let n = unsafe { libc::read(libc::STDIN_FILENO, block.as_mut_ptr().cast(), block.len()) }; 
+            if n < 0 {
+                let e = Error::last_os_error(); 
+                if e.kind() == ErrorKind::Interrupted {
+                    continue; 
+                }
+                return Err(e); 
+            }
+            let read = &block[..n as usize]; 
+            if let Some(end) = read.iter().position(|&byte| byte == b'\n') {
+                bytes.extend_from_slice(&read[..=end]);
+                // SAFETY: as above, moving back over what followed the line 
+                unsafe { libc::lseek(libc::STDIN_FILENO, end as libc::off_t + 1 - n as libc::off_t, libc::SEEK_CUR) }; 
+                break; 
+            }
+            if read.is_empty() {
+                break; 
+            }
+            bytes.extend_from_slice(read);
+        }
+    } else {
+        while let Some(byte) = editor::read_byte()? {
+            bytes.push(byte);
+            if byte == b'\n' {
+                break; 
+            }
+        }
+    }
+    Ok((!bytes.is_empty()).then(|| String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+///
+/// Exits the shell, running `~/.rsh_logout` if it is a login shell and 
+/// then the EXIT trap 
+///
+fn leave(state: &mut State, code: i32) -> ! {
+    let mut code = code; 
+    if std::mem::take(&mut state.login) 
+        && let Some(logout) = platform::home_file(".rsh_logout") 
+        && let ControlFlow::Break(exited) = run_file(state, &logout) {
+        code = exited; 
+    }
+    let code = trap::on_exit(state, code); 
+    stdout().flush().ok(); 
+    jobs::release_control(); 
+    exit(code)
+}
+
+///
+/// Runs the shell as the `shell` binary does, reading commands from the 
+/// terminal or from a script or `-c` string 
+///
+/// Input: 
+///   Command line arguments, the program name first: a name starting 
+///   with `-`, as login passes it, or `-l`/`--login` make a login shell, 
+///   which runs `/etc/profile` and `~/.rsh_profile` first unless 
+///   `--noprofile` is given, and an interactive shell runs `~/.rshrc` 
+///   unless `--norc` is. Then `-r` for a restricted shell, `-c text` to 
+///   run text, or a script path, and after `--` or the script path the 
+///   script's arguments, `$1` onwards. 
+///   With `-c`, the first operand is the name `$0` gives instead of the 
+///   shell's, and the rest are the arguments 
+///
+pub fn start(args: impl IntoIterator<Item = String>) -> ! {
+    start_with(Shell::new(), args)
+}
+
+///
+/// Runs the shell as `start` does, from a shell already set up, such as 
+/// with plugins and prompt segments added 
+///
+pub fn start_with(mut shell: Shell, args: impl IntoIterator<Item = String>) -> ! {
+    let state = &mut shell.state; 
+    let mut restrict = false; 
+    let mut profile = true; 
+    let mut rc = true; 
+    let mut command = None; 
+    let mut script = None; 
+
+    let mut args = args.into_iter(); 
+    state.name = args.next().unwrap_or_default(); 
+    state.login = state.name.starts_with('-'); 
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                script = args.next(); 
+                break; 
+            },
+            "-l" | "--login" => state.login = true, 
+            "-r" | "--restricted" => restrict = true, 
+            "--noprofile" => profile = false, 
+            "--norc" => rc = false, 
+            "-c" => match args.next() {
+                Some(text) => command = Some(text), 
+                None => {
+                    eprintln!("rust-shell: -c: option requires an argument");
+                    exit(2);
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("rust-shell: {arg}: invalid option");
+                exit(2);
+            },
+            // The first operand is a script to run 
+            _ => {
+                script = Some(arg); 
+                break; 
+            }
+        }
+    }
+    state.positional = args.collect(); 
+    if let Some(name) = &script {
+        state.name = name.clone(); 
+    }
+
+    let given = match (command, script) {
+        (Some(text), _) => Some(text), 
+        (None, Some(path)) => match fs::read_to_string(&path) {
+            Ok(contents) => Some(contents), 
+            Err(error) => {
+                let error = ShellError::Spawn { command: path, error }; 
+                error.print(); 
+                exit(error.status());
+            }
+        },
+        (None, None) => None, 
+    };
+
+    // A command string or script runs without prompt, editor or startup file 
+    if let Some(contents) = given {
+        jobs::set_noninteractive(); 
+        if profile {
+            run_profiles(state); 
+        }
+        state.restricted = restrict; 
+
+        let code = match run_lines(state, &contents) {
+            ControlFlow::Break(code) => code, 
+            ControlFlow::Continue(()) => state.last_status, 
+        };
+        leave(state, code); 
+    }
+
+    if jobs::interactive() {
+        jobs::take_control(); 
+        signals::install_handlers(); 
+        state.history.load(history::max_size(lookup(state, "HISTSIZE").as_deref())); 
+        state.path_table.rehash(); 
+        if profile {
+            run_profiles(state); 
+        }
+
+        if rc 
+            && let Some(rc) = rc_path() 
+            && let ControlFlow::Break(code) = run_file(state, &rc) {
+            leave(state, code); 
+        }
+        // After the rc file, so it can pick the theme or its own colours 
+        theme::export_colours(&state.editor.theme); 
+    } else if profile {
+        run_profiles(state); 
+    }
+
+    // Like bash, restrictions apply only once the startup file has run 
+    state.restricted = restrict; 
+
+    // Shell loop 
+    loop {
+        builtins::notify_jobs(state); 
+        if let ControlFlow::Break(code) = trap::run_pending(state) {
+            leave(state, code); 
+        }
+        if jobs::interactive() 
+            && let ControlFlow::Break(code) = hooks::run(state, hooks::Event::Precmd, &[]) {
+            leave(state, code); 
+        }
+
+        // End of input leaves like exit without an argument; Ctrl-C drops 
+        // the line and starts over 
+        let template = lookup(state, "PROMPT")
+            .or_else(|| lookup(state, "PS1"))
+            .unwrap_or_else(|| prompt::DEFAULT.to_string()); 
+        let mut prompt = render_prompt(state, &template); 
+        state.editor.rprompt = lookup(state, "RPROMPT")
+            .map(|template| render_prompt(state, &template))
+            .unwrap_or_default(); 
+        let input = match read_command(state, &mut prompt) {
+            Ok(Some(input)) => input, 
+            Err(e) if e.kind() == ErrorKind::Interrupted => {
+                println!(); 
+                state.last_status = 130; 
+                // The editor reads Ctrl-C as a key, so no signal arrives 
+                if let ControlFlow::Break(code) = trap::run(state, libc::SIGINT) {
+                    leave(state, code); 
+                }
+                continue; 
+            },
+            Ok(None) | Err(_) => {
+                // Ends the line the prompt was on, which piped input has not got 
+                if jobs::interactive() {
+                    println!(); 
+                }
+                let code = state.last_status; 
+                leave(state, code); 
+            },
+        };
+
+        let line = input.trim_end_matches('\n'); 
+        if jobs::interactive() 
+            && !line.trim().is_empty() 
+            && let ControlFlow::Break(code) = hooks::run(state, hooks::Event::Preexec, &[line.to_string()]) {
+            leave(state, code); 
+        }
+
+        // Iterable over commands split by a pipeline 
+        let started = Instant::now(); 
+        let flow = shell_run(state, input); 
+        state.duration = started.elapsed(); 
+        if let ControlFlow::Break(code) = flow { 
+            leave(state, code); 
+        }    
+    }
+}
@@ -0,0 +1,264 @@
+use std::env;
+
+use crate::error;
+use crate::{lookup, State};
+
+///
+/// Shell options switched with `set`
+///
+#[derive(Default)]
+pub struct Options {
+    /// `-e`: exit as soon as a command fails, outside of conditions
+    pub errexit: bool,
+    /// `-x`: print each command to stderr, expanded, before running it
+    pub xtrace: bool,
+    /// `-o pipefail`: a pipeline fails if any of its stages does
+    pub pipefail: bool,
+    /// `-o vi`: the line editor takes vi keys; `-o emacs`, the default,
+    /// switches it back
+    pub vi: bool,
+    /// `-o globstar`: `**` as a whole part of a path matches any number
+    /// of directories
+    pub globstar: bool,
+    /// `-o nullglob`: a pattern matching no files is dropped rather than
+    /// kept as written
+    pub nullglob: bool,
+    /// `-o failglob`: a pattern matching no files is an error and the
+    /// command does not run
+    pub failglob: bool,
+    /// `-u`: expanding a variable that is unset is an error
+    pub nounset: bool,
+    /// `-f`: words are never matched against files as patterns
+    pub noglob: bool,
+    /// `-C`: `>` refuses to write over a file that exists; `>|` still does
+    pub noclobber: bool,
+    /// `-o nopager`: long output of builtins such as `history` is never
+    /// sent through `$PAGER`
+    pub nopager: bool,
+    /// `shopt -s xpg_echo`: echo interprets backslash escapes without `-e`
+    pub xpg_echo: bool,
+}
+
+/// Long name of each option, with the letter that also switches it
+const NAMES: &[(&str, Option<char>)] = &[
+    ("emacs", None), ("errexit", Some('e')), ("failglob", None), ("globstar", None), ("noclobber", Some('C')),
+    ("noglob", Some('f')), ("nopager", None), ("nounset", Some('u')), ("nullglob", None), ("pipefail", None),
+    ("vi", None), ("xtrace", Some('x')),
+];
+
+/// Options `shopt` switches, some of which `set -o` does too, as in bash
+const SHOPT_NAMES: &[&str] = &["failglob", "globstar", "nullglob", "xpg_echo"];
+
+impl Options {
+    ///
+    /// Switches an option by long name
+    ///
+    /// Output:
+    ///   false if there is no such option
+    ///
+    fn set(&mut self, name: &str, on: bool) -> bool {
+        match name {
+            // The two editing modes are one switch
+            "emacs" => self.vi = !on,
+            "errexit" => self.errexit = on,
+            "failglob" => self.failglob = on,
+            "globstar" => self.globstar = on,
+            "noclobber" => self.noclobber = on,
+            "noglob" => self.noglob = on,
+            "nopager" => self.nopager = on,
+            "nounset" => self.nounset = on,
+            "nullglob" => self.nullglob = on,
+            "pipefail" => self.pipefail = on,
+            "vi" => self.vi = on,
+            "xtrace" => self.xtrace = on,
+            "xpg_echo" => self.xpg_echo = on,
+            _ => return false,
+        }
+        true
+    }
+
+    fn get(&self, name: &str) -> bool {
+        match name {
+            "emacs" => !self.vi,
+            "errexit" => self.errexit,
+            "failglob" => self.failglob,
+            "globstar" => self.globstar,
+            "noclobber" => self.noclobber,
+            "noglob" => self.noglob,
+            "nopager" => self.nopager,
+            "nounset" => self.nounset,
+            "nullglob" => self.nullglob,
+            "vi" => self.vi,
+            "pipefail" => self.pipefail,
+            "xtrace" => self.xtrace,
+            "xpg_echo" => self.xpg_echo,
+            _ => false,
+        }
+    }
+}
+
+///
+/// Quotes a word, if it needs it, so a trace can be pasted back in
+///
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word.chars().all(|c| c.is_alphanumeric() || "-_./:=,+%@~^".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+///
+/// Prints a command for `set -x`, after `$PS4` (`+ ` when unset)
+///
+/// Inputs:
+///   Shell state, for `$PS4`
+///   Assignments written before the command
+///   Expanded command name and arguments
+///
+pub fn trace(state: &State, assignments: &[(String, String)], argv: &[String]) {
+    let prefix = lookup(state, "PS4").unwrap_or_else(|| "+ ".to_string());
+
+    let words: Vec<String> = assignments.iter()
+        .map(|(name, value)| format!("{name}={}", quote(value)))
+        .chain(argv.iter().map(|arg| quote(arg)))
+        .collect();
+    eprintln!("{prefix}{}", words.join(" "));
+}
+
+///
+/// Builtin set: switches shell options or sets the positional parameters
+///
+/// Inputs:
+///   Shell state holding the options, variables and positional parameters
+///   Arguments after `set`. `-e`/`-x`/`-u`/`-f`/`-C` switch an option on
+///   and `+e`/`+x`/... off, letters combining as in `-ex`; `-o name`/`+o name` do the same by
+///   long name. `-o` alone lists the options and `+o` alone prints the
+///   commands that would restore them. Words after `--`, or after the last
+///   option, become `$1` onwards. With no arguments it lists the variables
+///
+/// Output:
+///   Exit status, 2 for an unknown option
+///
+pub fn builtin_set(state: &mut State, args: &[String]) -> i32 {
+    if args.is_empty() {
+        let mut vars: Vec<(String, String)> = env::vars()
+            .chain(state.vars.iter().map(|(name, value)| (name.clone(), value.clone())))
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            println!("{name}={}", quote(&value));
+        }
+        return 0;
+    }
+
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let (on, flags) = match arg.as_str() {
+            "--" => {
+                state.positional = args.cloned().collect();
+                return 0;
+            }
+            arg if arg.len() > 1 && arg.starts_with('-') => (true, &arg[1..]),
+            arg if arg.len() > 1 && arg.starts_with('+') => (false, &arg[1..]),
+            _ => {
+                state.positional = std::iter::once(arg).chain(args).cloned().collect();
+                return 0;
+            }
+        };
+
+        if flags == "o" {
+            let Some(name) = args.next_if(|name| !name.starts_with(['-', '+'])) else {
+                list(&state.options, on);
+                continue;
+            };
+            if !state.options.set(name, on) {
+                return error::usage("set", format!("{name}: invalid option name"));
+            }
+            continue;
+        }
+
+        for letter in flags.chars() {
+            let name = NAMES.iter().find(|(_, short)| *short == Some(letter));
+            if !name.is_some_and(|(name, _)| state.options.set(name, on)) {
+                return error::usage("set", format!("{}{letter}: invalid option", if on { '-' } else { '+' }));
+            }
+        }
+    }
+    0
+}
+
+///
+/// Shows the options for `set -o`, or as `set` commands for `set +o`
+///
+fn list(options: &Options, readable: bool) {
+    for (name, _) in NAMES {
+        let on = options.get(name);
+        if readable {
+            println!("{name:<15}{}", if on { "on" } else { "off" });
+        } else {
+            println!("set {}o {name}", if on { '-' } else { '+' });
+        }
+    }
+}
+
+///
+/// Builtin shopt: switches or shows the options bash keeps apart from `set`
+///
+/// Inputs:
+///   Shell state holding the options
+///   Arguments after `shopt`: `-s` switches the named options on and `-u`
+///   off, `-q` only tells through the status whether they are all on,
+///   and `-p` prints them as `shopt` commands. Without names every option
+///   is shown, or with `-s` or `-u` those on or off
+///
+/// Output:
+///   Exit status: 0, or when querying or showing named options, 1 if any
+///   is off. 1 for a name that is not an option, 2 for a bad flag
+///
+pub fn builtin_shopt(state: &mut State, args: &[String]) -> i32 {
+    let (mut switch, mut quiet, mut commands) = (None, false, false);
+    let mut args = args.iter().peekable();
+    while let Some(flags) = args.next_if(|arg| arg.len() > 1 && arg.starts_with('-')) {
+        for flag in flags[1..].chars() {
+            match flag {
+                's' => switch = Some(true),
+                'u' => switch = Some(false),
+                'q' => quiet = true,
+                'p' => commands = true,
+                _ => return error::usage("shopt", format!("-{flag}: invalid option")),
+            }
+        }
+    }
+
+    let named = args.peek().is_some();
+    let names: Vec<&str> = match named {
+        true => args.map(String::as_str).collect(),
+        false => SHOPT_NAMES.iter().copied()
+            .filter(|name| switch.is_none_or(|on| state.options.get(name) == on))
+            .collect(),
+    };
+    if let Some(name) = names.iter().find(|name| !SHOPT_NAMES.contains(name)) {
+        return error::builtin("shopt", format!("{name}: invalid shell option name"));
+    }
+
+    if named && let Some(on) = switch {
+        for name in names {
+            state.options.set(name, on);
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for name in names {
+        let on = state.options.get(name);
+        status |= i32::from(named && !on);
+        match (quiet, commands) {
+            (true, _) => {}
+            (false, true) => println!("shopt {} {name}", if on { "-s" } else { "-u" }),
+            (false, false) => println!("{name:<15}{}", if on { "on" } else { "off" }),
+        }
+    }
+    status
+}
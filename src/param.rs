@@ -0,0 +1,310 @@
+use crate::error::ShellError;
+use crate::lexer::is_name;
+use crate::pattern::{self, Pattern};
+use crate::{assign, expand_vars, lookup, State};
+
+///
+/// Whether the text between `${` and `}` is just a parameter, with no
+/// operator to apply
+///
+pub fn is_plain(body: &str) -> bool {
+    matches!(body, "?" | "#" | "@" | "*" | "!")
+        || (!body.is_empty() && body.bytes().all(|b| b.is_ascii_digit()))
+        || is_name(body)
+}
+
+///
+/// Splits the parameter off the front of a braced expansion
+///
+/// Output:
+///   The parameter's name and the operator text after it
+///
+fn split_name(body: &str) -> (&str, &str) {
+    if body.starts_with(['?', '#', '@', '*', '!']) {
+        return body.split_at(1);
+    }
+    let end = if body.starts_with(|c: char| c.is_ascii_digit()) {
+        body.find(|c: char| !c.is_ascii_digit())
+    } else {
+        body.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+    };
+    body.split_at(end.unwrap_or(body.len()))
+}
+
+///
+/// Value of a parameter, None if it is unset
+///
+fn value(state: &State, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(state.last_status.to_string()),
+        "#" => Some(state.positional.len().to_string()),
+        "!" => state.last_background.map(|pid| pid.to_string()),
+        "0" => Some(state.name.clone()),
+        "@" | "*" => Some(state.positional.join(" ")),
+        digits if digits.bytes().all(|b| b.is_ascii_digit()) => {
+            digits.parse::<usize>().ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| state.positional.get(i))
+                .cloned()
+        }
+        name => lookup(state, name),
+    }
+}
+
+///
+/// Value of a parameter that is used as it is rather than tested
+///
+/// Inputs:
+///   Shell state, for `set -u`
+///   Name of the parameter and its value, None if it is unset
+///
+/// Output:
+///   The value, empty if it is unset, or an error for that under `set -u`
+///
+pub fn required(state: &State, name: &str, value: Option<String>) -> Result<String, ShellError> {
+    match value {
+        Some(value) => Ok(value),
+        None if state.options.nounset => {
+            Err(ShellError::Expansion { text: name.to_string(), message: "unbound variable".to_string() })
+        }
+        None => Ok(String::new()),
+    }
+}
+
+///
+/// Expands the word after an operator, such as the default of `${x:-word}`
+///
+/// Inputs:
+///   Shell state, for variables and substitutions in the word
+///   Word as written, quotes included
+///   Whether the word is a pattern, so its quoted parts are escaped to
+///   match literally
+///
+/// Output:
+///   The expanded word, or the error from an expansion in it that failed
+///
+fn operand(state: &mut State, text: &str, pattern: bool) -> Result<String, ShellError> {
+    let mut out = String::new();
+    let literal = |out: &mut String, text: &str| {
+        out.push_str(&if pattern { pattern::escape(text) } else { text.to_string() });
+    };
+    let expand = |state: &mut State, text: &str| -> Result<String, ShellError> {
+        Ok(expand_vars(state, text, false)?.map_or_else(|| text.to_string(), |fields| fields.concat()))
+    };
+
+    let mut rest = text;
+    while !rest.is_empty() {
+        let run = rest.find(['\\', '\'', '"']).unwrap_or(rest.len());
+        out.push_str(&expand(state, &rest[..run])?);
+        rest = &rest[run..];
+
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('\\') => {
+                let escaped: String = chars.next().into_iter().collect();
+                literal(&mut out, &escaped);
+                rest = &rest[1 + escaped.len()..];
+            }
+            Some('\'') => {
+                let end = rest[1..].find('\'').map_or(rest.len(), |end| end + 1);
+                literal(&mut out, &rest[1..end]);
+                rest = rest.get(end + 1..).unwrap_or_default();
+            }
+            Some('"') => {
+                let end = rest[1..].find('"').map_or(rest.len(), |end| end + 1);
+                let inner = expand(state, &rest[1..end])?;
+                literal(&mut out, &inner);
+                rest = rest.get(end + 1..).unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+///
+/// Byte offsets of every character boundary in text, end included
+///
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices().map(|(i, _)| i).chain(std::iter::once(text.len())).collect()
+}
+
+///
+/// Removes the shortest or longest prefix (`#`/`##`) or suffix (`%`/`%%`)
+/// matching a pattern
+///
+fn strip(value: &str, pattern: &Pattern, suffix: bool, longest: bool) -> String {
+    let mut cuts = boundaries(value);
+    // Shortest first means the fewest characters taken
+    if suffix != longest {
+        cuts.reverse();
+    }
+    for cut in cuts {
+        let (taken, kept) = if suffix {
+            (&value[cut..], &value[..cut])
+        } else {
+            (&value[..cut], &value[cut..])
+        };
+        if pattern.matches(taken) {
+            return kept.to_string();
+        }
+    }
+    value.to_string()
+}
+
+///
+/// Replaces the longest matches of a pattern, as `${x/pat/repl}` does
+///
+/// Inputs:
+///   Value to change and the pattern to look for
+///   Replacement text
+///   The operator: `/` for the first match, `//` for every match, `/#`
+///   for one at the start only and `/%` for one at the end only
+///
+fn replace(value: &str, pattern: &Pattern, replacement: &str, mode: &str) -> String {
+    let cuts = boundaries(value);
+    let mut out = String::new();
+    let mut from = 0;
+    let mut replaced = false;
+
+    for (index, &start) in cuts.iter().enumerate() {
+        if start < from || (mode == "/#" && start > 0) {
+            continue;
+        }
+        if replaced && mode != "//" {
+            break;
+        }
+        let found = cuts[index..].iter().rev()
+            .filter(|&&end| end > start && (mode != "/%" || end == value.len()))
+            .find(|&&end| pattern.matches(&value[start..end]));
+        if let Some(&end) = found {
+            out.push_str(&value[from..start]);
+            out.push_str(replacement);
+            from = end;
+            replaced = true;
+        }
+    }
+    out.push_str(&value[from..]);
+    out
+}
+
+///
+/// Expands `${...}` with an operator applied to the parameter
+///
+/// `${#x}` is the length of x. `${x:-w}` gives w if x is unset or empty,
+/// `${x:=w}` also assigns it, `${x:?w}` reports w as an error instead and
+/// `${x:+w}` gives w only if x is set and not empty; without the `:` only
+/// unset counts. `${x#p}`/`${x##p}` remove the shortest/longest prefix
+/// matching p and `${x%p}`/`${x%%p}` the suffix. `${x/p/r}` replaces the
+/// first match of p with r, `${x//p/r}` every match, and `${x/#p/r}`/
+/// `${x/%p/r}` only one at the start/end
+///
+/// Inputs:
+///   Shell state holding the parameters
+///   Text between `${` and `}`
+///
+/// Output:
+///   The expanded text, or the error to stop the command with, as for
+///   `${x:?}` with x unset
+///
+pub fn expand(state: &mut State, body: &str) -> Result<String, ShellError> {
+    let bad = || ShellError::Expansion { text: format!("${{{body}}}"), message: "bad substitution".to_string() };
+
+    if let Some(name) = body.strip_prefix('#')
+        && is_plain(name) {
+        let length = match name {
+            "@" | "*" => state.positional.len(),
+            name => required(state, name, value(state, name))?.chars().count(),
+        };
+        return Ok(length.to_string());
+    }
+
+    let (name, op) = split_name(body);
+    if name.is_empty() {
+        return Err(bad());
+    }
+    let current = value(state, name);
+
+    let (colon, op) = match op.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, op),
+    };
+    // With `:` an empty value counts as unset
+    let set = current.as_ref().filter(|value| !colon || !value.is_empty());
+
+    let Some(kind) = op.chars().next() else {
+        return if colon { Err(bad()) } else { required(state, name, current) };
+    };
+    let word = &op[kind.len_utf8()..];
+
+    match kind {
+        '-' => Ok(match set {
+            Some(value) => value.clone(),
+            None => operand(state, word, false)?,
+        }),
+        '+' => Ok(match set {
+            Some(_) => operand(state, word, false)?,
+            None => String::new(),
+        }),
+        '=' => {
+            if let Some(value) = set {
+                return Ok(value.clone());
+            }
+            if !is_name(name) {
+                return Err(ShellError::Expansion { text: format!("${name}"), message: "cannot assign in this way".to_string() });
+            }
+            let value = operand(state, word, false)?;
+            assign(state, name, value.clone())?;
+            Ok(value)
+        }
+        '?' => match set {
+            Some(value) => Ok(value.clone()),
+            None => {
+                let message = operand(state, word, false)?;
+                let message = if message.is_empty() { "parameter null or not set".to_string() } else { message };
+                Err(ShellError::Expansion { text: name.to_string(), message })
+            }
+        },
+        '#' | '%' if !colon => {
+            let longest = word.starts_with(kind);
+            let word = if longest { &word[1..] } else { word };
+            let pattern = Pattern::new(&operand(state, word, true)?);
+            Ok(strip(&required(state, name, current)?, &pattern, kind == '%', longest))
+        }
+        '/' if !colon => {
+            let (mode, word) = match word.chars().next() {
+                Some(c @ ('/' | '#' | '%')) => (&op[..2], &word[c.len_utf8()..]),
+                _ => ("/", word),
+            };
+            let (find, replacement) = split_replacement(word);
+            let pattern = operand(state, find, true)?;
+            let value = required(state, name, current)?;
+            if pattern.is_empty() {
+                return Ok(value);
+            }
+            let pattern = Pattern::new(&pattern);
+            let replacement = operand(state, replacement, false)?;
+            Ok(replace(&value, &pattern, &replacement, mode))
+        }
+        _ => Err(bad()),
+    }
+}
+
+///
+/// Splits `pat/repl` at the first `/` that is not quoted or escaped
+///
+fn split_replacement(word: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in word.char_indices() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', q) if q != Some('\'') => escaped = true,
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('/', None) => return (&word[..i], &word[i + 1..]),
+            _ => {}
+        }
+    }
+    (word, "")
+}
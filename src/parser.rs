@@ -0,0 +1,577 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+use crate::error::ShellError;
+use crate::lexer::{self, RedirectOp, Token, Word};
+use crate::redirect::Redirect;
+
+/// Words that start or continue a compound command where a command could
+/// start; quoting any part of one makes it an ordinary word
+pub const RESERVED: &[&str] = &["case", "do", "done", "elif", "else", "esac", "fi", "for", "if", "then", "time", "until", "while", "{", "}"];
+
+///
+/// Simple command: its words and where its fds point
+///
+#[derive(Default)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl SimpleCommand {
+    fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.redirects.is_empty()
+    }
+}
+
+///
+/// Control structure run by the shell itself
+///
+pub enum Compound {
+    /// `if c; then a; elif d; then b; else e; fi`, as condition and body
+    /// pairs in order and what runs when none matched
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while c; do a; done`, or `until` running while c fails
+    While {
+        condition: List,
+        body: List,
+        until: bool,
+    },
+    /// `for name in words; do a; done`. Without `in`, the words are the
+    /// positional parameters
+    For {
+        name: String,
+        items: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `{ a; b; }`, commands grouped to run as one
+    Group(List),
+    /// `( a; b )`, commands grouped to run in a copy of the shell, so
+    /// changes they make to it are lost when it exits
+    Subshell(List),
+    /// `case word in a|b) x;; *) y;; esac`, the patterns of each branch
+    /// and what runs for the first to match
+    Case {
+        word: Word,
+        branches: Vec<(Vec<Word>, List)>,
+    },
+}
+
+///
+/// One stage of a pipeline
+///
+pub enum Command {
+    Simple(SimpleCommand),
+    /// Control structure with redirections written after its end, which
+    /// apply to everything inside it
+    Compound(Compound, Vec<Redirect>),
+    /// `name() body`, defining a function whose body is a compound command
+    Function(String, Rc<Command>),
+}
+
+///
+/// Writes redirections back out, e.g. `2>&1`
+///
+fn redirects_text(redirects: &[Redirect]) -> impl Iterator<Item = String> + '_ {
+    redirects.iter().map(|r| {
+        let op = Token::Redirect { fd: Some(r.fd), op: r.op };
+        // A here-document's target is its whole body
+        match r.op {
+            RedirectOp::HereDoc { .. } => format!("{op}..."),
+            _ => format!("{op}{}", r.target),
+        }
+    })
+}
+
+///
+/// Commands connected stdout to stdin by `|`
+///
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Started with `time`, so how long it took is reported
+    pub timed: bool,
+}
+
+impl fmt::Display for Pipeline {
+    ///
+    /// Writes the pipeline back out as text, for the jobs table
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.timed {
+            f.write_str("time ")?;
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" | ")?;
+            }
+            let parts: Vec<String> = match command {
+                Command::Simple(simple) => simple.words.iter()
+                    .map(Word::to_string)
+                    .chain(redirects_text(&simple.redirects))
+                    .collect(),
+                // Jobs only need a recognisable name, not the whole body
+                Command::Compound(compound, redirects) => {
+                    let keyword = match compound {
+                        Compound::If { .. } => "if ... fi",
+                        Compound::While { until: false, .. } => "while ... done",
+                        Compound::While { until: true, .. } => "until ... done",
+                        Compound::For { .. } => "for ... done",
+                        Compound::Group(_) => "{ ... }",
+                        Compound::Subshell(_) => "( ... )",
+                        Compound::Case { .. } => "case ... esac",
+                    };
+                    std::iter::once(keyword.to_string()).chain(redirects_text(redirects)).collect()
+                }
+                Command::Function(name, _) => vec![format!("{name}() {{ ... }}")],
+            };
+            f.write_str(&parts.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// How a pipeline in a list depends on the one before it
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connector {
+    /// First in the list, or after `;`
+    Always,
+    /// After `&&`
+    IfSuccess,
+    /// After `||`
+    IfFailure,
+}
+
+///
+/// Pipeline of a command list, with how and where it runs
+///
+pub struct ListItem {
+    pub connector: Connector,
+    pub pipeline: Pipeline,
+    /// Followed by `&`
+    pub background: bool,
+}
+
+///
+/// Pipelines separated by `;`, `&`, `&&` and `||`
+///
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+///
+/// Reasons tokens do not form a valid command line
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Token found where it cannot go
+    Unexpected(String),
+    /// Input ended inside a control structure or after an operator that
+    /// needs a command after it, so more lines may complete it
+    Incomplete,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Unexpected(token) => write!(f, "syntax error near unexpected token '{token}'"),
+            ParseError::Incomplete => f.write_str("syntax error: unexpected end of file"),
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+///
+/// Recursive descent parser over the tokens of one line
+///
+struct Parser {
+    tokens: Peekable<IntoIter<Token>>,
+}
+
+impl Parser {
+    fn unexpected(token: Option<Token>) -> ParseError {
+        match token {
+            Some(token) => ParseError::Unexpected(token.to_string()),
+            None => ParseError::Incomplete,
+        }
+    }
+
+    ///
+    /// Reserved word the next token is, if it is one
+    ///
+    fn peek_reserved(&mut self) -> Option<&'static str> {
+        let Some(Token::Word(word)) = self.tokens.peek() else {
+            return None;
+        };
+        let text = word.bare_text()?;
+        RESERVED.iter().copied().find(|&reserved| reserved == text)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.tokens.next_if_eq(&Token::Newline).is_some() {}
+    }
+
+    ///
+    /// Takes the reserved word expected next, such as the `fi` of an `if`
+    ///
+    fn expect(&mut self, reserved: &str) -> ParseResult<()> {
+        if self.peek_reserved() == Some(reserved) {
+            self.tokens.next();
+            return Ok(());
+        }
+        Err(Parser::unexpected(self.tokens.next()))
+    }
+
+    ///
+    /// Parses pipelines up to the end of the tokens or one of the reserved
+    /// words that end the enclosing structure, which is left to be taken
+    ///
+    /// Pipelines are separated by `;`, `&` or newlines, and `&` backgrounds
+    /// just the pipeline right before it. An operator with no pipeline
+    /// before it is an error; `&&`/`||` with none after it, or tokens that
+    /// run out before `until` is reached, leave the list incomplete. A `)`
+    /// in `until` stands for the token closing a subshell
+    ///
+    fn list(&mut self, until: &[&str]) -> ParseResult<List> {
+        let mut items = Vec::new();
+        let mut connector = Connector::Always;
+
+        // A branch of `case` also ends at `;;`, and a subshell at `)`
+        let closer = if until.contains(&"esac") {
+            Some(Token::DoubleSemi)
+        } else if until.contains(&")") {
+            Some(Token::RParen)
+        } else {
+            None
+        };
+        let at_closer = |token: Option<&Token>| closer.is_some() && token == closer.as_ref();
+
+        loop {
+            self.skip_newlines();
+            if connector == Connector::Always {
+                match self.peek_reserved() {
+                    Some(reserved) if until.contains(&reserved) => break,
+                    _ if at_closer(self.tokens.peek()) => break,
+                    _ if self.tokens.peek().is_none() && until.is_empty() => break,
+                    _ => {}
+                }
+            }
+
+            let pipeline = self.pipeline()?;
+            // A structure can end straight after a compound command
+            let ends = self.peek_reserved().is_some_and(|reserved| until.contains(&reserved))
+                || at_closer(self.tokens.peek());
+            let (next, background) = match self.tokens.peek() {
+                Some(Token::Semi | Token::Newline) => (Connector::Always, false),
+                Some(Token::Amp) => (Connector::Always, true),
+                Some(Token::And) => (Connector::IfSuccess, false),
+                Some(Token::Or) => (Connector::IfFailure, false),
+                None => (Connector::Always, false),
+                Some(_) if ends => {
+                    items.push(ListItem { connector, pipeline, background: false });
+                    break;
+                }
+                Some(_) => return Err(Parser::unexpected(self.tokens.next())),
+            };
+            self.tokens.next();
+            items.push(ListItem { connector, pipeline, background });
+            connector = next;
+        }
+
+        Ok(List { items })
+    }
+
+    ///
+    /// Parses a list that must hold at least one command, as the parts of
+    /// a control structure do
+    ///
+    fn body(&mut self, until: &[&str]) -> ParseResult<List> {
+        let list = self.list(until)?;
+        if list.items.is_empty() {
+            return Err(Parser::unexpected(self.tokens.next()));
+        }
+        Ok(list)
+    }
+
+    ///
+    /// Parses commands joined by `|` or `|&`
+    ///
+    /// A pipe with nothing before it (`| grep`, `ls | | grep`) is an error,
+    /// and one at the end of a line continues the pipeline on the next
+    ///
+    fn pipeline(&mut self) -> ParseResult<Pipeline> {
+        // `time` times the whole pipeline, and may time nothing at all
+        let timed = self.peek_reserved() == Some("time");
+        if timed {
+            self.tokens.next();
+            if matches!(self.tokens.peek(), None | Some(Token::Semi | Token::Newline | Token::Amp)) {
+                return Ok(Pipeline { commands: Vec::new(), timed });
+            }
+        }
+        let mut commands = vec![self.command()?];
+
+        while let Some(pipe @ (Token::Pipe | Token::PipeAll)) = self.tokens.peek() {
+            // `a |& b` is short for `a 2>&1 | b`; a definition writes nothing
+            let redirects = match commands.last_mut() {
+                Some(Command::Simple(simple)) => Some(&mut simple.redirects),
+                Some(Command::Compound(_, redirects)) => Some(redirects),
+                _ => None,
+            };
+            if *pipe == Token::PipeAll
+                && let Some(redirects) = redirects {
+                redirects.push(Redirect::new(Some(2), RedirectOp::DupOut, Word::bare("1")));
+            }
+            self.tokens.next();
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands, timed })
+    }
+
+    ///
+    /// Parses one command: a control structure if it starts with a
+    /// reserved word that opens one or with `(`, a function definition if
+    /// its name is followed by `()`, otherwise a simple command
+    ///
+    fn command(&mut self) -> ParseResult<Command> {
+        let compound = match self.peek_reserved() {
+            Some("{") => {
+                self.tokens.next();
+                let body = self.body(&["}"])?;
+                self.expect("}")?;
+                Compound::Group(body)
+            }
+            Some("if") => self.if_clause()?,
+            Some(keyword @ ("while" | "until")) => {
+                self.tokens.next();
+                let condition = self.body(&["do"])?;
+                self.expect("do")?;
+                let body = self.body(&["done"])?;
+                self.expect("done")?;
+                Compound::While { condition, body, until: keyword == "until" }
+            }
+            Some("for") => self.for_clause()?,
+            Some("case") => self.case_clause()?,
+            Some(_) => return Err(Parser::unexpected(self.tokens.next())),
+            None if self.tokens.next_if_eq(&Token::LParen).is_some() => {
+                let body = self.body(&[")"])?;
+                match self.tokens.next() {
+                    Some(Token::RParen) => {}
+                    other => return Err(Parser::unexpected(other)),
+                }
+                Compound::Subshell(body)
+            }
+            None => {
+                let first = self.tokens.next_if(|token| matches!(token, Token::Word(_)));
+                if let Some(Token::Word(name)) = &first
+                    && let Some(name) = name.bare_text().filter(|name| lexer::is_name(name))
+                    && self.tokens.next_if_eq(&Token::LParen).is_some() {
+                    return self.function(name.to_string());
+                }
+                return self.simple_command(first).map(Command::Simple);
+            }
+        };
+
+        let mut redirects = Vec::new();
+        while let Some(Token::Redirect { fd, op }) = self.tokens.next_if(|token| matches!(token, Token::Redirect { .. })) {
+            match self.tokens.next() {
+                Some(Token::Word(target)) => redirects.push(Redirect::new(fd, op, target)),
+                other => return Err(Parser::unexpected(other)),
+            }
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    ///
+    /// Parses a function definition after its name and `(`
+    ///
+    fn function(&mut self, name: String) -> ParseResult<Command> {
+        match self.tokens.next() {
+            Some(Token::RParen) => {}
+            other => return Err(Parser::unexpected(other)),
+        }
+
+        self.skip_newlines();
+        match self.peek_reserved() {
+            Some("{" | "if" | "while" | "until" | "for" | "case") => Ok(Command::Function(name, Rc::new(self.command()?))),
+            _ => Err(Parser::unexpected(self.tokens.next())),
+        }
+    }
+
+    fn if_clause(&mut self) -> ParseResult<Compound> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        // `if` and each `elif` open another condition
+        self.tokens.next();
+        loop {
+            let condition = self.body(&["then"])?;
+            self.expect("then")?;
+            let body = self.body(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+
+            match self.peek_reserved() {
+                Some("elif") => {
+                    self.tokens.next();
+                }
+                Some("else") => {
+                    self.tokens.next();
+                    otherwise = Some(self.body(&["fi"])?);
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        self.expect("fi")?;
+        Ok(Compound::If { branches, otherwise })
+    }
+
+    fn for_clause(&mut self) -> ParseResult<Compound> {
+        self.tokens.next();
+        let name = match self.tokens.next() {
+            Some(Token::Word(word)) if word.bare_text().is_some_and(lexer::is_name) => word.to_string(),
+            other => return Err(Parser::unexpected(other)),
+        };
+
+        self.skip_newlines();
+        let mut items = None;
+        if self.tokens.next_if(|token| matches!(token, Token::Word(word) if word.bare_text() == Some("in"))).is_some() {
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.tokens.next_if(|token| matches!(token, Token::Word(_))) {
+                words.push(word);
+            }
+            items = Some(words);
+            match self.tokens.next() {
+                Some(Token::Semi | Token::Newline) => {}
+                other => return Err(Parser::unexpected(other)),
+            }
+        } else {
+            self.tokens.next_if_eq(&Token::Semi);
+        }
+
+        self.skip_newlines();
+        self.expect("do")?;
+        let body = self.body(&["done"])?;
+        self.expect("done")?;
+        Ok(Compound::For { name, items, body })
+    }
+
+    ///
+    /// Parses `case word in pattern) list;; ... esac`
+    ///
+    /// Each branch has one or more patterns split by `|`, optionally after
+    /// a `(`. The `;;` may be left off the last branch, and a branch may
+    /// run nothing
+    ///
+    fn case_clause(&mut self) -> ParseResult<Compound> {
+        self.tokens.next();
+        let word = match self.tokens.next() {
+            Some(Token::Word(word)) => word,
+            other => return Err(Parser::unexpected(other)),
+        };
+        self.skip_newlines();
+        match self.tokens.next() {
+            Some(Token::Word(word)) if word.bare_text() == Some("in") => {}
+            other => return Err(Parser::unexpected(other)),
+        }
+
+        let mut branches = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_reserved() == Some("esac") {
+                break;
+            }
+
+            self.tokens.next_if_eq(&Token::LParen);
+            let mut patterns = Vec::new();
+            loop {
+                match self.tokens.next() {
+                    Some(Token::Word(pattern)) => patterns.push(pattern),
+                    other => return Err(Parser::unexpected(other)),
+                }
+                match self.tokens.next() {
+                    Some(Token::Pipe) => continue,
+                    Some(Token::RParen) => break,
+                    other => return Err(Parser::unexpected(other)),
+                }
+            }
+
+            let body = self.list(&["esac"])?;
+            branches.push((patterns, body));
+            if self.tokens.next_if_eq(&Token::DoubleSemi).is_none() {
+                break;
+            }
+        }
+
+        self.skip_newlines();
+        self.expect("esac")?;
+        Ok(Compound::Case { word, branches })
+    }
+
+    ///
+    /// Parses a simple command, its words and redirections in any order
+    ///
+    /// Input:
+    ///   First token of the command if already taken, which is a word
+    ///
+    fn simple_command(&mut self, first: Option<Token>) -> ParseResult<SimpleCommand> {
+        let mut command = SimpleCommand::default();
+        if let Some(Token::Word(word)) = first {
+            command.words.push(word);
+        }
+
+        let starts_command = |token: &Token| matches!(token, Token::Word(_) | Token::Redirect { .. });
+        while let Some(token) = self.tokens.next_if(starts_command) {
+            match token {
+                Token::Word(word) => command.words.push(word),
+                Token::Redirect { fd, op } => match self.tokens.next() {
+                    Some(Token::Word(target)) => command.redirects.push(Redirect::new(fd, op, target)),
+                    other => return Err(Parser::unexpected(other)),
+                },
+                _ => unreachable!("only words and redirections are taken"),
+            }
+        }
+
+        if command.is_empty() {
+            return Err(Parser::unexpected(self.tokens.next()));
+        }
+        Ok(command)
+    }
+}
+
+///
+/// Builds the syntax tree of a command line
+///
+/// Input:
+///   Tokens of the line, aliases already expanded
+///
+/// Output:
+///   The list of pipelines to run, or a syntax error naming the token the
+///   syntax went wrong at and the line, counting from 1, it is on.
+///   Incomplete if the line stops inside a control structure such as
+///   `while true; do`
+///
+pub fn parse(tokens: Vec<Token>) -> Result<List, ShellError> {
+    let total = tokens.len();
+    let newlines: Vec<usize> = tokens.iter()
+        .enumerate()
+        .filter(|(_, token)| **token == Token::Newline)
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut parser = Parser { tokens: tokens.into_iter().peekable() };
+    parser.list(&[]).map_err(|error| {
+        // The token it went wrong at has just been taken
+        let at = (total - parser.tokens.len()).saturating_sub(1);
+        let line = newlines.iter().filter(|&&i| i < at).count() + 1;
+        ShellError::Syntax { error, line: Some(line) }
+    })
+}
@@ -0,0 +1,415 @@
+use std::fs;
+use std::path::Path;
+
+///
+/// Piece of a pattern, matching one or more characters
+///
+enum Token {
+    /// The character itself
+    Char(char),
+    /// `?`: any one character
+    One,
+    /// `*`: any run of characters, none included
+    Any,
+    /// `[...]`: one character in, or with `!`/`^` not in, the set
+    Class { negated: bool, items: Vec<ClassItem> },
+    /// `?(...)`, `*(...)`, `+(...)`, `@(...)` or `!(...)`: zero or one,
+    /// any number, at least one, exactly one, or anything but one of the
+    /// `|`-separated alternatives
+    Extended { kind: char, alternatives: Vec<Vec<Token>> },
+}
+
+enum ClassItem {
+    /// A character, or a range such as `a-z` from its first to its last
+    Range(char, char),
+    /// A named class such as `[:digit:]`
+    Named(String),
+}
+
+impl ClassItem {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(first, last) => (*first..=*last).contains(&c),
+            ClassItem::Named(name) => match name.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "cntrl" => c.is_control(),
+                "digit" => c.is_ascii_digit(),
+                "lower" => c.is_lowercase(),
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Characters that open an extended pattern when followed by `(`
+const EXTENDED: &str = "?*+@!";
+
+///
+/// Shell pattern, as in filename expansion, `case` and `${x#pattern}`
+///
+/// Besides `*`, `?` and `[...]`, the extended patterns `?(a|b)`,
+/// `*(a|b)`, `+(a|b)`, `@(a|b)` and `!(a|b)` are understood, and a
+/// backslash makes the next character literal. Brackets and parentheses
+/// that are never closed are literal too, so every text is a valid pattern
+///
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    pub fn new(text: &str) -> Pattern {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        Pattern { tokens: parse(&chars, &mut i, false) }
+    }
+
+    ///
+    /// Whether the pattern matches all of text
+    ///
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        match_here(&self.tokens, &chars)
+    }
+
+    ///
+    /// The text the pattern matches if it has no wildcards, None if it does
+    ///
+    pub fn literal(&self) -> Option<String> {
+        self.tokens.iter()
+            .map(|token| match token {
+                Token::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    ///
+    /// Whether the pattern starts with a literal `.`, as it must to match
+    /// the name of a hidden file
+    ///
+    fn leading_dot(&self) -> bool {
+        matches!(self.tokens.first(), Some(Token::Char('.')))
+    }
+}
+
+///
+/// Makes text match only itself when used in a pattern
+///
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\*?[]()|+@!".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+///
+/// Whether unquoted text has anything in it that would make it a pattern
+///
+pub fn has_wildcards(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+        || EXTENDED.chars().any(|c| text.contains(&format!("{c}(")))
+}
+
+///
+/// Index of the `)` closing the `(` at open, None if it is never closed
+///
+fn closing(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+///
+/// Reads tokens up to the end of the text or, inside an extended pattern,
+/// up to the `|` or `)` that ends the alternative
+///
+fn parse(chars: &[char], i: &mut usize, nested: bool) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    while *i < chars.len() {
+        let c = chars[*i];
+        match c {
+            '|' | ')' if nested => break,
+            '\\' if *i + 1 < chars.len() => {
+                tokens.push(Token::Char(chars[*i + 1]));
+                *i += 1;
+            }
+            c if EXTENDED.contains(c) && chars.get(*i + 1) == Some(&'(') && closing(chars, *i + 1).is_some() => {
+                *i += 2;
+                let mut alternatives = Vec::new();
+                loop {
+                    alternatives.push(parse(chars, i, true));
+                    match chars.get(*i) {
+                        Some('|') => *i += 1,
+                        _ => break,
+                    }
+                }
+                tokens.push(Token::Extended { kind: c, alternatives });
+            }
+            '*' => tokens.push(Token::Any),
+            '?' => tokens.push(Token::One),
+            '[' => match parse_class(chars, *i) {
+                Some((token, end)) => {
+                    tokens.push(token);
+                    *i = end;
+                }
+                None => tokens.push(Token::Char('[')),
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+        *i += 1;
+    }
+    tokens
+}
+
+///
+/// Reads a bracket expression starting at the `[` at start
+///
+/// Output:
+///   The class and the index of its closing `]`, None if it is never closed
+///
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let first = i;
+    let mut items = Vec::new();
+
+    loop {
+        let mut c = *chars.get(i)?;
+        match c {
+            // A `]` straight after the `[` is one of the set
+            ']' if i > first => return Some((Token::Class { negated, items }, i)),
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let rest: String = chars[i + 2..].iter().collect();
+                let end = rest.find(":]")?;
+                items.push(ClassItem::Named(rest[..end].to_string()));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+            '\\' => {
+                i += 1;
+                c = *chars.get(i)?;
+            }
+            _ => {}
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&last)) if last != ']' => {
+                items.push(ClassItem::Range(c, last));
+                i += 3;
+            }
+            _ => {
+                items.push(ClassItem::Range(c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+///
+/// Whether the tokens match all of text
+///
+fn match_here(tokens: &[Token], text: &[char]) -> bool {
+    let Some((first, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+    match first {
+        Token::Char(c) => text.first() == Some(c) && match_here(rest, &text[1..]),
+        Token::One => !text.is_empty() && match_here(rest, &text[1..]),
+        Token::Any => (0..=text.len()).any(|k| match_here(rest, &text[k..])),
+        Token::Class { negated, items } => text.first()
+            .is_some_and(|&c| items.iter().any(|item| item.contains(c)) != *negated)
+            && match_here(rest, &text[1..]),
+        Token::Extended { kind, alternatives } => {
+            let one = |k: usize| alternatives.iter().any(|alternative| match_here(alternative, &text[..k]));
+            match kind {
+                '@' => (0..=text.len()).any(|k| one(k) && match_here(rest, &text[k..])),
+                '?' => match_here(rest, text) || (0..=text.len()).any(|k| one(k) && match_here(rest, &text[k..])),
+                '*' => repeat(alternatives, rest, text),
+                '+' => (1..=text.len()).any(|k| one(k) && repeat(alternatives, rest, &text[k..])),
+                _ => (0..=text.len()).any(|k| !one(k) && match_here(rest, &text[k..])),
+            }
+        }
+    }
+}
+
+///
+/// Whether text is any number of the alternatives followed by the rest
+///
+fn repeat(alternatives: &[Vec<Token>], rest: &[Token], text: &[char]) -> bool {
+    match_here(rest, text)
+        || (1..=text.len()).any(|k| {
+            alternatives.iter().any(|alternative| match_here(alternative, &text[..k]))
+                && repeat(alternatives, rest, &text[k..])
+        })
+}
+
+///
+/// Splits a pattern into the parts between slashes, escapes included
+///
+fn components(pattern: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in pattern.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => {
+                parts.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&pattern[start..]);
+    parts
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() || dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+///
+/// Names in a directory, the current one if dir is empty, with whether
+/// each is a directory, following symlinks
+///
+fn entries(dir: &str) -> Vec<(String, bool)> {
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    entries.flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = fs::metadata(entry.path()).is_ok_and(|meta| meta.is_dir());
+            Some((name, is_dir))
+        })
+        .collect()
+}
+
+///
+/// Adds everything below dir for `**`, skipping hidden names and not
+/// following symlinks to directories
+///
+/// Inputs:
+///   Directory to walk, empty for the current one
+///   Whether to add only directories, as `**` before a `/` does
+///   Paths found so far
+///
+fn descend(dir: &str, dirs_only: bool, out: &mut Vec<String>) {
+    let Ok(list) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return;
+    };
+    for entry in list.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = join(dir, &name);
+        let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+        if is_dir || !dirs_only {
+            out.push(path.clone());
+        }
+        if is_dir {
+            descend(&path, dirs_only, out);
+        }
+    }
+}
+
+///
+/// Finds the paths a filename pattern matches
+///
+/// Each part between slashes is matched against the names in the
+/// directories found so far. A name starting with `.` is matched only by a
+/// part that starts with a literal `.`. With globstar, a part that is just
+/// `**` matches any number of directories below, and files too when it is
+/// the last part; otherwise it is the same as `*`. A trailing `/` matches
+/// only directories
+///
+/// Inputs:
+///   Pattern, with quoted text escaped
+///   Whether `**` reaches into subdirectories
+///
+/// Output:
+///   The matching paths, sorted, written as the pattern wrote them
+///
+pub fn expand(pattern: &str, globstar: bool) -> Vec<String> {
+    let parts = components(pattern);
+    let (mut found, parts) = match parts.split_first() {
+        Some((&"", rest)) if !rest.is_empty() => (vec!["/".to_string()], rest),
+        _ => (vec![String::new()], parts.as_slice()),
+    };
+
+    for (index, part) in parts.iter().enumerate() {
+        let last = index + 1 == parts.len();
+        let mut next = Vec::new();
+
+        if part.is_empty() {
+            // `a//b` is `a/b`; a trailing `/` keeps only directories
+            if !last {
+                continue;
+            }
+            for path in found {
+                if Path::new(&path).is_dir() {
+                    next.push(join(&path, ""));
+                }
+            }
+        } else if *part == "**" && globstar {
+            for path in found {
+                if !last {
+                    next.push(path.clone());
+                }
+                descend(&path, !last, &mut next);
+            }
+        } else {
+            let pattern = Pattern::new(part);
+            match pattern.literal() {
+                // Checked once the whole path is built
+                Some(name) => next.extend(found.iter().map(|path| join(path, &name))),
+                None => {
+                    for path in found {
+                        for (name, is_dir) in entries(&path) {
+                            if (is_dir || last)
+                                && (!name.starts_with('.') || pattern.leading_dot())
+                                && pattern.matches(&name) {
+                                next.push(join(&path, &name));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        found = next;
+    }
+
+    found.retain(|path| !path.is_empty() && fs::symlink_metadata(path).is_ok());
+    found.sort_unstable();
+    found
+}
@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+///
+/// The user's home directory, `$HOME`
+///
+pub fn home() -> Option<PathBuf> {
+    env::var_os("HOME").filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+///
+/// A file in the home directory
+///
+pub fn home_file(name: &str) -> Option<PathBuf> {
+    home().map(|home| home.join(name))
+}
+
+///
+/// Replaces a leading `~` or `~/` with the home directory
+///
+pub fn tilde(text: &str) -> Cow<'_, str> {
+    shellexpand::tilde_with_context(text, || home().and_then(|home| home.into_os_string().into_string().ok()))
+}
+
+///
+/// Whether a command name is a path to run rather than a name to look up
+///
+pub fn is_path(name: &str) -> bool {
+    name.contains('/')
+}
+
+///
+/// Whether metadata is of a file the shell could run, one with an execute
+/// bit
+///
+pub fn is_runnable(meta: &Metadata) -> bool {
+    meta.permissions().mode() & 0o111 != 0
+}
+
+///
+/// Whether a path is a file the shell could run
+///
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && is_runnable(&meta))
+}
+
+///
+/// Sets an environment variable of the shell's process
+///
+/// The shell's own worker threads never touch the environment: the
+/// prompt's host name lookup only calls gethostname, and its git check
+/// only reads the pipe of a git started from the main thread. Everything
+/// that reads or writes the environment runs on the thread running the
+/// shell, so a program embedding it must not use the environment from
+/// other threads while a command runs
+///
+pub fn set_env(name: &str, value: impl AsRef<OsStr>) {
+    // SAFETY: per the above, no other thread reads or writes the
+    // environment while this one changes it
+    unsafe { env::set_var(name, value) };
+}
+
+///
+/// Removes an environment variable of the shell's process, under the same
+/// rule as set_env
+///
+pub fn remove_env(name: &str) {
+    // SAFETY: as for set_env
+    unsafe { env::remove_var(name) };
+}
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::*;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard, Once, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::platform;
+use crate::theme::{paint, Theme};
+
+/// Prompt used when neither `$PROMPT` nor `$PS1` is set
+pub const DEFAULT: &str = "\\u@\\H:\\w$ ";
+
+///
+/// Path with the home directory at its start shortened to `~`
+///
+pub fn tilde(path: &Path) -> String {
+    let path = path.to_string_lossy().into_owned();
+
+    if let Some(home) = platform::home()
+        && let Some(rest) = path.strip_prefix(&*home.to_string_lossy())
+        && (rest.is_empty() || rest.starts_with(std::path::is_separator)) {
+        return format!("~{rest}");
+    }
+    path
+}
+
+///
+/// Current directory with the home directory shortened to `~`
+///
+fn cwd() -> String {
+    tilde(&env::current_dir().unwrap_or_else(|_| PathBuf::from("?")))
+}
+
+///
+/// Local time of day as hours, minutes and seconds
+///
+fn local_time() -> (i32, i32, i32) {
+    // SAFETY: localtime_r only writes into the tm we own
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        (tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+/// How long a dirty check is trusted while the repository looks unchanged
+const GIT_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// How long the prompt waits for `git status` before leaving the dirty
+/// marker as it last was
+const GIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the prompt waits for the host name before showing `unknown`
+const HOST_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Drawn where the host name goes until it is known
+const HOST_PLACEHOLDER: &str = "…";
+
+/// Host name, once a worker has looked it up
+static HOST: OnceLock<String> = OnceLock::new();
+
+///
+/// Host name if it is known yet, starting a worker to look it up the first
+/// time it is asked for
+///
+fn host() -> Option<&'static str> {
+    static LOOKUP: Once = Once::new();
+    LOOKUP.call_once(|| {
+        let lookup = || {
+            let _ = HOST.set(whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_string()));
+        };
+        if thread::Builder::new().spawn(lookup).is_err() {
+            lookup();
+        }
+    });
+    HOST.get().map(String::as_str)
+}
+
+///
+/// The host name as `\h` (up to the first dot) or `\H` (in full) shows it
+///
+fn host_part(host: &str, short: bool) -> String {
+    match short {
+        true => host.split('.').next().unwrap_or(host).to_string(),
+        false => host.to_string(),
+    }
+}
+
+///
+/// Finds the git directory of the repository containing dir
+///
+/// Output:
+///   The `.git` directory, following the `gitdir:` file of a worktree or
+///   submodule, or None outside a repository
+///
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    for ancestor in dir.ancestors() {
+        let dot_git = ancestor.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        if let Ok(contents) = fs::read_to_string(&dot_git)
+            && let Some(path) = contents.trim().strip_prefix("gitdir:") {
+            return Some(ancestor.join(path.trim()));
+        }
+    }
+    None
+}
+
+///
+/// Current branch from `HEAD`, or the short commit hash when detached
+///
+fn branch(git_dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+///
+/// Whether `git status` lists changes to tracked files
+///
+/// It is started on the main thread, so the worker that waits for it
+/// neither forks nor reads the environment. Its exit status may already
+/// have been collected by the shell reaping its children, so only the
+/// output counts: git prints nothing there when it fails
+///
+fn dirty(mut git: Child) -> bool {
+    let mut out = Vec::new();
+    let read = git.stdout.take().is_some_and(|mut stdout| stdout.read_to_end(&mut out).is_ok());
+    let _ = git.wait();
+    read && !out.is_empty()
+}
+
+fn git_text(branch: &str, dirty: bool) -> String {
+    format!(" ({branch}{})", if dirty { "*" } else { "" })
+}
+
+///
+/// What a dirty check was worked out from: the repository, and when its
+/// `HEAD` and index last changed
+///
+#[derive(Clone, PartialEq)]
+struct Snapshot {
+    git_dir: PathBuf,
+    head: Option<SystemTime>,
+    index: Option<SystemTime>,
+}
+
+///
+/// Git status last worked out
+///
+struct GitStatus {
+    snapshot: Snapshot,
+    checked: Instant,
+    dirty: bool,
+}
+
+///
+/// What the prompt and its workers share about the repository
+///
+#[derive(Default)]
+struct GitState {
+    last: Option<GitStatus>,
+    /// A worker is running `git status`
+    running: bool,
+}
+
+impl GitState {
+    ///
+    /// The last dirty check, if it was of the repository as it is now and
+    /// is recent enough to trust
+    ///
+    fn fresh(&self, snapshot: &Snapshot) -> Option<bool> {
+        self.last.as_ref()
+            .filter(|last| last.snapshot == *snapshot && last.checked.elapsed() < GIT_CACHE_TTL)
+            .map(|last| last.dirty)
+    }
+}
+
+fn lock(state: &Mutex<GitState>) -> MutexGuard<'_, GitState> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+///
+/// Remembers whether the repository had uncommitted changes, so pressing
+/// Enter repeatedly does not run `git status` every time, and runs it on a
+/// worker thread when it is needed so a big repository does not hold up
+/// the prompt
+///
+#[derive(Default)]
+pub struct GitCache {
+    state: Arc<Mutex<GitState>>,
+}
+
+impl GitCache {
+    ///
+    /// Prompt segment for the repository containing the current directory
+    ///
+    /// Input:
+    ///   Colour to paint it with
+    ///
+    /// Output:
+    ///   ` (branch)`, with `*` after the branch if tracked files have
+    ///   changes, or empty outside a repository. Until a dirty check of
+    ///   the repository as it is now comes back, the segment is pending and
+    ///   shows the marker as it last was. If git itself cannot be run the
+    ///   branch is shown without the marker
+    ///
+    fn segment(&mut self, colour: &str) -> Piece {
+        let Ok(cwd) = env::current_dir() else {
+            return Piece::Done(String::new());
+        };
+        let Some(git_dir) = git_dir(&cwd) else {
+            return Piece::Done(String::new());
+        };
+        let Some(branch) = branch(&git_dir) else {
+            return Piece::Done(String::new());
+        };
+
+        let snapshot = Snapshot {
+            head: modified(&git_dir.join("HEAD")),
+            index: modified(&git_dir.join("index")),
+            git_dir,
+        };
+        let mut state = lock(&self.state);
+        if let Some(dirty) = state.fresh(&snapshot) {
+            return Piece::Done(paint(colour, &git_text(&branch, dirty)));
+        }
+        let stale = state.last.as_ref()
+            .is_some_and(|last| last.snapshot.git_dir == snapshot.git_dir && last.dirty);
+
+        if !state.running {
+            let git = Command::new("git")
+                .args(["status", "--porcelain", "--untracked-files=no"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let shared = Arc::clone(&self.state);
+            let checked = snapshot.clone();
+            let check = move || {
+                let dirty = git.is_ok_and(dirty);
+                let mut state = lock(&shared);
+                state.running = false;
+                state.last = Some(GitStatus { snapshot: checked, checked: Instant::now(), dirty });
+            };
+            state.running = thread::Builder::new().spawn(check).is_ok();
+        }
+
+        Piece::Git {
+            snapshot,
+            branch,
+            colour: colour.to_string(),
+            stale,
+            deadline: Instant::now() + GIT_TIMEOUT,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+///
+/// Part of a rendered prompt
+///
+enum Piece {
+    /// Text that is already final
+    Done(String),
+    /// `\h` or `\H` while the host name is looked up
+    Host { short: bool, deadline: Instant },
+    /// `\g` while `git status` runs, with what its result must match
+    Git {
+        snapshot: Snapshot,
+        branch: String,
+        colour: String,
+        /// Dirty marker from the last check, shown until this one is done
+        stale: bool,
+        deadline: Instant,
+        state: Arc<Mutex<GitState>>,
+    },
+}
+
+impl Piece {
+    ///
+    /// Text to draw for the piece as things stand
+    ///
+    /// Output:
+    ///   The text, and whether it is final: a slow piece is final once its
+    ///   worker has answered or it has waited as long as it may
+    ///
+    fn text(&self) -> (String, bool) {
+        match self {
+            Piece::Done(text) => (text.clone(), true),
+            Piece::Host { short, deadline } => match HOST.get() {
+                Some(host) => (host_part(host, *short), true),
+                None if Instant::now() >= *deadline => ("unknown".to_string(), true),
+                None => (HOST_PLACEHOLDER.to_string(), false),
+            },
+            Piece::Git { snapshot, branch, colour, stale, deadline, state } => {
+                let (dirty, done) = match lock(state).fresh(snapshot) {
+                    Some(dirty) => (dirty, true),
+                    None => (*stale, Instant::now() >= *deadline),
+                };
+                (paint(colour, &git_text(branch, dirty)), done)
+            }
+        }
+    }
+}
+
+///
+/// A rendered prompt, parts of which may still be coming from workers
+///
+/// Slow segments, the host name and the git dirty check, are drawn with
+/// a placeholder until their worker answers or their timeout runs out,
+/// and `poll` fills them in so the line editor can repaint
+///
+#[derive(Default)]
+pub struct Prompt {
+    pieces: Vec<Piece>,
+    text: String,
+}
+
+impl Prompt {
+    fn new(pieces: Vec<Piece>) -> Prompt {
+        let mut prompt = Prompt { pieces, text: String::new() };
+        prompt.poll();
+        prompt
+    }
+
+    ///
+    /// The prompt as it should be drawn now
+    ///
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    ///
+    /// Whether some segment is still waiting on a worker
+    ///
+    pub fn is_pending(&self) -> bool {
+        self.pieces.iter().any(|piece| !matches!(piece, Piece::Done(_)))
+    }
+
+    ///
+    /// Takes in whatever workers have finished, or segments have given up
+    /// on, since the last call
+    ///
+    /// Output:
+    ///   Whether the text changed and needs drawing again
+    ///
+    pub fn poll(&mut self) -> bool {
+        let mut text = String::new();
+        for piece in &mut self.pieces {
+            let (part, done) = piece.text();
+            text.push_str(&part);
+            if done && !matches!(piece, Piece::Done(_)) {
+                *piece = Piece::Done(part);
+            }
+        }
+        let changed = text != self.text;
+        self.text = text;
+        changed
+    }
+
+    ///
+    /// Waits for every slow segment, for when there is no line editor to
+    /// repaint the prompt
+    ///
+    pub fn wait(&mut self) -> &str {
+        while self.is_pending() {
+            thread::sleep(Duration::from_millis(10));
+            self.poll();
+        }
+        &self.text
+    }
+}
+
+///
+/// What the prompt can show about the command that ran before it
+///
+pub struct Last {
+    /// Exit status, for `\?`
+    pub status: i32,
+    /// How long it took, for `\D`, or None if that is too short to show
+    pub duration: Option<Duration>,
+}
+
+///
+/// Duration the way `\D` shows it: `4.2s`, `3m07s` or `1h05m`
+///
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3_600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3_600, secs / 60 % 60),
+    }
+}
+
+///
+/// Names of the `\{name}` segments a template uses, in order
+///
+pub fn segment_names(template: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('\\') {
+        rest = &rest[at + 1..];
+        if let Some(segment) = rest.strip_prefix('{')
+            && let Some((name, after)) = segment.split_once('}') {
+            names.push(name.to_string());
+            rest = after;
+        } else if let Some(escaped) = rest.get(1..) {
+            // Past the escaped character, so `\\{` is not a segment
+            rest = escaped;
+        }
+    }
+    names
+}
+
+///
+/// Builds the prompt from a PS1-style template
+///
+/// Inputs:
+///   Template, where `\u` is the user, `\h`/`\H` the short/full host name,
+///   `\w`/`\W` the current directory in full (with `~`) or its last part,
+///   `\$` is `#` for root and `$` otherwise, `\t`/`\A` the time with and
+///   without seconds, `\g` the git branch as ` (main*)` inside a
+///   repository, `\?` the last exit status, `\D` how long the last
+///   command took if it was long enough to show, `\n` a newline, `\e` or
+///   `\033` an escape for ANSI colors, `\{name}` a segment from a plugin
+///   or function, and `\[`/`\]` are accepted and dropped
+///   Text of each `\{name}` segment the template uses
+///   Cache of the git status, only consulted if the template uses `\g`
+///   Theme whose user, directory and git colours go around `\u`, `\w`/`\W`
+///   and `\g`, and whose status colour marks a nonzero `\?`
+///   Status and duration of the last command
+///
+/// Output:
+///   The prompt, with the host name and the git dirty check left pending
+///   if they are not known yet
+///
+pub fn render(template: &str, segments: &HashMap<String, String>, git: &mut GitCache, theme: &Theme, last: &Last) -> Prompt {
+    let mut pieces = Vec::new();
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    // Ends the text so far and starts a slow piece
+    let mut slow = |out: &mut String, piece: Piece| {
+        pieces.push(Piece::Done(std::mem::take(out)));
+        pieces.push(piece);
+    };
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('u') => out.push_str(&paint(&theme.user, &whoami::username())),
+            Some(h @ ('h' | 'H')) => match host() {
+                Some(host) => out.push_str(&host_part(host, h == 'h')),
+                None => slow(&mut out, Piece::Host { short: h == 'h', deadline: Instant::now() + HOST_TIMEOUT }),
+            },
+            Some('w') => out.push_str(&paint(&theme.directory, &cwd())),
+            Some('W') => {
+                let cwd = cwd();
+                let last = match cwd.rsplit_once('/') {
+                    Some((_, last)) if !last.is_empty() => last,
+                    _ => &cwd,
+                };
+                out.push_str(&paint(&theme.directory, last));
+            }
+            // SAFETY: geteuid has no memory safety requirements
+            Some('$') => out.push(if unsafe { libc::geteuid() } == 0 { '#' } else { '$' }),
+            Some('t') => {
+                let (h, m, s) = local_time();
+                out.push_str(&format!("{h:02}:{m:02}:{s:02}"));
+            }
+            Some('A') => {
+                let (h, m, _) = local_time();
+                out.push_str(&format!("{h:02}:{m:02}"));
+            }
+            Some('g') => match git.segment(&theme.git) {
+                Piece::Done(text) => out.push_str(&text),
+                piece => slow(&mut out, piece),
+            },
+            Some('?') => match last.status {
+                0 => out.push('0'),
+                status => out.push_str(&paint(&theme.status, &status.to_string())),
+            },
+            Some('D') => out.extend(last.duration.map(format_duration)),
+            Some('{') => {
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                out.extend(segments.get(&name).map(String::as_str));
+            }
+            Some('n') => out.push('\n'),
+            Some('e') => out.push('\x1b'),
+            Some('[' | ']') => {}
+            Some('\\') => out.push('\\'),
+            // Octal character code such as \033
+            Some(d @ '0'..='7') => {
+                let mut code = d.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.extend(char::from_u32(code));
+            }
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    pieces.push(Piece::Done(out));
+    Prompt::new(pieces)
+}
@@ -0,0 +1,256 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+
+use crate::lexer::{RedirectOp, Word};
+
+///
+/// One redirection of a command, target not yet expanded
+///
+pub struct Redirect {
+    pub fd: i32,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+impl Redirect {
+    ///
+    /// Builds a redirection, defaulting the fd to stdin for `<`/`<&`/`<<`/
+    /// `<<<` and stdout for `>`/`>>`/`>|`/`>&` when none was written
+    ///
+    pub fn new(fd: Option<i32>, op: RedirectOp, target: Word) -> Self {
+        let fd = fd.unwrap_or(match op {
+            RedirectOp::Read | RedirectOp::DupIn | RedirectOp::HereDoc { .. } | RedirectOp::HereString => 0,
+            RedirectOp::Write | RedirectOp::Append | RedirectOp::Clobber | RedirectOp::DupOut => 1,
+        });
+        Redirect { fd, op, target }
+    }
+
+    pub fn writes(&self) -> bool {
+        matches!(self.op, RedirectOp::Write | RedirectOp::Append | RedirectOp::Clobber | RedirectOp::DupOut)
+    }
+
+    ///
+    /// Whether the target is the text to read rather than a path
+    ///
+    pub fn is_here(&self) -> bool {
+        matches!(self.op, RedirectOp::HereDoc { .. } | RedirectOp::HereString)
+    }
+
+    ///
+    /// Whether the target names another fd rather than a file
+    ///
+    pub fn duplicates(&self) -> bool {
+        matches!(self.op, RedirectOp::DupIn | RedirectOp::DupOut)
+    }
+}
+
+///
+/// What a redirected fd is pointed at once the redirection is opened
+///
+pub enum Source {
+    File(File),
+    /// Another fd, as it is at the time the redirection is applied
+    Fd(i32),
+    /// Nothing: the fd is closed, as by `>&-`
+    Closed,
+}
+
+///
+/// Points an fd at a source
+///
+/// Only calls dup2 and close, which are async-signal-safe
+///
+fn point(fd: i32, source: &Source) -> io::Result<()> {
+    let raw = match source {
+        Source::File(file) => file.as_raw_fd(),
+        Source::Fd(source) => *source,
+        Source::Closed => {
+            // SAFETY: close only operates on a descriptor number; closing
+            // one that is not open is not an error here
+            unsafe { libc::close(fd) };
+            return Ok(());
+        }
+    };
+    // SAFETY: dup2 only operates on descriptor numbers
+    if unsafe { libc::dup2(raw, fd) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lowest fd the shell keeps its own files at, leaving those below for
+/// the user to redirect
+const FIRST_PRIVATE_FD: i32 = 10;
+
+///
+/// Moves a file the shell opened above the fds the user can redirect, so
+/// `3>file` cannot land on it. The copy stays close-on-exec
+///
+fn private(file: File) -> io::Result<File> {
+    if file.as_raw_fd() >= FIRST_PRIVATE_FD {
+        return Ok(file);
+    }
+    // SAFETY: F_DUPFD_CLOEXEC returns a new descriptor, owned by the File
+    // from here on
+    let moved = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, FIRST_PRIVATE_FD) };
+    if moved < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(moved) })
+}
+
+///
+/// Points fds at their sources in order, in a child between fork and exec
+///
+/// Only calls dup2 and close, which are async-signal-safe
+///
+/// Input:
+///   Target fd and source of each redirection
+///
+pub fn apply_in_child(redirects: &[(i32, Source)]) -> io::Result<()> {
+    for (fd, source) in redirects {
+        point(*fd, source)?;
+    }
+    Ok(())
+}
+
+///
+/// Points the shell's own fds at their sources for good, as `exec` does
+/// when it has no command
+///
+pub fn keep(redirects: &[(i32, Source)]) -> io::Result<()> {
+    flush_std();
+    apply_in_child(redirects)
+}
+
+///
+/// Opens the file a redirection points at
+///
+/// Inputs:
+///   Operator deciding how the file is opened
+///   Expanded path of the target
+///   Whether `>` may only create the file, from `set -o noclobber`. A
+///   file that exists but is not a regular one, such as `/dev/null`, is
+///   still opened
+///
+pub fn open(op: RedirectOp, path: &str, noclobber: bool) -> io::Result<File> {
+    let file = match op {
+        RedirectOp::Read => File::open(path),
+        RedirectOp::Write if noclobber => match OpenOptions::new().write(true).create_new(true).open(path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match std::fs::metadata(path) {
+                Ok(metadata) if !metadata.is_file() => OpenOptions::new().write(true).open(path),
+                _ => Err(io::Error::other("cannot overwrite existing file")),
+            },
+            file => file,
+        },
+        RedirectOp::Write | RedirectOp::Clobber => File::create(path),
+        RedirectOp::Append => OpenOptions::new().append(true).create(true).open(path),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file redirection")),
+    };
+    private(file?)
+}
+
+/// Most a pipe is grown to hold, past which a here-document is refused
+/// rather than risk the shell blocking on a pipe nobody reads yet
+const HERE_DOC_MAX: usize = 1 << 20;
+
+///
+/// Puts the text of a here-document or here-string in a pipe to read from
+///
+/// Input:
+///   Text, already expanded
+///
+/// Output:
+///   Read end of a pipe holding the whole text, its write end closed
+///
+pub fn here_doc(text: &str) -> io::Result<File> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe writes two descriptors into the array we own, and each
+    // is owned by a File from then on
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (reader, mut writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // The text is written before anything reads it, so it has to fit
+    if text.len() > 1 << 16 {
+        let size = text.len().min(HERE_DOC_MAX) as libc::c_int;
+        // SAFETY: F_SETPIPE_SZ only resizes the pipe's buffer
+        let grown = unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_SETPIPE_SZ, size) };
+        if grown < 0 || (grown as usize) < text.len() {
+            return Err(io::Error::other("here-document too large"));
+        }
+    }
+
+    writer.write_all(text.as_bytes())?;
+    private(reader)
+}
+
+///
+/// Shell's own descriptors, moved aside while a builtin runs redirected
+///
+/// Builtins write straight to the shell's stdout/stderr, so redirecting one
+/// means pointing the fds of the shell itself at the files. The originals
+/// come back when this is dropped, and an fd that was closed is closed
+/// again
+///
+pub struct SavedFds {
+    /// Each fd redirected and a copy of what it was, None if it was closed
+    saved: Vec<(i32, Option<i32>)>,
+}
+
+impl SavedFds {
+    ///
+    /// Points each fd at its source, remembering what it was before
+    ///
+    /// Input:
+    ///   Target fd and source for each redirection, applied in order
+    ///
+    pub fn apply(redirects: Vec<(i32, Source)>) -> io::Result<SavedFds> {
+        let mut saved = SavedFds { saved: Vec::new() };
+        flush_std();
+
+        for (fd, source) in redirects {
+            // SAFETY: F_DUPFD_CLOEXEC only operates on descriptor numbers
+            let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, FIRST_PRIVATE_FD) };
+            if copy < 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::EBADF) {
+                    return Err(err);
+                }
+            }
+            saved.saved.push((fd, (copy >= 0).then_some(copy)));
+            point(fd, &source)?;
+        }
+
+        Ok(saved)
+    }
+}
+
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        flush_std();
+
+        // Undo in reverse so an fd redirected twice ends up as it started
+        for &(fd, copy) in self.saved.iter().rev() {
+            // SAFETY: copy was returned by fcntl and is closed only here
+            unsafe {
+                match copy {
+                    Some(copy) => {
+                        libc::dup2(copy, fd);
+                        libc::close(copy);
+                    }
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn flush_std() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
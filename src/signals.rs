@@ -0,0 +1,209 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::jobs;
+
+///
+/// Signal name to number table shared by builtins that take signals
+///
+/// Names are stored without the SIG prefix, which lookups accept either
+/// way. `kill -l` lists signals in this order
+///
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP",  libc::SIGHUP),
+    ("INT",  libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("WINCH", libc::SIGWINCH),
+];
+
+///
+/// What a signal did to a process it killed, as `jobs` shows it, in the
+/// words of strsignal(3)
+///
+pub fn description(sig: i32) -> String {
+    let text = match sig {
+        libc::SIGHUP => "Hangup",
+        libc::SIGINT => "Interrupt",
+        libc::SIGQUIT => "Quit",
+        libc::SIGILL => "Illegal instruction",
+        libc::SIGTRAP => "Trace/breakpoint trap",
+        libc::SIGABRT => "Aborted",
+        libc::SIGBUS => "Bus error",
+        libc::SIGFPE => "Floating point exception",
+        libc::SIGKILL => "Killed",
+        libc::SIGUSR1 => "User defined signal 1",
+        libc::SIGSEGV => "Segmentation fault",
+        libc::SIGUSR2 => "User defined signal 2",
+        libc::SIGPIPE => "Broken pipe",
+        libc::SIGALRM => "Alarm clock",
+        libc::SIGTERM => "Terminated",
+        _ => return format!("Signal {sig}"),
+    };
+    text.to_string()
+}
+
+///
+/// Resolves a signal given by name or number
+///
+/// Input:
+///   `9`, `KILL`, or `SIGKILL` (names are case insensitive)
+///
+/// Output:
+///   Signal number if the name is known or the number is in the table
+///
+pub fn signal_number(spec: &str) -> Option<i32> {
+    if let Ok(num) = spec.parse::<i32>() {
+        return signal_name(num).map(|_| num);
+    }
+
+    let upper = spec.to_ascii_uppercase();
+    let name  = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS.iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, num)| num)
+}
+
+///
+/// Finds the name (without SIG prefix) of a signal number
+///
+pub fn signal_name(num: i32) -> Option<&'static str> {
+    SIGNALS.iter()
+        .find(|&&(_, n)| n == num)
+        .map(|&(name, _)| name)
+}
+
+/// Set by the SIGINT handler, cleared by whoever handles the interrupt
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Signals caught by `trap` that arrived and have not been handled, one
+/// bit per signal number
+static TRAPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Signals ignored by `trap ''`, which commands the shell runs inherit
+static IGNORED: AtomicU64 = AtomicU64::new(0);
+
+/// Signals an interactive shell handles itself instead of dying or stopping
+const JOB_CONTROL_SIGNALS: &[i32] = &[
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_trapped(sig: libc::c_int) {
+    TRAPPED.fetch_or(1 << sig, Ordering::SeqCst);
+    if sig == libc::SIGINT {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+}
+
+///
+/// Installs a handler that only raises a flag, without SA_RESTART so a
+/// blocking read or wait returns EINTR
+///
+fn flag_on(sig: i32, handler: extern "C" fn(libc::c_int)) {
+    // SAFETY: the handler only touches atomics, and sigaction is given a
+    // fully initialized struct
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(sig, &action, std::ptr::null_mut());
+    }
+}
+
+///
+/// Sets up signal handling for an interactive shell
+///
+/// SIGINT only raises a flag, and is installed without SA_RESTART so a
+/// blocking read or wait returns EINTR and the shell can react. Ctrl-Z,
+/// Ctrl-\ and background terminal access are ignored; they are meant for
+/// the foreground job, not the shell
+///
+pub fn install_handlers() {
+    flag_on(libc::SIGINT, on_interrupt);
+    for &sig in &JOB_CONTROL_SIGNALS[1..] {
+        // SAFETY: signal has no memory safety requirements
+        unsafe { libc::signal(sig, libc::SIG_IGN) };
+    }
+}
+
+///
+/// Catches a signal for `trap`, to be collected by `take_trapped`
+///
+pub fn catch(sig: i32) {
+    IGNORED.fetch_and(!(1 << sig), Ordering::SeqCst);
+    flag_on(sig, on_trapped);
+}
+
+///
+/// Ignores a signal for `trap ''`, in the shell and what it runs
+///
+pub fn ignore(sig: i32) {
+    IGNORED.fetch_or(1 << sig, Ordering::SeqCst);
+    // SAFETY: signal has no memory safety requirements
+    unsafe { libc::signal(sig, libc::SIG_IGN) };
+}
+
+///
+/// Puts back the handling a signal has without a trap
+///
+pub fn restore(sig: i32) {
+    IGNORED.fetch_and(!(1 << sig), Ordering::SeqCst);
+    if jobs::interactive() && sig == libc::SIGINT {
+        flag_on(sig, on_interrupt);
+    } else {
+        let ignored = jobs::interactive() && JOB_CONTROL_SIGNALS.contains(&sig);
+        // SAFETY: signal has no memory safety requirements
+        unsafe { libc::signal(sig, if ignored { libc::SIG_IGN } else { libc::SIG_DFL }) };
+    }
+}
+
+///
+/// Takes the trapped signals that arrived since the last call
+///
+/// Output:
+///   Their numbers, lowest first
+///
+pub fn take_trapped() -> Vec<i32> {
+    let pending = TRAPPED.swap(0, Ordering::SeqCst);
+    (1..64).filter(|sig| pending & (1 << sig) != 0).collect()
+}
+
+///
+/// Restores default handling of the job control signals
+///
+/// Meant for a freshly forked child before exec: ignored signals would
+/// otherwise stay ignored in the program it runs. Only calls signal(),
+/// which is async-signal-safe. Signals ignored with `trap ''` stay ignored
+///
+pub fn reset_for_child() {
+    let ignored = IGNORED.load(Ordering::SeqCst);
+    for &sig in JOB_CONTROL_SIGNALS.iter().filter(|&&sig| ignored & (1 << sig) == 0) {
+        // SAFETY: signal has no memory safety requirements
+        unsafe { libc::signal(sig, libc::SIG_DFL) };
+    }
+}
+
+///
+/// Checks for and clears a pending Ctrl-C
+///
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
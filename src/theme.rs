@@ -0,0 +1,189 @@
+use std::env;
+
+use crate::{error, platform};
+
+///
+/// Colours the shell draws with, each the parameters of an ANSI SGR
+/// sequence such as `1;32`, or empty for the terminal's own colour
+///
+pub struct Theme {
+    /// Command names that would run
+    pub command: String,
+    /// Command names that would not
+    pub unknown: String,
+    /// Quoted text
+    pub quote: String,
+    /// Operators such as `|` and `>`
+    pub operator: String,
+    /// Suggested rest of the line, from history
+    pub suggestion: String,
+    /// `\u` in the prompt
+    pub user: String,
+    /// `\w` and `\W` in the prompt, and directories in `LS_COLORS`
+    pub directory: String,
+    /// `\g` in the prompt
+    pub git: String,
+    /// `\?` in the prompt when the last command failed
+    pub status: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            command: "32".to_string(),
+            unknown: "31".to_string(),
+            quote: "33".to_string(),
+            operator: "36".to_string(),
+            suggestion: "2".to_string(),
+            user: String::new(),
+            directory: String::new(),
+            git: String::new(),
+            status: "31".to_string(),
+        }
+    }
+}
+
+/// Colour names and the SGR parameters they stand for
+const NAMES: &[(&str, &str)] = &[
+    ("bold", "1"), ("dim", "2"), ("italic", "3"), ("underline", "4"),
+    ("black", "30"), ("red", "31"), ("green", "32"), ("yellow", "33"),
+    ("blue", "34"), ("magenta", "35"), ("cyan", "36"), ("white", "37"),
+    ("bright-black", "90"), ("bright-red", "91"), ("bright-green", "92"), ("bright-yellow", "93"),
+    ("bright-blue", "94"), ("bright-magenta", "95"), ("bright-cyan", "96"), ("bright-white", "97"),
+];
+
+/// `LS_COLORS` exported when the environment has none, after the defaults
+/// of GNU dircolors; `di` and `ex` come from the theme
+const LS_COLORS: &str = "ln=01;36:pi=40;33:so=01;35:do=01;35:bd=40;33;01:cd=40;33;01:or=40;31;01:\
+su=37;41:sg=30;43:tw=30;42:ow=34;42:st=37;44:*.tar=01;31:*.tgz=01;31:*.gz=01;31:*.xz=01;31:\
+*.zst=01;31:*.zip=01;31:*.jpg=01;35:*.png=01;35:*.gif=01;35:*.mp3=00;36:*.flac=00;36";
+
+impl Theme {
+    ///
+    /// A theme with no colour at all
+    ///
+    pub fn plain() -> Self {
+        Theme {
+            command: String::new(),
+            unknown: String::new(),
+            quote: String::new(),
+            operator: String::new(),
+            suggestion: String::new(),
+            user: String::new(),
+            directory: String::new(),
+            git: String::new(),
+            status: String::new(),
+        }
+    }
+
+    ///
+    /// Every role by the name `theme` knows it by, in the order it lists
+    /// them
+    ///
+    fn roles(&mut self) -> [(&'static str, &mut String); 9] {
+        [
+            ("command", &mut self.command),
+            ("unknown", &mut self.unknown),
+            ("quote", &mut self.quote),
+            ("operator", &mut self.operator),
+            ("suggestion", &mut self.suggestion),
+            ("user", &mut self.user),
+            ("directory", &mut self.directory),
+            ("git", &mut self.git),
+            ("status", &mut self.status),
+        ]
+    }
+}
+
+///
+/// Adds a colour around text, leaving it alone if the colour is empty
+///
+pub fn paint(colour: &str, text: &str) -> String {
+    if colour.is_empty() || text.is_empty() {
+        return text.to_string();
+    }
+    format!("\x1b[{colour}m{text}\x1b[0m")
+}
+
+///
+/// Reads a colour, such as `bold blue`, `1;34` or `none`
+///
+/// Output:
+///   SGR parameters, empty for `none`, or None if a word is not a colour
+///
+fn parse_colour(words: &[String]) -> Option<String> {
+    let mut codes = Vec::new();
+    for word in words.iter().flat_map(|word| word.split_whitespace()) {
+        match NAMES.iter().find(|(name, _)| *name == word) {
+            Some((_, code)) => codes.push(code.to_string()),
+            None if word == "none" => {}
+            None if word.split(';').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) => {
+                codes.push(word.to_string());
+            }
+            None => return None,
+        }
+    }
+    Some(codes.join(";"))
+}
+
+///
+/// Runs the `theme` builtin
+///
+/// `theme` lists each role with its colour, `theme default` and
+/// `theme none` switch to the built-in colours or to none, and
+/// `theme ROLE COLOUR...` sets one role, as colour names like
+/// `bold green`, SGR parameters like `38;5;208`, or `none`
+///
+/// Output:
+///   Exit status: 1 for an unknown role or colour, 2 for bad usage
+///
+pub fn builtin_theme(theme: &mut Theme, args: &[String]) -> i32 {
+    match args {
+        [] => {
+            for (role, colour) in theme.roles() {
+                let shown = if colour.is_empty() { "none" } else { colour.as_str() };
+                println!("{role}\t{}", paint(colour, shown));
+            }
+            0
+        }
+        [preset] if preset == "default" => {
+            *theme = Theme::default();
+            0
+        }
+        [preset] if preset == "none" => {
+            *theme = Theme::plain();
+            0
+        }
+        [_] => error::usage("theme", "usage: theme [default | none | role colour...]"),
+        [role, words @ ..] => {
+            let mut roles = theme.roles();
+            let names: Vec<&str> = roles.iter().map(|(name, _)| *name).collect();
+            let Some((_, slot)) = roles.iter_mut().find(|(name, _)| name == role) else {
+                return error::builtin("theme", format!("{role}: unknown role, expected one of {}", names.join(", ")));
+            };
+            match parse_colour(words) {
+                Some(colour) => {
+                    **slot = colour;
+                    0
+                }
+                None => error::builtin("theme", format!("{}: unknown colour", words.join(" "))),
+            }
+        }
+    }
+}
+
+///
+/// Sets `LS_COLORS` and `CLICOLOR` in the environment if they are not
+/// set, so programs such as ls colour their output to match the shell
+///
+pub fn export_colours(theme: &Theme) {
+    if env::var_os("LS_COLORS").is_none() {
+        let directory = if theme.directory.is_empty() { "01;34" } else { &theme.directory };
+        let executable = if theme.command.is_empty() { "01;32" } else { &theme.command };
+        let value = format!("di={directory}:ex={executable}:{LS_COLORS}");
+        platform::set_env("LS_COLORS", value);
+    }
+    if env::var_os("CLICOLOR").is_none() {
+        platform::set_env("CLICOLOR", "1");
+    }
+}
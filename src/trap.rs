@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+
+use crate::error;
+use crate::{shell_run, signals, State};
+
+/// Number `trap` gives the EXIT condition, as `trap ... 0` does
+const EXIT: i32 = 0;
+/// Condition run after a command fails, numbered below the signals
+const ERR: i32 = -1;
+/// Condition run before each simple command
+const DEBUG: i32 = -2;
+
+///
+/// Commands registered with `trap`, by signal
+///
+#[derive(Default)]
+pub struct Traps {
+    /// Command to run for each trapped signal, EXIT being 0. An empty
+    /// command ignores the signal
+    commands: BTreeMap<i32, String>,
+    /// Whether an ERR or DEBUG trap is running, so the commands in it do
+    /// not set off another
+    running: bool,
+}
+
+///
+/// Resolves a signal given to `trap`
+///
+/// Input:
+///   `EXIT` or `0`, `ERR`, `DEBUG`, or a signal by name or number as
+///   `kill` takes it
+///
+/// Output:
+///   Signal number, 0 for EXIT and below it for ERR and DEBUG, or None
+///   if it is unknown or cannot be caught
+///
+fn signal(spec: &str) -> Option<i32> {
+    let upper = spec.to_ascii_uppercase();
+    if spec == "0" {
+        return Some(EXIT);
+    }
+    match upper.strip_prefix("SIG").unwrap_or(&upper) {
+        "EXIT" => return Some(EXIT),
+        "ERR" => return Some(ERR),
+        "DEBUG" => return Some(DEBUG),
+        _ => {}
+    }
+    signals::signal_number(spec).filter(|&sig| sig != libc::SIGKILL && sig != libc::SIGSTOP)
+}
+
+fn name(sig: i32) -> &'static str {
+    match sig {
+        EXIT => "EXIT",
+        ERR => "ERR",
+        DEBUG => "DEBUG",
+        _ => signals::signal_name(sig).unwrap_or("?"),
+    }
+}
+
+///
+/// Prints a trap as the command that would set it again
+///
+fn show(sig: i32, command: &str) {
+    println!("trap -- '{}' {}", command.replace('\'', "'\\''"), name(sig));
+}
+
+///
+/// Builtin trap: runs commands when the shell gets a signal or exits
+///
+/// Inputs:
+///   Shell state holding the traps
+///   Arguments after `trap`: a command and the signals to run it for,
+///   EXIT (or 0) meaning when the shell exits, ERR after a command fails
+///   where `set -e` would exit, and DEBUG before each simple command. An
+///   empty command ignores
+///   the signals and `-` puts back their usual handling, as does giving
+///   only signals. With no arguments, or `-p` and the signals to show, it
+///   prints each trap as the command that would set it
+///
+/// Output:
+///   Exit status, 1 if any signal was not valid
+///
+pub fn builtin_trap(state: &mut State, args: &[String]) -> i32 {
+    let mut args = args;
+    if args.first().is_some_and(|arg| arg == "--") {
+        args = &args[1..];
+    }
+
+    if args.first().is_some_and(|arg| arg == "-p") || args.is_empty() {
+        let wanted: Vec<Option<i32>> = args.iter().skip(1).map(|spec| signal(spec)).collect();
+        for (&sig, command) in &state.traps.commands {
+            if wanted.is_empty() || wanted.contains(&Some(sig)) {
+                show(sig, command);
+            }
+        }
+        return 0;
+    }
+
+    // A lone signal, like a leading `-`, resets rather than being run
+    let (action, specs) = if args.len() == 1 {
+        (None, args)
+    } else if args[0] == "-" {
+        (None, &args[1..])
+    } else {
+        (Some(args[0].as_str()), &args[1..])
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let Some(sig) = signal(spec) else {
+            status = error::builtin("trap", format!("{spec}: invalid signal specification"));
+            continue;
+        };
+
+        match action {
+            Some(command) => {
+                if sig > EXIT {
+                    if command.is_empty() {
+                        signals::ignore(sig);
+                    } else {
+                        signals::catch(sig);
+                    }
+                }
+                state.traps.commands.insert(sig, command.to_string());
+            }
+            None => {
+                if sig > EXIT {
+                    signals::restore(sig);
+                }
+                state.traps.commands.remove(&sig);
+            }
+        }
+    }
+    status
+}
+
+///
+/// Runs the trap for a signal, leaving `$?` as it was
+///
+/// Output:
+///   Break with the exit status if the trap exited the shell
+///
+pub fn run(state: &mut State, sig: i32) -> ControlFlow<i32> {
+    let Some(command) = state.traps.commands.get(&sig).filter(|command| !command.is_empty()) else {
+        return ControlFlow::Continue(());
+    };
+
+    let status = state.last_status;
+    shell_run(state, command.clone())?;
+    state.last_status = status;
+    ControlFlow::Continue(())
+}
+
+///
+/// Runs the ERR or DEBUG trap, unless one is already running
+///
+fn run_hook(state: &mut State, condition: i32) -> ControlFlow<i32> {
+    if state.traps.running || !state.traps.commands.contains_key(&condition) {
+        return ControlFlow::Continue(());
+    }
+    state.traps.running = true;
+    let flow = run(state, condition);
+    state.traps.running = false;
+    flow
+}
+
+///
+/// Runs the ERR trap, for a command that failed where `set -e` would
+/// exit the shell
+///
+/// Output:
+///   Break with the exit status if the trap exited the shell
+///
+pub fn on_err(state: &mut State) -> ControlFlow<i32> {
+    run_hook(state, ERR)
+}
+
+///
+/// Runs the DEBUG trap, before a simple command
+///
+/// Output:
+///   Break with the exit status if the trap exited the shell
+///
+pub fn on_debug(state: &mut State) -> ControlFlow<i32> {
+    run_hook(state, DEBUG)
+}
+
+///
+/// Runs the traps for signals that arrived since the last call
+///
+/// Output:
+///   Break with the exit status if a trap exited the shell
+///
+pub fn run_pending(state: &mut State) -> ControlFlow<i32> {
+    for sig in signals::take_trapped() {
+        run(state, sig)?;
+    }
+    ControlFlow::Continue(())
+}
+
+///
+/// Runs the EXIT trap, once, as the shell is about to exit
+///
+/// Inputs:
+///   Shell state holding the traps
+///   Status the shell is exiting with, which the trap sees as `$?`
+///
+/// Output:
+///   Status to exit with, changed only if the trap ran `exit`
+///
+pub fn on_exit(state: &mut State, code: i32) -> i32 {
+    let Some(command) = state.traps.commands.remove(&EXIT) else {
+        return code;
+    };
+
+    state.last_status = code;
+    match shell_run(state, command) {
+        ControlFlow::Break(code) => code,
+        ControlFlow::Continue(()) => code,
+    }
+}
+
+///
+/// Drops the traps that run commands, for a forked child that runs shell
+/// code; the signals they catch go back to their usual handling, while
+/// ignored ones stay ignored
+///
+pub fn clear_for_subshell(state: &mut State) {
+    state.traps.commands.retain(|&sig, command| {
+        if sig > EXIT && !command.is_empty() {
+            signals::restore(sig);
+        }
+        command.is_empty()
+    });
+    signals::take_trapped();
+}
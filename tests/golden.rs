@@ -0,0 +1,163 @@
+//!
+//! Golden tests: each script in `tests/golden` is run by the shell binary
+//! and what it writes, with its exit status, is compared to the files
+//! next to it
+//!
+//! `NAME.sh` is the script, `NAME.out` its expected stdout, and `NAME.err`
+//! and `NAME.status` its stderr and exit status if they are not empty and
+//! 0. Running with `UPDATE_GOLDEN=1` writes what the shell gave instead
+//!
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+///
+/// What a script did when run
+///
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    status: i32,
+}
+
+///
+/// Runs a script in an empty directory of its own, with a fixed
+/// environment so the output does not depend on who runs it
+///
+/// Input:
+///   Path of the script
+///
+/// Output:
+///   Its output and exit status
+///
+fn run_script(script: &Path) -> Outcome {
+    let name = script.file_stem().unwrap().to_string_lossy();
+    let dir = env::temp_dir().join(format!("rust-shell-golden-{}-{name}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(dir.join("home")).unwrap();
+    fs::create_dir_all(dir.join("work")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .arg(script)
+        .current_dir(dir.join("work"))
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin")
+        .env("HOME", dir.join("home"))
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .output()
+        .expect("the shell should start");
+    fs::remove_dir_all(&dir).ok();
+
+    Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        status: output.status.code().unwrap_or(-1),
+    }
+}
+
+///
+/// Reads the expected outcome of a script, missing files being empty
+/// output and status 0
+///
+fn expected(script: &Path) -> Outcome {
+    let read = |ext: &str| fs::read_to_string(script.with_extension(ext)).unwrap_or_default();
+    Outcome {
+        stdout: read("out"),
+        stderr: read("err"),
+        status: read("status").trim().parse().unwrap_or(0),
+    }
+}
+
+///
+/// Writes an outcome as the expected one, leaving out the files that
+/// would hold nothing
+///
+fn update(script: &Path, outcome: &Outcome) {
+    let write = |ext: &str, text: &str, empty: bool| {
+        let path = script.with_extension(ext);
+        if empty {
+            fs::remove_file(path).ok();
+        } else {
+            fs::write(path, text).unwrap();
+        }
+    };
+    write("out", &outcome.stdout, false);
+    write("err", &outcome.stderr, outcome.stderr.is_empty());
+    write("status", &format!("{}\n", outcome.status), outcome.status == 0);
+}
+
+fn check(name: &str) {
+    let script: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", &format!("{name}.sh")].iter().collect();
+    let outcome = run_script(&script);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        update(&script, &outcome);
+        return;
+    }
+    assert_eq!(outcome, expected(&script), "{} gave different output", script.display());
+}
+
+macro_rules! golden {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                check(stringify!($name));
+            }
+        )*
+    };
+}
+
+golden!(builtins, control, errors, expansion, globbing, pipelines, redirections);
+
+///
+/// Commands piped in rather than given as a script are read without a
+/// prompt, so only what they print reaches stdout
+///
+#[test]
+fn piped_commands() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .arg("--norc")
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin")
+        .env("PS1", "rsh> ")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the shell should start");
+    child.stdin.take().unwrap().write_all(b"echo one\nif true; then\necho two\nfi\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert!(output.status.success());
+}
+
+#[test]
+fn commands_read_the_rest_of_stdin() {
+    let run = |stdin: Stdio| {
+        let output = Command::new(env!("CARGO_BIN_EXE_shell"))
+            .arg("--norc")
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .stdin(stdin)
+            .output()
+            .expect("the shell should start");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let input = "read x; echo got $x\nhello\necho done\n";
+
+    let mut child = Command::new("printf").arg(input).stdout(Stdio::piped()).spawn().unwrap();
+    assert_eq!(run(child.stdout.take().unwrap().into()), "got hello\ndone\n");
+    child.wait().unwrap();
+
+    let path = env::temp_dir().join(format!("rsh-stdin-{}", std::process::id()));
+    fs::write(&path, input).unwrap();
+    assert_eq!(run(fs::File::open(&path).unwrap().into()), "got hello\ndone\n");
+    fs::remove_file(&path).ok();
+}
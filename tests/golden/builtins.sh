@@ -0,0 +1,183 @@
+echo -n "no newline"; echo
+echo -e 'tab\there'
+echo -E 'kept\tliteral'
+
+cd dir 2>/dev/null || mkdir dir && cd dir
+basename "$(pwd)"
+cd ..
+[ "$PWD" = "$(pwd)" ] && echo "PWD follows cd"
+# A quoted name with a space is one directory
+mkdir "with space"
+cd "with space" && basename "$PWD"
+cd ..
+# CDPATH is searched even when it is not exported
+mkdir -p base/inner
+CDPATH=$PWD/base
+cd inner > /dev/null && echo "CDPATH found $(basename "$PWD")"
+cd - > /dev/null
+unset CDPATH
+
+printf 'alpha beta gamma\n' > words
+read first rest < words
+echo "first=$first rest=$rest"
+IFS=: read a b <<< "x:y"
+echo "a=$a b=$b"
+late=before
+{ printf partial; sleep 0.3; } | { read -t 0.05 late; echo "timed out: $? $late"; }
+read -t 1 prompt <<< "in time"
+echo "read: $? $prompt"
+printf ' a\nb c' | { read -N 4 exact; echo "exact: [$exact]"; }
+
+alias greet='echo hello'
+greet there
+unalias greet
+type greet 2>&1
+
+let n=2+3 m=n*2
+echo "n=$n m=$m"
+echo $(( (n + 1) * m ))
+
+export EXPORTED=yes
+env | grep '^EXPORTED='
+export -- ALSO=too
+unset -- EXPORTED
+env | grep '^EXPORTED=\|^ALSO='
+gone() { echo still here; }
+unset -v gone
+gone
+unset gone
+type gone
+
+type cd
+type if
+f() { echo "f got $# args: $*"; return 3; }
+f one two
+echo "f returned $?"
+
+kill -l 9
+kill -l TERM
+trap 'echo trapped exit' EXIT
+trap
+trap 'echo "err $?"' ERR
+false
+false || true
+if false; then :; fi
+trap - ERR
+trap 'echo debug' DEBUG
+true | true
+trap - DEBUG
+TIMEFORMAT='took %0R %0lS %%'
+time true
+TIMEFORMAT=
+time true
+umask 027
+umask
+umask -S
+true; echo "true: $?"
+false; echo "false: $?"
+
+printf 'sourced=yes\nmkdir -p sub; cd sub\necho "sourced with $# args: $*"\nreturn 5\necho unreachable\n' > lib.sh
+. ./lib.sh first second
+echo "source returned $? sourced=$sourced in ${PWD##*/}"
+cd ..
+
+touch plain; mkdir -p dir
+if [ -f plain ]; then echo "plain is a file"; fi
+[ -d dir -a ! -f dir ] && echo "dir is a directory"
+[ -e missing ] || echo "missing does not exist"
+test -z "" && test -n x && echo "empty and non-empty strings"
+[ abc = abc ] && [ abc != abd ] && echo "string comparison"
+[ 3 -lt 10 ] && [ 10 -ge 10 ] && [ 007 -eq 7 ] && echo "numeric comparison"
+[ \( 1 -eq 2 \) -o -n x ] && echo "grouping"
+test 1 -eq one; echo "bad integer: $?"
+[ 1 = 1; echo "missing bracket: $?"
+
+chpwd() { echo "chpwd: ${PWD##*/}"; }
+announce() { echo "announce: ${PWD##*/}, status $?"; }
+hook chpwd announce
+hook
+false
+cd dir
+echo "status after cd: $?"
+hook -d chpwd announce
+cd ..
+hook nosuch announce; echo "unknown event: $?"
+
+# history -s adds entries that history lists and searches
+history -s echo one
+history -s ls -l
+history -s echo two
+history
+history search echo
+history 1
+history search
+echo "search without a pattern: $?"
+# HISTSIZE need not be exported to limit it
+HISTSIZE=2
+history -s echo three
+history
+unset HISTSIZE
+
+# shopt -s xpg_echo makes echo interpret escapes without -e
+echo 'a\tb'
+shopt -s xpg_echo
+echo 'a\tb'
+echo -E 'a\tb'
+shopt xpg_echo
+shopt -q xpg_echo && echo "xpg_echo is on"
+shopt -u xpg_echo
+shopt -q xpg_echo || echo "xpg_echo is off"
+echo 'a\tb'
+shopt -p
+shopt nosuch
+echo "unknown: $?"
+
+# declare -i evaluates what is assigned, declare -r stops any change
+declare -i n
+n=2+3
+echo "n=$n"
+n+=4
+echo "n=$n"
+declare -r c=5
+c=6
+echo "status $? c=$c"
+let c=7
+echo "let $?"
+( echo $((c = 8)) )
+echo "arith $?"
+unset c
+echo "unset $?"
+c=9 true
+echo "prefix $?"
+export c=1
+echo "export $?"
+declare -p n c
+declare -r
+typeset -x X=1
+declare -p X
+unset X
+declare +i n
+n=1+1
+echo "n=$n"
+read c <<< hi
+echo "read $? $c"
+declare -z
+echo "bad $?"
+
+# Builtins report errors through the shell, naming themselves
+cd /nonexistent/dir
+echo "cd failed: $?"
+cd /nonexistent /usr
+echo "cd with two operands: $? $(basename "$PWD")"
+# Among the paths one glob matches, the first directory is taken
+mkdir -p globbed/a2
+touch globbed/a1
+cd globbed/a* && basename "$PWD"
+cd ../..
+# cd keeps the symlink it went through in $PWD unless given -P
+mkdir -p real/sub
+ln -s real/sub linked
+cd linked && basename "$PWD" && basename "$(pwd -P)"
+cd .. && [ -d real ] && echo "back through the link"
+cd -P linked && basename "$PWD"
+cd ../..
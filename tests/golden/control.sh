@@ -0,0 +1,47 @@
+if false; then echo no; elif true; then echo elif; else echo else; fi
+
+i=0
+while [ $i -lt 3 ]; do echo "while $i"; i=$((i + 1)); done
+until [ $i -eq 0 ]; do i=$((i - 1)); done
+echo "until left $i"
+
+for word in a b c; do echo "for $word"; done
+set -- p1 p2
+for p; do echo "positional $p"; done
+
+for file in report.txt image.png notes; do
+    case $file in
+        *.txt) echo "$file: text";;
+        *.png|*.jpg) echo "$file: image";;
+        *) echo "$file: other";;
+    esac
+done
+
+# A subshell's changes stay inside it; a group's do not
+x=1
+(x=2; echo "subshell $x")
+echo "after subshell $x"
+{ x=3; echo "group $x"; }
+echo "after group $x"
+(exit 7)
+echo "subshell status $?"
+
+count() {
+    local_total=0
+    for n in "$@"; do local_total=$((local_total + n)); done
+    echo "$local_total"
+}
+echo "sum $(count 1 2 3 4)"
+
+# break and continue, for the innermost loop or n loops out
+while true; do echo once; break; done
+for i in 1 2 3; do [ $i = 2 ] && continue; echo "i=$i"; done
+for i in a b; do for j in 1 2; do [ $j = 2 ] && continue 2; echo "$i$j"; done; done
+for i in a b; do while true; do break 2; done; echo not-shown; done
+echo "after break 2: $?"
+
+set -e
+true
+echo "set -e keeps going while commands succeed"
+false
+echo not-shown
@@ -0,0 +1,31 @@
+# Syntax errors are reported and the script carries on
+echo before
+if true; then
+echo after
+fi )
+echo "status $?"
+( echo $((1/0)); echo not reached )
+echo "arithmetic: $?"
+( n=$((2 ** -1)); echo not reached )
+echo "negative exponent: $?"
+( echo "${missing:?is required}"; echo not reached )
+echo "unset parameter: $?"
+( set -u; echo "${unset_default:-fine} $1"; echo not reached )
+echo "nounset: $?"
+( set -u; echo "${#never_set}"; echo not reached )
+echo "nounset length: $?"
+# A missing command is only given a suggestion that is close to it
+f() { :; }
+unset -f f
+f
+exprot
+echo "not found: $?"
+# A handler function or command runs in place of the message, quoted
+# words in COMMAND_NOT_FOUND_CMD kept whole
+COMMAND_NOT_FOUND_CMD="printf '[%s] '"
+nosuchcmd one two; echo
+command_not_found_handle() { echo "handled $1 with $# words"; return 5; }
+nosuchcmd one two
+echo "handler: $?"
+unset -f command_not_found_handle; unset COMMAND_NOT_FOUND_CMD
+echo 'unterminated
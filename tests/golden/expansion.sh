@@ -0,0 +1,61 @@
+name=value
+empty=
+echo "$name ${name} ${#name}"
+echo "${unset:-default} ${empty:-empty-default} ${empty-set-but-empty}"
+echo "${name:+alternate} [${unset:+alternate}]"
+echo "${assigned:=now-set} $assigned"
+path=/usr/local/lib/file.tar.gz
+echo "${path##*/} ${path%/*} ${path%%.*} ${path#*.}"
+echo "${path/lib/LIB} ${path//l/L}"
+v=abc
+echo ${v/#b/X} ${v/%b/X} ${v/#a/X}
+v=abcabc
+echo ${v/%c/X} ${v/#abc/X}
+name+=" more"
+fresh+=start
+echo "$name $fresh"
+# Each assignment sees the ones before it, also before a command
+first=1 second=$first; echo "$second"
+x=1 x+=2; echo "$x"
+one=1 two=$one sh -c 'echo "$one $two"'
+
+# Command substitution, nested and in quotes
+echo "today is $(echo Monday)"
+echo "nested $(echo "$(echo inner)")"
+echo `echo backticks`
+echo $( case x in x) echo cx;; esac )
+echo "$(case y in (x) echo no;; y|z) echo yz;; esac) $(echo case in)"
+
+# Unquoted substitution splits into words, quoted does not
+for w in $(echo one   two); do echo "[$w]"; done
+for w in "$(echo one   two)"; do echo "[$w]"; done
+spaced="three   four"
+for w in $spaced; do echo "[$w]"; done
+for w in "$spaced"; do echo "[$w]"; done
+colons=five:six
+IFS=:
+for w in $colons; do echo "[$w]"; done
+# Other than blanks, each IFS character ends a field, empty or not, and
+# "$*" joins with the first of them
+IFS=,
+x=a,,b
+set -- $x
+echo "$# fields"
+IFS=-
+set -- a b c
+echo "$*"
+unset IFS
+set -- "a b" c
+for w in "$@"; do echo "<$w>"; done
+echo "$#"
+
+echo $((7 / 2)) $((7 % 2)) $((2 ** 10)) $((1 < 2 && 3 > 2))
+echo '$name stays' "\$name too" \$name
+
+echo "script: ${0##*/}, $# arguments"
+set -- one "two words" three
+for arg; do echo "arg: $arg"; done
+shift
+echo "after shift: $# left, first is $1"
+shift 2
+echo "after shift 2: $# left"
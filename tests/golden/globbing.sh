@@ -0,0 +1,58 @@
+touch a.txt b.txt c.log .hidden
+mkdir dir
+touch dir/inner.txt
+
+echo *.txt
+echo ?.log
+echo [ab].txt
+# Hidden files are left out unless asked for
+echo *
+echo dir/*
+echo */*.txt
+
+# Patterns matching nothing stay as written, and quotes keep them literal
+echo *.none
+echo "*.txt" '*.txt' \*.txt
+
+# Brace expansion comes first and keeps its order
+echo {c,a,b}.txt
+echo file{1..3}
+echo pre{x,y{1,2}}post
+
+# Tilde expands to HOME at the start of a word only
+HOME=/home/user
+echo ~ ~/docs a~b
+
+# Hidden files match a pattern that starts with a dot
+echo .h*
+
+# `**` is `*` until globstar turns it recursive
+mkdir -p dir/deep
+touch dir/deep/leaf.txt
+echo **/*.txt
+set -o globstar
+echo **/*.txt
+echo dir/**/
+set +o globstar
+
+# Extended patterns
+echo @(a|c).*
+echo !(*.txt)
+echo +([a-c]).txt
+case b.txt in ?(x|y).txt) echo none ;; @(a|b).txt) echo picked b ;; esac
+name=archive.tar.gz
+echo ${name%%.*([a-z.])}
+
+# nullglob drops patterns matching nothing and failglob makes them errors
+set -o nullglob
+echo before *.none after
+set +o nullglob
+set -o failglob
+echo *.none
+echo "failglob: $?"
+set +o failglob
+
+# set -f turns matching off altogether
+set -f
+echo *.txt
+set +f
@@ -0,0 +1,48 @@
+# Stages pass their output along
+echo hello world | tr a-z A-Z
+printf 'c\nb\na\n' | sort | head -n 2
+echo one two three | wc -w | tr -d ' '
+
+# The status is that of the last stage
+false | true
+echo "false | true: $?"
+true | false
+echo "true | false: $?"
+
+# Unless pipefail asks for the last failing stage
+set -o pipefail
+false | true
+echo "pipefail: $?"
+set +o pipefail
+
+# |& sends stderr down the pipe too
+{ echo out; echo err >&2; } |& sort
+
+# Builtins and compound commands take part like any stage
+for i in 1 2 3; do echo "line $i"; done | tail -n 1
+echo piped | read word; echo "read in a pipeline keeps the shell's word: [$word]"
+x=outer; echo x | { read x; echo "inside: $x"; }; echo "outside: $x"
+
+# && and || chain on status
+true && echo and-ran
+false && echo not-shown
+false || echo or-ran
+true || echo not-shown
+
+# Background jobs fan out and wait collects them
+(sleep 0.2; echo slow; exit 3) &
+slow=$!
+(echo fast; exit 4) &
+wait %2
+echo "fast exited $?"
+wait $slow
+echo "slow exited $?"
+(exit 5) &
+wait
+echo "wait for all: $?"
+
+# Readers see end of file once their writer exits, even when the
+# stages between are run by the shell itself
+yes | head -2
+printf 'a\nb\n' | while read line; do echo "[$line]"; done | { cat; } | cat
+nosuchcommand 2>/dev/null | wc -l
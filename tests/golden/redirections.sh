@@ -0,0 +1,56 @@
+echo first > file
+echo second >> file
+cat < file
+
+# Order matters: the first sends both to the file, the second only stdout
+{ echo out; echo err >&2; } > both 2>&1
+sort both
+{ echo out; echo err >&2; } 2>&1 > only 2>/dev/null
+cat only
+
+# Here-documents expand unless the word is quoted, and <<- drops tabs
+name=world
+cat <<EOT
+hello $name
+EOT
+cat <<'EOT'
+hello $name
+EOT
+cat <<-EOT
+	indented $name
+	EOT
+tr a-z A-Z <<< "here string"
+
+# exec keeps redirections for the rest of the shell
+exec 3> fd3
+echo to three >&3
+exec 3>&-
+cat fd3
+
+# A group or loop is redirected as a whole
+{ echo a; echo b; } > group
+cat group
+for i in 1 2; do echo "loop $i"; done > loop
+cat loop
+
+# Process substitution
+cat <(echo from substitution)
+
+# A redirection target's tilde is the home directory
+echo in home > ~/tmpfile
+cat "$HOME/tmpfile"
+[ -e tmpfile ] || echo "not in the working directory"
+
+# noclobber keeps > from writing over a file, but >| and new files are fine
+set -C
+echo again > file
+cat file
+echo forced >| file
+echo fresh > new-file
+echo discarded > /dev/null
+cat file new-file
+set +C
+
+# Missing input is reported and the command does not run
+cat < missing && echo not-shown
+echo "status $?"
@@ -0,0 +1,234 @@
+//!
+//! Interactive tests: the shell binary runs on a pseudo-terminal, as it
+//! does under a terminal emulator, so job control and the line editor are
+//! switched on, and each test types lines at it and checks what it shows
+//!
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Prompt the shell is given, to tell when it is waiting for a line
+const PROMPT: &str = "rsh> ";
+
+/// Longest to wait for the shell to answer a line
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+///
+/// Shell running on the slave side of a pseudo-terminal, driven from the
+/// master side
+///
+struct Terminal {
+    master: File,
+    child: Child,
+    /// Directory holding its home, removed when it is dropped
+    dir: PathBuf,
+}
+
+impl Terminal {
+    ///
+    /// Starts the shell in a new session whose controlling terminal is the
+    /// pseudo-terminal, with a fixed environment and prompt, and waits
+    /// for its first prompt
+    ///
+    fn start(name: &str) -> Terminal {
+        let (mut master, mut slave) = (0, 0);
+        // SAFETY: openpty only writes the two fds through the pointers we
+        // own, and the name, termios and window size may be null
+        let opened = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null())
+        };
+        assert_eq!(opened, 0, "openpty: {}", io::Error::last_os_error());
+        // SAFETY: openpty has just opened both fds and nothing else owns them
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        let dir = env::temp_dir().join(format!("rust-shell-interactive-{}-{name}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_shell"));
+        command
+            .arg("--norc")
+            .current_dir(&dir)
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .env("HOME", &dir)
+            .env("LC_ALL", "C")
+            .env("TERM", "dumb")
+            .env("PS1", PROMPT)
+            .stdin(Stdio::from(slave.try_clone().unwrap()))
+            .stdout(Stdio::from(slave.try_clone().unwrap()))
+            .stderr(Stdio::from(slave));
+        // SAFETY: setsid and ioctl are async-signal-safe, so may run
+        // between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn().expect("the shell should start");
+
+        let mut terminal = Terminal { master, child, dir };
+        terminal.read_until(|text| plain(text).ends_with(PROMPT));
+        terminal
+    }
+
+    ///
+    /// Reads what the shell writes until it satisfies done, failing the
+    /// test if that takes too long
+    ///
+    fn read_until(&mut self, done: impl Fn(&str) -> bool) -> String {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut text = String::new();
+        while !done(&text) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            assert!(!left.is_zero(), "the shell stopped answering after {text:?}");
+
+            let mut poll = libc::pollfd { fd: self.master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            // SAFETY: poll only touches the one pollfd we own
+            if unsafe { libc::poll(&mut poll, 1, left.as_millis() as i32) } <= 0 {
+                continue;
+            }
+            let mut buf = [0; 4096];
+            match self.master.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => text.push_str(&String::from_utf8_lossy(&buf[..n])),
+            }
+        }
+        text
+    }
+
+    ///
+    /// Types a line and waits for the prompt after it
+    ///
+    /// Output:
+    ///   What the line printed, its lines ending in `\n`, without the
+    ///   line itself as the editor drew it or colours
+    ///
+    fn run(&mut self, line: &str) -> String {
+        self.master.write_all(format!("{line}\r").as_bytes()).unwrap();
+        let text = plain(&self.read_until(|text| {
+            let text = plain(text);
+            text.contains("\r\n") && text.ends_with(PROMPT)
+        }));
+        let (_, output) = text.split_once("\r\n").unwrap();
+        output
+            .strip_suffix(PROMPT)
+            .unwrap_or(output)
+            .trim_end_matches('\r')
+            .replace("\r\n", "\n")
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+///
+/// Text without the escape sequences that colour it or move the cursor
+///
+fn plain(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // A CSI sequence ends at its first letter
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[test]
+fn jobs_and_wait() {
+    let mut terminal = Terminal::start("jobs");
+    let started = terminal.run("sleep 1 &");
+    assert!(started.starts_with("[1] "), "starting a job gave {started:?}");
+
+    assert_eq!(terminal.run("jobs"), "[1]+  Running                 sleep 1 &\n");
+    assert_eq!(terminal.run("wait"), "");
+    assert_eq!(terminal.run("echo \"wait gave $?\""), "wait gave 0\n");
+    // The job waited for is gone, not reported later as done
+    assert_eq!(terminal.run("jobs"), "");
+}
+
+#[test]
+fn finished_job_is_reported() {
+    let mut terminal = Terminal::start("report");
+    terminal.run("sh -c 'sleep 0.5; exit 3' &");
+    // Reported before the prompt after it finished, and only that once
+    assert_eq!(terminal.run("sleep 1.5"), "[1]+  Exit 3                  sh -c 'sleep 0.5; exit 3'\n");
+    assert_eq!(terminal.run("jobs"), "");
+}
+
+#[test]
+fn killed_job_is_named_by_signal() {
+    let mut terminal = Terminal::start("killed");
+    terminal.run("sleep 5 &");
+    assert_eq!(terminal.run("kill %1; sleep 0.5"), "[1]+  Terminated              sleep 5\n");
+
+    // An exit status above 128 is still an exit, not a signal
+    terminal.run("sh -c 'sleep 0.5; exit 143' &");
+    assert_eq!(terminal.run("sleep 1.5"), "[1]+  Exit 143                sh -c 'sleep 0.5; exit 143'\n");
+}
+
+#[test]
+fn long_history_goes_through_the_pager() {
+    let mut terminal = Terminal::start("pager");
+    terminal.run("for i in $(seq 30); do history -s \"entry $i\"; done");
+    terminal.run("PAGER=\"sed 's/^/paged: /'\"");
+
+    let paged = terminal.run("history");
+    assert!(paged.lines().count() > 30, "history gave {paged:?}");
+    assert!(paged.lines().all(|line| line.starts_with("paged: ")), "history gave {paged:?}");
+
+    // Nor when it would fit, or the option turns it off
+    assert_eq!(terminal.run("history 1"), "   34  history 1\n");
+    terminal.run("set -o nopager");
+    let plain = terminal.run("history");
+    assert!(!plain.lines().any(|line| line.starts_with("paged: ")), "history gave {plain:?}");
+}
+
+#[test]
+fn job_specs_complete() {
+    let mut terminal = Terminal::start("complete-jobs");
+    terminal.run("sleep 5 &");
+    // The only job completes in full
+    assert_eq!(terminal.run("kill %\t; sleep 0.5"), "[1]+  Terminated              sleep 5\n");
+
+    // With no jobs left the word completes as a path
+    terminal.run("touch notes");
+    let output = terminal.run("kill no\t");
+    assert!(output.contains("notes"), "kill gave {output:?}");
+}
+
+#[test]
+fn kill_word_stops_at_slashes() {
+    let mut terminal = Terminal::start("kill-word");
+    assert_eq!(terminal.run("echo /usr/local/bin\x17"), "/usr/local/\n");
+    assert_eq!(terminal.run("echo /usr/local-bin\x1b\x7f"), "/usr/\n");
+
+    // WORDCHARS decides what else stays in the word
+    terminal.run("WORDCHARS=");
+    assert_eq!(terminal.run("echo /usr/local-bin\x17"), "/usr/local-\n");
+}